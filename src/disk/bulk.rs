@@ -0,0 +1,190 @@
+use super::{
+    codec::Codec,
+    directory::DirStore,
+    error::Error,
+    node::{Internal, Leaf, Link, Node},
+    store::NodeStore,
+    BPTree,
+};
+use serde::Deserialize;
+use std::path::Path;
+use uuid::Uuid;
+
+impl<K, V> BPTree<K, V, DirStore> {
+    // Builds a tree out of entries sorted by strictly increasing key, packing
+    // the nodes as full as they go. Nothing is written until the tree is
+    // persisted, so it can still be encrypted or compressed first.
+    pub fn bulk_load(
+        path: impl AsRef<Path>,
+        order: usize,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        Self::bulk_load_with_fill(path, order, 1.0, entries)
+    }
+
+    // Like `bulk_load`, but fills the nodes to about `fill` of their capacity,
+    // leaving room for later inserts to land without splitting.
+    pub fn bulk_load_with_fill(
+        path: impl AsRef<Path>,
+        order: usize,
+        fill: f64,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        Self::bulk_load_with_store(DirStore::new(path), order, fill, entries)
+    }
+}
+
+impl<K, V, S: NodeStore, C: Codec> BPTree<K, V, S, C> {
+    pub fn bulk_load_with_store(
+        store: S,
+        order: usize,
+        fill: f64,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+        C: Default,
+    {
+        let mut tree = Self::with_store(store, order);
+        tree.build_sorted(fill, entries)?;
+        Ok(tree)
+    }
+
+    // Builds the tree bottom-up, one level at a time, into an empty tree.
+    fn build_sorted(
+        &mut self,
+        fill: f64,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        assert!(
+            fill > 0.0 && fill <= 1.0,
+            "fill factor must be in (0, 1] in BPTree"
+        );
+
+        // Check everything before building anything, so bad input can't leave
+        // a half-built tree behind.
+        let mut keys = Vec::new();
+        let mut values = Vec::new();
+        for (index, (key, value)) in entries.into_iter().enumerate() {
+            if keys.last().is_some_and(|last| *last >= key) {
+                return Err(Error::Unsorted { index });
+            }
+            keys.push(key);
+            values.push(value);
+        }
+
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        self.len = keys.len();
+        self.len_is_dirty = true;
+
+        // Each node of a level, along with the smallest key under it.
+        let mut level = Vec::new();
+
+        let mut keys = keys.into_iter();
+        let mut values = values.into_iter();
+        let mut prev_leaf: Option<Link<K, V>> = None;
+
+        let target = fill_target(self.order, fill, self.order.div_ceil(2));
+        for size in node_sizes(self.len, target, self.order.div_ceil(2)) {
+            let leaf_keys = keys.by_ref().take(size).collect::<Vec<_>>();
+            let min_key = leaf_keys[0].clone();
+
+            let leaf = self.pager.alloc(Node::Leaf(Leaf {
+                uuid: Uuid::new_v4(),
+                keys: leaf_keys,
+                values: values.by_ref().take(size).collect(),
+                parent: None,
+                next_leaf: None,
+                prev_leaf,
+                is_dirty: true,
+            }));
+
+            if let Some(prev_leaf) = prev_leaf {
+                unsafe {
+                    if let Node::Leaf(prev_leaf) = (*prev_leaf.as_ptr()).access_mut(&self.pager)? {
+                        prev_leaf.next_leaf = Some(leaf);
+                    }
+                }
+            }
+
+            level.push((min_key, leaf));
+            prev_leaf = Some(leaf);
+        }
+
+        // Internal nodes hold between `order / 2` and `order` keys, and one
+        // more child than keys.
+        let target = fill_target(self.order + 1, fill, self.order / 2 + 1);
+        while level.len() > 1 {
+            let mut children = level.into_iter();
+            let mut parents = Vec::new();
+
+            for size in node_sizes(children.len(), target, self.order / 2 + 1) {
+                let (min_key, first_child) = children.next().unwrap();
+                let (keys, rest): (Vec<_>, Vec<_>) = children.by_ref().take(size - 1).unzip();
+
+                let mut node_children = vec![first_child];
+                node_children.extend(rest);
+
+                let parent = self.pager.alloc(Node::Internal(Internal {
+                    uuid: Uuid::new_v4(),
+                    keys,
+                    children: node_children.clone(),
+                    parent: None,
+                    is_dirty: true,
+                }));
+
+                for child in node_children {
+                    unsafe {
+                        match (*child.as_ptr()).access_mut(&self.pager)? {
+                            Node::Internal(child) => child.parent = Some(parent),
+                            Node::Leaf(child) => child.parent = Some(parent),
+                        }
+                    }
+                }
+
+                parents.push((min_key, parent));
+            }
+
+            level = parents;
+        }
+
+        self.root = level.pop().map(|(_, root)| root);
+        self.root_is_dirty = true;
+
+        Ok(())
+    }
+}
+
+// The number of entries or children to put in each node for a fill factor,
+// given the most and fewest a node can hold.
+fn fill_target(max: usize, fill: f64, min: usize) -> usize {
+    ((max as f64 * fill).round() as usize).clamp(min.max(1), max)
+}
+
+// Splits `len` entries or children into nodes of about `target` each, as
+// evenly as possible. The nodes must hold at least `min` each unless there's
+// just one, which becomes the root, so fewer nodes are used if need be.
+fn node_sizes(len: usize, target: usize, min: usize) -> impl Iterator<Item = usize> {
+    let mut nodes = len.div_ceil(target);
+    while nodes > 1 && len / nodes < min {
+        nodes -= 1;
+    }
+
+    (0..nodes).map(move |index| len / nodes + usize::from(index < len % nodes))
+}
@@ -0,0 +1,21 @@
+// Node and metadata files start with a CRC32 of the rest of their contents,
+// so damaged data can be told apart from data of the wrong type.
+const CHECKSUM_SIZE: usize = 4;
+
+pub(crate) fn seal(data: &[u8]) -> Vec<u8> {
+    let mut sealed = Vec::with_capacity(CHECKSUM_SIZE + data.len());
+    sealed.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+    sealed.extend_from_slice(data);
+    sealed
+}
+
+// Returns the contents of sealed data, or `None` if they don't match their
+// checksum.
+pub(crate) fn unseal(sealed: &[u8]) -> Option<&[u8]> {
+    if sealed.len() < CHECKSUM_SIZE {
+        return None;
+    }
+
+    let (checksum, data) = sealed.split_at(CHECKSUM_SIZE);
+    (crc32fast::hash(data).to_le_bytes() == checksum).then_some(data)
+}
@@ -0,0 +1,55 @@
+use super::error::Error;
+use ron::ser::PrettyConfig;
+use serde::{de::DeserializeOwned, Serialize};
+
+// How a `BPTree` turns its nodes and metadata into bytes for its store.
+//
+// The codec's name is recorded with the tree, so a tree can only be loaded
+// with the codec it was written with.
+pub trait Codec {
+    const NAME: &'static str;
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error>;
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, Error>;
+}
+
+// Compact and fast, but opaque. The default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bincode;
+
+impl Codec for Bincode {
+    const NAME: &'static str = "bincode";
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, Error> {
+        Ok(bincode::deserialize(data)?)
+    }
+}
+
+// Self-describing and readable in any text editor, at the cost of size and
+// speed. Handy for debugging.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ron {
+    pub pretty: bool,
+}
+
+impl Codec for Ron {
+    const NAME: &'static str = "ron";
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        let data = if self.pretty {
+            ron::ser::to_string_pretty(value, PrettyConfig::default())
+        } else {
+            ron::to_string(value)
+        };
+        Ok(data.map_err(|err| Error::Serde(err.into()))?.into_bytes())
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, Error> {
+        ron::de::from_bytes(data).map_err(|err| Error::Serde(err.into()))
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+// How node files are compressed. Each node file starts with a flag saying how
+// it was compressed, so a tree's files can be a mix of the two: nodes that
+// don't shrink are stored as is, and changing a tree's compression only
+// affects the nodes written after the change.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+}
+
+const STORED: u8 = 0;
+const LZ4: u8 = 1;
+
+// Compresses an encoded node and prepends the flag.
+pub(crate) fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+    if compression == Compression::Lz4 {
+        let compressed = lz4_flex::compress_prepend_size(data);
+        if compressed.len() < data.len() {
+            return flagged(LZ4, &compressed);
+        }
+    }
+
+    flagged(STORED, data)
+}
+
+// Undoes `compress`, or returns `None` if the data is damaged or was
+// compressed some unknown way.
+pub(crate) fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    let (flag, data) = data.split_first()?;
+
+    match *flag {
+        STORED => Some(data.to_vec()),
+        LZ4 => lz4_flex::decompress_size_prepended(data).ok(),
+        _ => None,
+    }
+}
+
+fn flagged(flag: u8, data: &[u8]) -> Vec<u8> {
+    let mut flagged = Vec::with_capacity(1 + data.len());
+    flagged.push(flag);
+    flagged.extend_from_slice(data);
+    flagged
+}
@@ -0,0 +1,206 @@
+use super::{error::Error, store::NodeStore};
+use path_macro::path;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+const JOURNAL: &str = "journal";
+
+// Files written but not yet committed carry this extension.
+const STAGED_EXTENSION: &str = "staged";
+
+// A directory with one file per node and per metadata entry.
+//
+// Changes are staged until `sync` commits them with a redo journal. Nodes are
+// written to staged files beside the committed ones, and `sync` writes a
+// journal of every staged change and renames it into place, which is the
+// commit point. Only then are the staged files moved over the committed ones.
+// Opening a directory finishes any commit a crash interrupted and throws away
+// staged files that were never committed.
+pub struct DirStore {
+    path: PathBuf,
+    staged: Journal,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct Journal {
+    writes: HashSet<Uuid>,
+    deletes: HashSet<Uuid>,
+    metadata: BTreeMap<String, Vec<u8>>,
+}
+
+impl Journal {
+    fn is_empty(&self) -> bool {
+        self.writes.is_empty() && self.deletes.is_empty() && self.metadata.is_empty()
+    }
+}
+
+impl DirStore {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().into(),
+            staged: Journal::default(),
+        }
+    }
+
+    // Opens a directory that may have been left mid-commit by a crash.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let this = Self::new(path);
+
+        match fs::read(this.journal_path()) {
+            Ok(journal) => {
+                this.apply(&bincode::deserialize(&journal)?)?;
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let entries = match fs::read_dir(&this.path) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(this),
+            Err(err) => return Err(err.into()),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == STAGED_EXTENSION) {
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(this)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        path![self.path / JOURNAL]
+    }
+
+    fn staged_path(&self, name: &str) -> PathBuf {
+        path![self.path / format!("{name}.{STAGED_EXTENSION}")]
+    }
+
+    fn write_staged(&self, name: &str, data: &[u8]) -> Result<(), Error> {
+        fs::create_dir_all(&self.path)?;
+        let mut file = File::create(self.staged_path(name))?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    fn sync_dir(&self) -> Result<(), Error> {
+        // Directories can only be opened, and so synced, like this on unix.
+        #[cfg(unix)]
+        File::open(&self.path)?.sync_all()?;
+        Ok(())
+    }
+
+    // Moves a committed journal's changes into place. Every step can be
+    // repeated, so this is safe to rerun after a crash part way through.
+    fn apply(&self, journal: &Journal) -> Result<(), Error> {
+        for id in &journal.writes {
+            let name = id.to_string();
+            match fs::rename(self.staged_path(&name), path![self.path / name]) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+
+        for id in &journal.deletes {
+            match fs::remove_file(path![self.path / id.to_string()]) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+
+        for (name, data) in &journal.metadata {
+            self.write_staged(name, data)?;
+            fs::rename(self.staged_path(name), path![self.path / name])?;
+        }
+
+        self.sync_dir()?;
+        fs::remove_file(self.journal_path())?;
+        self.sync_dir()
+    }
+}
+
+impl NodeStore for DirStore {
+    fn read(&self, id: Uuid) -> Result<Vec<u8>, Error> {
+        if self.staged.deletes.contains(&id) {
+            return Err(Error::IO(ErrorKind::NotFound.into()));
+        }
+
+        if self.staged.writes.contains(&id) {
+            return Ok(fs::read(self.staged_path(&id.to_string()))?);
+        }
+
+        Ok(fs::read(path![self.path / id.to_string()])?)
+    }
+
+    fn write(&mut self, id: Uuid, data: &[u8]) -> Result<(), Error> {
+        self.write_staged(&id.to_string(), data)?;
+        self.staged.deletes.remove(&id);
+        self.staged.writes.insert(id);
+        Ok(())
+    }
+
+    fn delete(&mut self, id: Uuid) -> Result<(), Error> {
+        if self.staged.writes.remove(&id) {
+            fs::remove_file(self.staged_path(&id.to_string()))?;
+        }
+        self.staged.deletes.insert(id);
+        Ok(())
+    }
+
+    fn discard(&mut self, id: Uuid) -> Result<(), Error> {
+        let name = id.to_string();
+        if self.staged.writes.remove(&id) {
+            fs::remove_file(self.staged_path(&name))?;
+        }
+
+        match fs::remove_file(path![self.path / name]) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn get_metadata(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        if let Some(data) = self.staged.metadata.get(name) {
+            return Ok(Some(data.clone()));
+        }
+
+        match fs::read(path![self.path / name]) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn put_metadata(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        self.staged.metadata.insert(name.into(), data.into());
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        if self.staged.is_empty() {
+            return Ok(());
+        }
+
+        self.write_staged(JOURNAL, &bincode::serialize(&self.staged)?)?;
+        fs::rename(self.staged_path(JOURNAL), self.journal_path())?;
+        self.sync_dir()?;
+
+        self.apply(&self.staged)?;
+        self.staged = Journal::default();
+
+        Ok(())
+    }
+}
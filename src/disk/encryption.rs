@@ -0,0 +1,86 @@
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+
+pub type Key = [u8; 32];
+
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 24;
+
+pub(crate) fn random_key() -> Key {
+    XChaCha20Poly1305::generate_key(&mut OsRng).into()
+}
+
+// Node files in trees with per-node keys start with the keys of the node's
+// children, in order, ahead of the rest of their contents.
+pub(crate) fn prepend_keys(keys: &[Key], data: &[u8]) -> Vec<u8> {
+    let mut prepended = Vec::with_capacity(4 + keys.len() * KEY_SIZE + data.len());
+    prepended.extend_from_slice(&(keys.len() as u32).to_le_bytes());
+    for key in keys {
+        prepended.extend_from_slice(key);
+    }
+    prepended.extend_from_slice(data);
+    prepended
+}
+
+pub(crate) fn split_keys(data: &[u8]) -> Option<(Vec<Key>, &[u8])> {
+    let (len, data) = data.split_first_chunk::<4>()?;
+    let len = u32::from_le_bytes(*len) as usize;
+
+    let (keys, data) = data.split_at_checked(len.checked_mul(KEY_SIZE)?)?;
+    let keys = keys
+        .chunks_exact(KEY_SIZE)
+        .map(|key| key.try_into().unwrap())
+        .collect();
+
+    Some((keys, data))
+}
+
+// Encrypts and authenticates node and metadata files with XChaCha20-Poly1305.
+// Every encryption draws a fresh random nonce, which goes in front of the
+// ciphertext, so a file never reuses a nonce however often it's rewritten.
+//
+// Each file is bound to where it belongs through the associated data: nodes
+// use their uuid, and metadata its name. A file copied over another therefore
+// fails to authenticate instead of silently changing the tree.
+#[derive(Clone)]
+pub(crate) struct Cipher(XChaCha20Poly1305);
+
+impl Cipher {
+    pub fn new(key: &Key) -> Self {
+        Self(XChaCha20Poly1305::new(key.into()))
+    }
+
+    pub fn encrypt(&self, aad: &[u8], data: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, Payload { msg: data, aad })
+            .expect("node too large to encrypt");
+
+        let mut sealed = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    // Returns `None` if the data was tampered with, encrypted under another
+    // key, or bound to something else.
+    pub fn decrypt(&self, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_SIZE {
+            return None;
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        self.0
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .ok()
+    }
+}
@@ -0,0 +1,219 @@
+use super::{
+    codec::{Bincode, Codec},
+    directory::DirStore,
+    error::Error,
+    guard::ValueMutationGuard,
+    node::{Leaf, Link, Node, NodeRef},
+    store::NodeStore,
+    BPTree,
+};
+use serde::Deserialize;
+use std::{
+    fmt::{self, Debug},
+    mem,
+};
+
+// A view into a single entry of a tree, found with one descent so that the
+// entry can be read, changed, filled or removed without another.
+pub enum Entry<'a, K, V, S = DirStore, C = Bincode> {
+    Vacant(VacantEntry<'a, K, V, S, C>),
+    Occupied(OccupiedEntry<'a, K, V, S, C>),
+}
+
+pub struct VacantEntry<'a, K, V, S = DirStore, C = Bincode> {
+    key: K,
+    // The leaf the key belongs in and where, unless the tree is empty.
+    cursor: Option<(Link<K, V>, usize)>,
+    tree: &'a mut BPTree<K, V, S, C>,
+}
+
+pub struct OccupiedEntry<'a, K, V, S = DirStore, C = Bincode> {
+    cursor: Link<K, V>,
+    cursor_index: usize,
+    index: usize,
+    tree: &'a mut BPTree<K, V, S, C>,
+}
+
+impl<K, V, S: NodeStore, C: Codec> BPTree<K, V, S, C> {
+    pub fn entry(&mut self, key: K) -> Result<Entry<'_, K, V, S, C>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.pager.evict();
+
+        Ok(match self.search_leaf(&key)? {
+            Some((cursor, cursor_index, Ok(index))) => Entry::Occupied(OccupiedEntry {
+                cursor,
+                cursor_index,
+                index,
+                tree: self,
+            }),
+            Some((cursor, _, Err(index))) => Entry::Vacant(VacantEntry {
+                key,
+                cursor: Some((cursor, index)),
+                tree: self,
+            }),
+            None => Entry::Vacant(VacantEntry {
+                key,
+                cursor: None,
+                tree: self,
+            }),
+        })
+    }
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> Entry<'a, K, V, S, C> {
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Vacant(entry) => entry.key(),
+            Entry::Occupied(entry) => entry.key(),
+        }
+    }
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> VacantEntry<'a, K, V, S, C> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    // Inserts the value, returning a guard to it that logs the entry once
+    // it's dropped.
+    pub fn insert(self, value: V) -> Result<ValueMutationGuard<'a, K, V, S, C>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        let tree = self.tree;
+        let (cursor, index) = match self.cursor {
+            Some((cursor, index)) => unsafe { tree.insert_at(cursor, index, self.key, value)? },
+            None => (tree.insert_root(self.key, value), 0),
+        };
+
+        let node = unsafe { leaf(cursor) };
+        Ok(ValueMutationGuard {
+            key: &node.keys[index],
+            value: &mut node.values[index],
+            cursor,
+            pager: &tree.pager,
+        })
+    }
+
+    pub(crate) fn insert_unlogged(self, value: V) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        match self.cursor {
+            Some((cursor, index)) => unsafe {
+                self.tree.insert_at(cursor, index, self.key, value)?;
+            },
+            None => {
+                self.tree.insert_root(self.key, value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> OccupiedEntry<'a, K, V, S, C> {
+    pub fn key(&self) -> &K {
+        unsafe { &leaf(self.cursor).keys[self.index] }
+    }
+
+    pub fn get(&self) -> &V {
+        unsafe { &leaf(self.cursor).values[self.index] }
+    }
+
+    pub fn get_mut(&mut self) -> ValueMutationGuard<'_, K, V, S, C>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let node = unsafe { leaf(self.cursor) };
+        ValueMutationGuard {
+            key: &node.keys[self.index],
+            value: &mut node.values[self.index],
+            cursor: self.cursor,
+            pager: &self.tree.pager,
+        }
+    }
+
+    pub fn into_mut(self) -> ValueMutationGuard<'a, K, V, S, C>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let node = unsafe { leaf(self.cursor) };
+        ValueMutationGuard {
+            key: &node.keys[self.index],
+            value: &mut node.values[self.index],
+            cursor: self.cursor,
+            pager: &self.tree.pager,
+        }
+    }
+
+    // Swaps in a new value, returning the old one.
+    pub fn insert(&mut self, value: V) -> Result<V, Error> {
+        let record = self.tree.pager.encode_insert(self.key(), &value)?;
+        let old_value = self.insert_unlogged(value);
+        self.tree.pager.log(record)?;
+        Ok(old_value)
+    }
+
+    pub(crate) fn insert_unlogged(&mut self, mut value: V) -> V {
+        let node = unsafe { leaf(self.cursor) };
+        node.is_dirty = true;
+        mem::swap(&mut node.values[self.index], &mut value);
+        value
+    }
+
+    pub fn remove_entry(self) -> Result<(K, V), Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        let entry = unsafe {
+            self.tree
+                .remove_at::<K>(self.cursor, self.cursor_index, self.index)?
+        };
+        let record = self.tree.pager.encode_remove(&entry.0)?;
+        self.tree.pager.log(record)?;
+        Ok(entry)
+    }
+
+    pub fn remove(self) -> Result<V, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        Ok(self.remove_entry()?.1)
+    }
+}
+
+// The leaf an entry was found in. The tree is borrowed for as long as the
+// entry lives, so nothing can have evicted it since.
+unsafe fn leaf<'a, K, V>(cursor: Link<K, V>) -> &'a mut Leaf<K, V> {
+    match &mut *cursor.as_ptr() {
+        NodeRef::Loaded(Node::Leaf(node)) => node,
+        _ => unreachable!("entry cursor isn't a loaded leaf"),
+    }
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> Debug for Entry<'a, K, V, S, C>
+where
+    K: Debug,
+    V: Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Vacant(entry) => write!(f, "Entry({:?})", entry.key()),
+            Entry::Occupied(entry) => write!(f, "Entry({:?}: {:?})", entry.key(), entry.get()),
+        }
+    }
+}
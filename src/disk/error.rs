@@ -1,5 +1,6 @@
-use std::io;
+use std::{error, io};
 use thiserror::Error;
+use uuid::Uuid;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -10,8 +11,53 @@ pub enum Error {
     UnknownKey,
 
     #[error("failed serialization/deserizalization")]
-    Serde,
+    Serde(#[source] Box<dyn error::Error + Send + Sync>),
 
     #[error("bad b+-tree")]
     BadBPTree,
+
+    #[error("node {node} is corrupted")]
+    Corruption { node: Uuid },
+
+    #[error("metadata {name:?} is corrupted")]
+    MetadataCorruption { name: String },
+
+    #[error("node {node} failed authentication")]
+    Authentication { node: Uuid },
+
+    #[error("metadata {name:?} failed authentication")]
+    MetadataAuthentication { name: String },
+
+    #[error("log record {record} failed authentication")]
+    LogAuthentication { record: u64 },
+
+    #[error("not a b+-tree")]
+    BadMagic,
+
+    #[error("unsupported format version {found} (this build supports {supported})")]
+    UnsupportedVersion { found: u32, supported: u32 },
+
+    #[error("written with codec {found:?}, expected {expected:?}")]
+    CodecMismatch { expected: String, found: String },
+
+    #[error("written with schema {found:?}, expected {expected:?}")]
+    SchemaMismatch {
+        expected: String,
+        found: Option<String>,
+    },
+
+    #[error("tree {} encrypted", if *found { "is" } else { "isn't" })]
+    EncryptionMismatch { expected: bool, found: bool },
+
+    #[error("tree has already been persisted")]
+    AlreadyPersisted,
+
+    #[error("entry {index} is out of order or a duplicate")]
+    Unsorted { index: usize },
+}
+
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Self {
+        Self::Serde(err)
+    }
 }
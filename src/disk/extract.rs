@@ -0,0 +1,255 @@
+use serde::Deserialize;
+
+use super::{
+    codec::Codec,
+    error::Error,
+    iter::first_leaf,
+    node::{Link, Node},
+    store::NodeStore,
+    BPTree,
+};
+use std::mem;
+
+impl<K, V, S: NodeStore, C: Codec> BPTree<K, V, S, C> {
+    // Keeps only the entries `f` returns true for. Values are only lent out
+    // immutably, since changing them here would bypass the log.
+    pub fn retain<F>(&mut self, mut f: F) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Clone,
+        for<'de> V: Deserialize<'de>,
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.extract_with(|key, value| !f(key, value), drop)
+    }
+
+    // Removes every entry, returning them in order.
+    pub fn drain(&mut self) -> Result<Vec<(K, V)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.extract_if(|_, _| true)
+    }
+
+    // Removes and returns the entries `pred` returns true for, in order.
+    //
+    // Unlike the in-memory map's, this runs to completion rather than
+    // handing back a lazy iterator, as rebalancing the tree afterwards can
+    // fail and there would be nowhere to report it.
+    pub fn extract_if<F>(&mut self, pred: F) -> Result<Vec<(K, V)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Clone,
+        for<'de> V: Deserialize<'de>,
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut extracted = Vec::new();
+        self.extract_with(pred, |entry| extracted.push(entry))?;
+        Ok(extracted)
+    }
+
+    // Walks the leaf chain, filtering each leaf in one go and logging the
+    // removals, then rebalances the whole tree once at the end.
+    fn extract_with<F>(
+        &mut self,
+        mut pred: F,
+        mut extracted: impl FnMut((K, V)),
+    ) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Clone,
+        for<'de> V: Deserialize<'de>,
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.pager.evict();
+
+        let mut leaf = match self.root {
+            Some(root) => unsafe { Some(first_leaf(root, &self.pager)?) },
+            None => return Ok(()),
+        };
+
+        let mut removed = 0;
+        while let Some(cursor) = leaf {
+            let node = match unsafe { (*cursor.as_ptr()).access_mut(&self.pager)? } {
+                Node::Leaf(node) => node,
+                Node::Internal(_) => return Err(Error::BadBPTree),
+            };
+
+            let keys = mem::take(&mut node.keys);
+            let values = mem::take(&mut node.values);
+            let len = keys.len();
+
+            for (key, value) in keys.into_iter().zip(values) {
+                if pred(&key, &value) {
+                    let record = self.pager.encode_remove(&key)?;
+                    self.pager.log(record)?;
+                    extracted((key, value));
+                } else {
+                    node.keys.push(key);
+                    node.values.push(value);
+                }
+            }
+
+            if node.keys.len() != len {
+                removed += len - node.keys.len();
+                node.is_dirty = true;
+            }
+            leaf = node.next_leaf;
+        }
+
+        if removed > 0 {
+            self.len -= removed;
+            self.len_is_dirty = true;
+            unsafe { self.rebalance_all()? };
+        }
+
+        Ok(())
+    }
+
+    // Restores the occupancy invariants after entries were taken out of
+    // leaves anywhere in the tree. Leaves left empty are reclaimed, along
+    // with any internal nodes left without children.
+    unsafe fn rebalance_all(&mut self) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        if let Some(root) = self.root {
+            self.rebalance_subtree(root)?;
+        }
+
+        // That may leave the root with a single child, or with nothing.
+        while let Some(root) = self.root {
+            match (*root.as_ptr()).access(&self.pager)? {
+                Node::Internal(node) if node.children.len() < 2 => {
+                    self.root = node.children.first().copied();
+                }
+                Node::Leaf(node) if node.keys.is_empty() => self.root = None,
+                _ => break,
+            }
+
+            root.reclaim(&mut self.pager);
+            self.root_is_dirty = true;
+            if let Some(root) = self.root {
+                self.set_parent(root, None)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Rebalances everything under `cursor` from the bottom up.
+    unsafe fn rebalance_subtree(&mut self, cursor: Link<K, V>) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        if let Node::Internal(node) = (*cursor.as_ptr()).access(&self.pager)? {
+            for child in node.children.clone() {
+                self.rebalance_subtree(child)?;
+            }
+            self.rebalance_children(cursor)?;
+        }
+        Ok(())
+    }
+
+    // Reclaims the empty children of an internal node, then merges or evens
+    // out each underfull child with a neighbour. Only the node itself can be
+    // left underfull, when it ends up with a single child.
+    unsafe fn rebalance_children(&mut self, cursor: Link<K, V>) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        let node = match (*cursor.as_ptr()).access_mut(&self.pager)? {
+            Node::Internal(node) => node,
+            Node::Leaf(_) => return Ok(()),
+        };
+
+        let mut index = 0;
+        while index < node.children.len() {
+            let child = node.children[index];
+            let neighbours = match (*child.as_ptr()).access(&self.pager)? {
+                Node::Internal(child) if child.children.is_empty() => None,
+                Node::Leaf(child) if child.keys.is_empty() => {
+                    Some((child.prev_leaf, child.next_leaf))
+                }
+                _ => {
+                    index += 1;
+                    continue;
+                }
+            };
+
+            if !node.keys.is_empty() {
+                node.keys.remove(index.saturating_sub(1));
+            }
+            node.children.remove(index);
+            node.is_dirty = true;
+
+            if let Some((prev_leaf, next_leaf)) = neighbours {
+                self.unlink_leaf(prev_leaf, next_leaf)?;
+            }
+            child.reclaim(&mut self.pager);
+        }
+
+        while node.children.len() > 1 {
+            let mut underfull = None;
+            for (index, child) in node.children.iter().enumerate() {
+                let is_underfull = match (*child.as_ptr()).access(&self.pager)? {
+                    Node::Internal(child) => child.is_underfull(self.order),
+                    Node::Leaf(child) => child.is_underfull(self.order),
+                };
+                if is_underfull {
+                    underfull = Some(index);
+                    break;
+                }
+            }
+            let index = match underfull {
+                Some(index) => index,
+                None => break,
+            };
+
+            // Pair it with its left neighbour if it has one.
+            let index = index.saturating_sub(1);
+            let (left, right) = (node.children[index], node.children[index + 1]);
+            node.is_dirty = true;
+            match self.rebalance(left, right, node.keys[index].clone())? {
+                Some(split_key) => {
+                    node.keys[index] = split_key;
+                    self.rebalance_children(left)?;
+                    self.rebalance_children(right)?;
+                }
+                None => {
+                    node.keys.remove(index);
+                    node.children.remove(index + 1);
+                    self.rebalance_children(left)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Links the neighbours of a reclaimed leaf to each other.
+    unsafe fn unlink_leaf(
+        &mut self,
+        prev_leaf: Option<Link<K, V>>,
+        next_leaf: Option<Link<K, V>>,
+    ) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        if let Some(prev) = prev_leaf {
+            if let Node::Leaf(prev) = (*prev.as_ptr()).access_mut(&self.pager)? {
+                prev.next_leaf = next_leaf;
+                prev.is_dirty = true;
+            }
+        }
+        if let Some(next) = next_leaf {
+            if let Node::Leaf(next) = (*next.as_ptr()).access_mut(&self.pager)? {
+                next.prev_leaf = prev_leaf;
+                next.is_dirty = true;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+use super::{
+    checksum,
+    codec::{Bincode, Codec},
+    compression::Compression,
+    encryption::Key,
+    error::Error,
+    store::NodeStore,
+};
+use serde::{Deserialize, Serialize};
+use std::any;
+
+const FORMAT_METADATA: &str = "format";
+
+const MAGIC: [u8; 8] = *b"BPLUSTRE";
+
+// Bumped whenever the layout of nodes or metadata changes. Trees written
+// before the format was recorded count as version 0.
+pub(crate) const FORMAT_VERSION: u32 = 5;
+
+// The oldest version that can be read without a migration.
+const MIN_VERSION: u32 = 1;
+
+// Node files carry a compression flag from this version on. Trees written
+// before it keep their layout, and so stay uncompressed, until migrated.
+pub(crate) const COMPRESSION_VERSION: u32 = 2;
+
+// Trees can be encrypted from this version on.
+const ENCRYPTION_VERSION: u32 = 3;
+
+// Encrypted trees encrypt each node under a key of its own from this version
+// on, rather than under the tree's key.
+pub(crate) const NODE_KEY_VERSION: u32 = 4;
+
+// Leaves leave out zero-sized values from this version on.
+pub(crate) const KEYS_ONLY_VERSION: u32 = 5;
+
+// Rewrites a store left by an older format version, given as the second
+// argument, into the current one, and records the format it leaves the store
+// in. Persisting a tree built on the store does both.
+pub type Migration<S> = fn(&mut S, u32) -> Result<(), Error>;
+
+// Describes how a tree was written, so that loading it with an incompatible
+// build or with the wrong types fails up front. The magic and version come
+// first so they can be checked before the rest is parsed.
+//
+// The format is always encoded with bincode, since it names the codec
+// everything else is encoded with. Fields are only ever added at the end, so
+// older builds can still read the fields they know of.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Format {
+    magic: [u8; 8],
+    pub(crate) version: u32,
+    codec: String,
+    pub(crate) schema: Option<String>,
+    pub(crate) compression: Compression,
+    pub(crate) encrypted: bool,
+}
+
+impl Format {
+    pub fn new(version: u32, codec: &str) -> Self {
+        Self {
+            magic: MAGIC,
+            version,
+            codec: codec.into(),
+            schema: None,
+            compression: Compression::None,
+            encrypted: false,
+        }
+    }
+
+    // Reads and checks the format of the tree in `store`, migrating it first
+    // if it's an older version.
+    pub fn load<S: NodeStore>(
+        store: &mut S,
+        codec: &str,
+        options: &LoadOptions<S>,
+    ) -> Result<Self, Error> {
+        let format = match Self::read(store)? {
+            Some(format) if format.version >= MIN_VERSION => format,
+            old => {
+                let version = old.as_ref().map_or(0, |format| format.version);
+
+                // Trees from before the format was recorded are plaintext
+                // bincode, and migrations aren't given the key or codec to
+                // rewrite them with, so a load expecting either is turned away
+                // before the store is touched.
+                Self::new(version, Bincode::NAME).check_encoding(codec, options)?;
+
+                Self::migrate(store, options, version)?;
+                Self::read(store)?
+                    .filter(|format| format.version >= MIN_VERSION)
+                    .ok_or(Error::UnsupportedVersion {
+                        found: version,
+                        supported: FORMAT_VERSION,
+                    })?
+            }
+        };
+
+        format.check_encoding(codec, options)?;
+
+        if let Some(expected) = &options.schema {
+            if format.schema.as_ref() != Some(expected) {
+                return Err(Error::SchemaMismatch {
+                    expected: expected.clone(),
+                    found: format.schema,
+                });
+            }
+        }
+
+        Ok(format)
+    }
+
+    fn check_encoding<S>(&self, codec: &str, options: &LoadOptions<S>) -> Result<(), Error> {
+        if self.codec != codec {
+            return Err(Error::CodecMismatch {
+                expected: codec.into(),
+                found: self.codec.clone(),
+            });
+        }
+
+        if self.encrypted != options.key.is_some() {
+            return Err(Error::EncryptionMismatch {
+                expected: options.key.is_some(),
+                found: self.encrypted,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn read<S: NodeStore>(store: &S) -> Result<Option<Self>, Error> {
+        let data = match store.get_metadata(FORMAT_METADATA)? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        let data = checksum::unseal(&data).ok_or_else(|| Error::MetadataCorruption {
+            name: FORMAT_METADATA.into(),
+        })?;
+
+        let (magic, version): ([u8; 8], u32) =
+            bincode::deserialize(data).map_err(|_| Error::BadMagic)?;
+        if magic != MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        if version > FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion {
+                found: version,
+                supported: FORMAT_VERSION,
+            });
+        } else if version < MIN_VERSION {
+            // Left to the migration.
+            return Ok(Some(Self::new(version, "")));
+        }
+
+        // Older versions only have some of the fields.
+        let format = if version < COMPRESSION_VERSION {
+            let (_, _, codec, schema): ([u8; 8], u32, String, Option<String>) =
+                bincode::deserialize(data)?;
+            Self {
+                schema,
+                ..Self::new(version, &codec)
+            }
+        } else if version < ENCRYPTION_VERSION {
+            let (_, _, codec, schema, compression): (
+                [u8; 8],
+                u32,
+                String,
+                Option<String>,
+                Compression,
+            ) = bincode::deserialize(data)?;
+            Self {
+                schema,
+                compression,
+                ..Self::new(version, &codec)
+            }
+        } else {
+            bincode::deserialize(data)?
+        };
+
+        Ok(Some(format))
+    }
+
+    pub fn store<S: NodeStore>(&self, store: &mut S) -> Result<(), Error> {
+        let data = bincode::serialize(self)?;
+        store.put_metadata(FORMAT_METADATA, &checksum::seal(&data))
+    }
+
+    fn migrate<S: NodeStore>(
+        store: &mut S,
+        options: &LoadOptions<S>,
+        version: u32,
+    ) -> Result<(), Error> {
+        match options.migration {
+            Some(migration) => migration(store, version),
+            None => Err(Error::UnsupportedVersion {
+                found: version,
+                supported: FORMAT_VERSION,
+            }),
+        }
+    }
+}
+
+// A schema tag naming the key and value types, for trees that don't have a
+// tag of their own. Type names aren't guaranteed to be stable between
+// compiler versions, so a tree tagged with this may need to be loaded without
+// checking its schema after a toolchain upgrade.
+pub fn type_fingerprint<K, V>() -> String {
+    format!("{} => {}", any::type_name::<K>(), any::type_name::<V>())
+}
+
+// Checks and hooks that apply when loading a tree.
+pub struct LoadOptions<S> {
+    pub(crate) schema: Option<String>,
+    pub(crate) migration: Option<Migration<S>>,
+    pub(crate) key: Option<Key>,
+}
+
+impl<S> Default for LoadOptions<S> {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            migration: None,
+            key: None,
+        }
+    }
+}
+
+impl<S> LoadOptions<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Fails the load unless the tree was persisted with this schema tag.
+    pub fn schema(mut self, schema: impl Into<String>) -> Self {
+        self.schema = Some(schema.into());
+        self
+    }
+
+    // Upgrades trees written with an older format version instead of failing.
+    pub fn migration(mut self, migration: Migration<S>) -> Self {
+        self.migration = Some(migration);
+        self
+    }
+
+    // The key an encrypted tree was encrypted under.
+    pub fn key(mut self, key: Key) -> Self {
+        self.key = Some(key);
+        self
+    }
+}
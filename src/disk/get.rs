@@ -1,8 +1,119 @@
-use super::{error::Error, guard::ValueMutationGuard, node::Node, BPTree};
+use super::{
+    codec::Codec,
+    error::Error,
+    guard::ValueMutationGuard,
+    node::{Link, Node},
+    store::NodeStore,
+    BPTree,
+};
 use serde::Deserialize;
 use std::borrow::Borrow;
 
-impl<K, V> BPTree<K, V> {
+impl<K, V, S: NodeStore, C: Codec> BPTree<K, V, S, C> {
+    // Finds the leaf a key is or belongs in, along with which child of its
+    // parent it is and where the key is or belongs in it.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn search_leaf<Q>(
+        &self,
+        key: &Q,
+    ) -> Result<Option<(Link<K, V>, usize, Result<usize, usize>)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+    {
+        let mut cursor = match self.root {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+        let mut cursor_index = 0;
+
+        unsafe {
+            while let Node::Internal(node) = (*cursor.as_ptr()).access(&self.pager)? {
+                cursor_index = match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
+                    Ok(index) => index + 1,
+                    Err(index) => index,
+                };
+                cursor = node.children[cursor_index];
+            }
+
+            match (*cursor.as_ptr()).access(&self.pager)? {
+                Node::Leaf(node) => Ok(Some((
+                    cursor,
+                    cursor_index,
+                    node.keys.binary_search_by(|probe| probe.borrow().cmp(key)),
+                ))),
+                Node::Internal(_) => Ok(None),
+            }
+        }
+    }
+
+    // Finds the leftmost or rightmost leaf, along with which child of its
+    // parent it is and the index of its first or last entry.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn edge_leaf(&self, last: bool) -> Result<Option<(Link<K, V>, usize, usize)>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let mut cursor = match self.root {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+        let mut cursor_index = 0;
+
+        unsafe {
+            loop {
+                match (*cursor.as_ptr()).access(&self.pager)? {
+                    Node::Internal(node) => {
+                        cursor_index = if last { node.children.len() - 1 } else { 0 };
+                        cursor = node.children[cursor_index];
+                    }
+                    Node::Leaf(node) => {
+                        let index = if last { node.keys.len() - 1 } else { 0 };
+                        return Ok(Some((cursor, cursor_index, index)));
+                    }
+                }
+            }
+        }
+    }
+
+    fn edge_key_value(&self, last: bool) -> Result<Option<(&K, &V)>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let (cursor, _, index) = match self.edge_leaf(last)? {
+            Some(edge) => edge,
+            None => return Ok(None),
+        };
+
+        unsafe {
+            match (*cursor.as_ptr()).access(&self.pager)? {
+                Node::Leaf(node) => Ok(Some((&node.keys[index], &node.values[index]))),
+                Node::Internal(_) => Ok(None),
+            }
+        }
+    }
+
+    // Only loads the leftmost path of the tree.
+    pub fn first_key_value(&self) -> Result<Option<(&K, &V)>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.edge_key_value(false)
+    }
+
+    // Only loads the rightmost path of the tree.
+    pub fn last_key_value(&self) -> Result<Option<(&K, &V)>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.edge_key_value(true)
+    }
+
     pub fn get_key_value<Q>(&self, key: &Q) -> Result<Option<(&K, &V)>, Error>
     where
         for<'de> K: Deserialize<'de> + Borrow<Q>,
@@ -16,7 +127,7 @@ impl<K, V> BPTree<K, V> {
         unsafe {
             let mut cursor = self.root.unwrap();
 
-            while let Node::Internal(node) = (*cursor.as_ptr()).access(&self.path)? {
+            while let Node::Internal(node) = (*cursor.as_ptr()).access(&self.pager)? {
                 let index = match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
                     Ok(index) => index + 1,
                     Err(index) => index,
@@ -24,7 +135,7 @@ impl<K, V> BPTree<K, V> {
                 cursor = node.children[index];
             }
 
-            if let Node::Leaf(node) = (*cursor.as_ptr()).access(&self.path)? {
+            if let Node::Leaf(node) = (*cursor.as_ptr()).access(&self.pager)? {
                 Ok(node
                     .keys
                     .binary_search_by(|probe| probe.borrow().cmp(key))
@@ -45,15 +156,18 @@ impl<K, V> BPTree<K, V> {
         Ok(self.get_key_value(key)?.map(|(_, value)| value))
     }
 
+    #[allow(clippy::type_complexity)]
     pub fn get_key_value_mut<Q>(
         &mut self,
         key: &Q,
-    ) -> Result<Option<(&K, ValueMutationGuard<K, V>)>, Error>
+    ) -> Result<Option<(&K, ValueMutationGuard<'_, K, V, S, C>)>, Error>
     where
         for<'de> K: Deserialize<'de> + Borrow<Q>,
         for<'de> V: Deserialize<'de>,
         Q: Ord,
     {
+        self.pager.evict();
+
         if self.root.is_none() {
             return Ok(None);
         }
@@ -61,7 +175,7 @@ impl<K, V> BPTree<K, V> {
         unsafe {
             let mut cursor = self.root.unwrap();
 
-            while let Node::Internal(node) = (*cursor.as_ptr()).access(&self.path)? {
+            while let Node::Internal(node) = (*cursor.as_ptr()).access(&self.pager)? {
                 let index = match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
                     Ok(index) => index + 1,
                     Err(index) => index,
@@ -69,7 +183,7 @@ impl<K, V> BPTree<K, V> {
                 cursor = node.children[index];
             }
 
-            if let Node::Leaf(node) = (*cursor.as_ptr()).access_mut(&self.path)? {
+            if let Node::Leaf(node) = (*cursor.as_ptr()).access_mut(&self.pager)? {
                 Ok(node
                     .keys
                     .binary_search_by(|probe| probe.borrow().cmp(key))
@@ -77,9 +191,10 @@ impl<K, V> BPTree<K, V> {
                         (
                             &node.keys[index],
                             ValueMutationGuard {
+                                key: &node.keys[index],
                                 value: &mut node.values[index],
                                 cursor,
-                                path: &self.path,
+                                pager: &self.pager,
                             },
                         )
                     })
@@ -90,7 +205,10 @@ impl<K, V> BPTree<K, V> {
         }
     }
 
-    pub fn get_mut<Q>(&mut self, key: &Q) -> Result<Option<ValueMutationGuard<K, V>>, Error>
+    pub fn get_mut<Q>(
+        &mut self,
+        key: &Q,
+    ) -> Result<Option<ValueMutationGuard<'_, K, V, S, C>>, Error>
     where
         for<'de> K: Deserialize<'de> + Borrow<Q>,
         for<'de> V: Deserialize<'de>,
@@ -1,22 +1,30 @@
-use super::node::{Link, Node};
+use super::{
+    codec::{Bincode, Codec},
+    directory::DirStore,
+    node::{Link, Node},
+    pager::Pager,
+    store::NodeStore,
+};
 use serde::Deserialize;
 use std::{
     fmt::{self, Debug},
     ops::{Deref, DerefMut},
-    path::PathBuf,
 };
 
-pub struct ValueMutationGuard<'a, K, V>
+pub struct ValueMutationGuard<'a, K, V, S = DirStore, C = Bincode>
 where
+    S: NodeStore,
+    C: Codec,
     for<'de> K: Deserialize<'de>,
     for<'de> V: Deserialize<'de>,
 {
+    pub(crate) key: &'a K,
     pub(crate) value: &'a mut V,
     pub(crate) cursor: Link<K, V>,
-    pub(crate) path: &'a PathBuf,
+    pub(crate) pager: &'a Pager<K, V, S, C>,
 }
 
-impl<'a, K, V> Deref for ValueMutationGuard<'a, K, V>
+impl<'a, K, V, S: NodeStore, C: Codec> Deref for ValueMutationGuard<'a, K, V, S, C>
 where
     for<'de> K: Deserialize<'de>,
     for<'de> V: Deserialize<'de>,
@@ -28,7 +36,7 @@ where
     }
 }
 
-impl<'a, K, V> DerefMut for ValueMutationGuard<'a, K, V>
+impl<'a, K, V, S: NodeStore, C: Codec> DerefMut for ValueMutationGuard<'a, K, V, S, C>
 where
     for<'de> K: Deserialize<'de>,
     for<'de> V: Deserialize<'de>,
@@ -38,22 +46,27 @@ where
     }
 }
 
-impl<'a, K, V> Drop for ValueMutationGuard<'a, K, V>
+impl<'a, K, V, S: NodeStore, C: Codec> Drop for ValueMutationGuard<'a, K, V, S, C>
 where
     for<'de> K: Deserialize<'de>,
     for<'de> V: Deserialize<'de>,
 {
     fn drop(&mut self) {
         unsafe {
-            match (*self.cursor.as_ptr()).access_mut(&self.path).unwrap() {
+            match (*self.cursor.as_ptr()).access_mut(self.pager).unwrap() {
                 Node::Internal(node) => node.is_dirty = true,
                 Node::Leaf(node) => node.is_dirty = true,
             }
         }
+
+        if let Some(wal) = self.pager.wal.borrow_mut().as_mut() {
+            let record = wal.encode_insert(self.key, self.value);
+            wal.append_or_defer(record);
+        }
     }
 }
 
-impl<'a, K, V> Debug for ValueMutationGuard<'a, K, V>
+impl<'a, K, V, S: NodeStore, C: Codec> Debug for ValueMutationGuard<'a, K, V, S, C>
 where
     for<'de> K: Deserialize<'de>,
     for<'de> V: Deserialize<'de> + Debug,
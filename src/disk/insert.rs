@@ -1,128 +1,149 @@
 use super::{
+    codec::Codec,
+    entry::Entry,
     error::Error,
     node::{Internal, Leaf, Link, Node},
+    store::NodeStore,
     BPTree,
 };
 use serde::Deserialize;
-use std::mem;
 use uuid::Uuid;
 
-impl<K, V> BPTree<K, V> {
-    pub fn insert(&mut self, key: K, mut value: V) -> Result<Option<V>, Error>
+impl<K, V, S: NodeStore, C: Codec> BPTree<K, V, S, C> {
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, Error>
     where
         for<'de> K: Deserialize<'de> + Ord + Clone,
         for<'de> V: Deserialize<'de>,
     {
-        unsafe {
-            if self.root.is_none() {
-                let new_root = Link::new(Node::Leaf(Leaf {
-                    uuid: Uuid::new_v4(),
-                    keys: vec![key],
-                    values: vec![value],
-                    parent: None,
-                    next_leaf: None,
-                    is_dirty: true,
-                }));
+        let record = self.pager.encode_insert(&key, &value)?;
+        let old_value = self.insert_unlogged(key, value)?;
+        self.pager.log(record)?;
+        Ok(old_value)
+    }
 
-                self.root = Some(new_root);
-                self.root_is_dirty = true;
+    pub(crate) fn insert_unlogged(&mut self, key: K, value: V) -> Result<Option<V>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        match self.entry(key)? {
+            Entry::Occupied(mut entry) => Ok(Some(entry.insert_unlogged(value))),
+            Entry::Vacant(entry) => {
+                entry.insert_unlogged(value)?;
+                Ok(None)
+            }
+        }
+    }
 
-                self.len += 1;
-                self.len_is_dirty = true;
+    pub(crate) fn insert_root(&mut self, key: K, value: V) -> Link<K, V> {
+        let new_root = self.pager.alloc(Node::Leaf(Leaf {
+            uuid: Uuid::new_v4(),
+            keys: vec![key],
+            values: vec![value],
+            parent: None,
+            next_leaf: None,
+            prev_leaf: None,
+            is_dirty: true,
+        }));
 
-                return Ok(None);
-            }
+        self.root = Some(new_root);
+        self.root_is_dirty = true;
 
-            let mut cursor = self.root.unwrap();
+        self.len += 1;
+        self.len_is_dirty = true;
 
-            // Descend the tree to the leaf node that the key should go in.
-            while let Node::Internal(node) = (*cursor.as_ptr()).access_mut(&self.path)? {
-                let index = match node.keys.binary_search(&key) {
-                    Ok(index) => index + 1,
-                    Err(index) => index,
-                };
-                cursor = node.children[index];
-                node.is_dirty = true;
-            }
+        new_root
+    }
 
-            if let Node::Leaf(node) = (*cursor.as_ptr()).access_mut(&self.path)? {
-                node.is_dirty = true;
+    // Inserts a key that isn't in the tree at `index` in the leaf it belongs
+    // in, splitting as needed. Returns the leaf the entry ends up in and where.
+    pub(crate) unsafe fn insert_at(
+        &mut self,
+        cursor: Link<K, V>,
+        index: usize,
+        key: K,
+        value: V,
+    ) -> Result<(Link<K, V>, usize), Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        if let Node::Leaf(node) = (*cursor.as_ptr()).access_mut(&self.pager)? {
+            node.is_dirty = true;
 
-                // Check if we already have a copy of this key and just need to
-                // swap in the updated value.
-                match node.keys.binary_search(&key) {
-                    Ok(index) => {
-                        // The key exists.
-                        mem::swap(&mut node.values[index], &mut value);
-                        return Ok(Some(value));
-                    }
-                    Err(index) => {
-                        // The key doesn't exist, so insert it.
-                        node.keys.insert(index, key);
-                        node.values.insert(index, value);
+            node.keys.insert(index, key);
+            node.values.insert(index, value);
 
-                        self.len += 1;
-                        self.len_is_dirty = true;
+            self.len += 1;
+            self.len_is_dirty = true;
 
-                        // We're done if the node isn't overfull.
-                        if !node.is_overfull(self.order) {
-                            return Ok(None);
-                        }
+            // We're done if the node isn't overfull.
+            if !node.is_overfull(self.order) {
+                return Ok((cursor, index));
+            }
 
-                        // The leaf node is overfull, so we split it in two.
-                        let split_index = node.keys.len() / 2;
-                        let sibling_keys = node.keys.drain(split_index..).collect::<Vec<_>>();
-                        let sibling_values = node.values.drain(split_index..).collect::<Vec<_>>();
-                        let split_key = sibling_keys[0].clone();
-
-                        // Make the sibling now so we can link to it.
-                        let sibling = Link::new(Node::Leaf(Leaf {
-                            uuid: Uuid::new_v4(),
-                            keys: sibling_keys,
-                            values: sibling_values,
-                            parent: node.parent,
-                            next_leaf: node.next_leaf,
-                            is_dirty: true,
-                        }));
-
-                        // Connect to the sibling.
-                        node.next_leaf = Some(sibling);
-
-                        if Some(cursor) == self.root {
-                            // We need a new root since we split it.
-                            let new_root = Link::new(Node::Internal(Internal {
-                                uuid: Uuid::new_v4(),
-                                keys: vec![split_key],
-                                children: vec![cursor, sibling],
-                                parent: None,
-                                is_dirty: true,
-                            }));
-
-                            // Connect the cursor to the new root.
-                            if let Node::Leaf(node) = (*cursor.as_ptr()).access_mut(&self.path)? {
-                                node.parent = Some(new_root);
-                            }
+            // The leaf node is overfull, so we split it in two.
+            let split_index = node.keys.len() / 2;
+            let sibling_keys = node.keys.drain(split_index..).collect::<Vec<_>>();
+            let sibling_values = node.values.drain(split_index..).collect::<Vec<_>>();
+            let split_key = sibling_keys[0].clone();
+
+            // Make the sibling now so we can link to it.
+            let sibling = self.pager.alloc(Node::Leaf(Leaf {
+                uuid: Uuid::new_v4(),
+                keys: sibling_keys,
+                values: sibling_values,
+                parent: node.parent,
+                next_leaf: node.next_leaf,
+                prev_leaf: Some(cursor),
+                is_dirty: true,
+            }));
+
+            // Connect the sibling and the leaf after it.
+            if let Some(next_leaf) = node.next_leaf {
+                if let Node::Leaf(next_leaf) = (*next_leaf.as_ptr()).access_mut(&self.pager)? {
+                    next_leaf.prev_leaf = Some(sibling);
+                    next_leaf.is_dirty = true;
+                }
+            }
 
-                            // Connect the sibling to the new root.
-                            if let Node::Leaf(sibling_node) =
-                                (*sibling.as_ptr()).access_mut(&self.path)?
-                            {
-                                sibling_node.parent = Some(new_root);
-                            }
+            // Connect to the sibling.
+            node.next_leaf = Some(sibling);
 
-                            // Use the new root.
-                            self.root = Some(new_root);
-                            self.root_is_dirty = true;
-                        } else {
-                            // Insert to the parent.
-                            self.insert_internal(split_key, node.parent.unwrap(), sibling)?;
-                        }
-                    }
+            if Some(cursor) == self.root {
+                // We need a new root since we split it.
+                let new_root = self.pager.alloc(Node::Internal(Internal {
+                    uuid: Uuid::new_v4(),
+                    keys: vec![split_key],
+                    children: vec![cursor, sibling],
+                    parent: None,
+                    is_dirty: true,
+                }));
+
+                // Connect the cursor to the new root.
+                if let Node::Leaf(node) = (*cursor.as_ptr()).access_mut(&self.pager)? {
+                    node.parent = Some(new_root);
                 }
+
+                // Connect the sibling to the new root.
+                if let Node::Leaf(sibling_node) = (*sibling.as_ptr()).access_mut(&self.pager)? {
+                    sibling_node.parent = Some(new_root);
+                }
+
+                // Use the new root.
+                self.root = Some(new_root);
+                self.root_is_dirty = true;
+            } else {
+                // Insert to the parent.
+                self.insert_internal(split_key, node.parent.unwrap(), sibling)?;
             }
 
-            Ok(None)
+            if index >= split_index {
+                return Ok((sibling, index - split_index));
+            }
         }
+
+        Ok((cursor, index))
     }
 
     fn insert_internal(
@@ -136,7 +157,7 @@ impl<K, V> BPTree<K, V> {
         for<'de> V: Deserialize<'de>,
     {
         unsafe {
-            if let Node::Internal(node) = (*cursor.as_ptr()).access_mut(&self.path)? {
+            if let Node::Internal(node) = (*cursor.as_ptr()).access_mut(&self.pager)? {
                 node.is_dirty = true;
 
                 // Find where the key should go.
@@ -148,7 +169,21 @@ impl<K, V> BPTree<K, V> {
                 // Insert the key and child.
                 node.keys.insert(index, key);
                 node.children.insert(index + 1, child);
+            }
 
+            self.split_internal(cursor)
+        }
+    }
+
+    // Splits an internal node in two if it's overfull, inserting the split
+    // key into its parent.
+    pub(crate) fn split_internal(&mut self, cursor: Link<K, V>) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        unsafe {
+            if let Node::Internal(node) = (*cursor.as_ptr()).access_mut(&self.pager)? {
                 // We're done if the node isn't overfull.
                 if !node.is_overfull(self.order) {
                     return Ok(());
@@ -161,7 +196,7 @@ impl<K, V> BPTree<K, V> {
                 let split_key = node.keys.pop().unwrap();
 
                 // Make the sibling now so we can link to it.
-                let sibling = Link::new(Node::Internal(Internal {
+                let sibling = self.pager.alloc(Node::Internal(Internal {
                     uuid: Uuid::new_v4(),
                     keys: sibling_keys,
                     children: sibling_children,
@@ -170,9 +205,9 @@ impl<K, V> BPTree<K, V> {
                 }));
 
                 // Fix up the parent for the sibling children.
-                if let Node::Internal(sibling_node) = (*sibling.as_ptr()).access_mut(&self.path)? {
+                if let Node::Internal(sibling_node) = (*sibling.as_ptr()).access_mut(&self.pager)? {
                     for child in sibling_node.children.iter_mut() {
-                        match (*child.as_ptr()).access_mut(&self.path)? {
+                        match (*child.as_ptr()).access_mut(&self.pager)? {
                             Node::Internal(child) => {
                                 child.parent = Some(sibling);
                                 child.is_dirty = true;
@@ -187,7 +222,7 @@ impl<K, V> BPTree<K, V> {
 
                 if Some(cursor) == self.root {
                     // The root split, so create a new root.
-                    let new_root = Link::new(Node::Internal(Internal {
+                    let new_root = self.pager.alloc(Node::Internal(Internal {
                         uuid: Uuid::new_v4(),
                         keys: vec![split_key],
                         children: vec![cursor, sibling],
@@ -195,7 +230,7 @@ impl<K, V> BPTree<K, V> {
                         is_dirty: true,
                     }));
 
-                    if let Node::Internal(sibling) = (*sibling.as_ptr()).access_mut(&self.path)? {
+                    if let Node::Internal(sibling) = (*sibling.as_ptr()).access_mut(&self.pager)? {
                         sibling.parent = Some(new_root);
                     }
 
@@ -1,54 +1,93 @@
 use super::{
+    codec::{Bincode, Codec},
+    directory::DirStore,
     error::Error,
     guard::ValueMutationGuard,
     node::{Link, Node},
+    pager::Pager,
+    store::NodeStore,
     BPTree,
 };
 use serde::Deserialize;
-use std::path::PathBuf;
 
-impl<K, V> BPTree<K, V> {
-    pub fn iter(&self) -> Iter<K, V> {
+impl<K, V, S: NodeStore, C: Codec> BPTree<K, V, S, C> {
+    pub fn iter(&self) -> Iter<'_, K, V, S, C> {
         Iter {
-            cursor: self.root,
-            index: 0,
+            front: self.root,
+            front_index: 0,
+            back: self.root,
+            back_index: 0,
             len: self.len,
             errored: false,
-            at_leaves: false,
-            path: &self.path,
+            front_at_leaves: false,
+            back_at_leaves: false,
+            pager: &self.pager,
         }
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<K, V> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V, S, C> {
         IterMut {
-            cursor: self.root,
-            index: 0,
+            front: self.root,
+            front_index: 0,
+            back: self.root,
+            back_index: 0,
             len: self.len,
             errored: false,
-            at_leaves: false,
-            path: &self.path,
+            front_at_leaves: false,
+            back_at_leaves: false,
+            pager: &self.pager,
         }
     }
 
-    pub fn keys(&self) -> Keys<K, V> {
+    pub fn keys(&self) -> Keys<'_, K, V, S, C> {
         Keys(self.iter())
     }
 
-    pub fn values(&self) -> Values<K, V> {
+    pub fn values(&self) -> Values<'_, K, V, S, C> {
         Values(self.iter())
     }
 
-    pub fn values_mut(&mut self) -> ValuesMut<K, V> {
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V, S, C> {
         ValuesMut(self.iter_mut())
     }
 }
 
-impl<'a, K, V> IntoIterator for &'a BPTree<K, V>
+// Descends to the leftmost leaf under `cursor`.
+pub(crate) unsafe fn first_leaf<K, V, S: NodeStore, C: Codec>(
+    mut cursor: Link<K, V>,
+    pager: &Pager<K, V, S, C>,
+) -> Result<Link<K, V>, Error>
 where
     for<'de> K: Deserialize<'de>,
     for<'de> V: Deserialize<'de>,
 {
-    type IntoIter = Iter<'a, K, V>;
+    while let Node::Internal(node) = (*cursor.as_ptr()).access(pager)? {
+        cursor = node.children[0];
+    }
+    Ok(cursor)
+}
+
+// Descends to the rightmost leaf under `cursor`.
+pub(crate) unsafe fn last_leaf<K, V, S: NodeStore, C: Codec>(
+    mut cursor: Link<K, V>,
+    pager: &Pager<K, V, S, C>,
+) -> Result<Link<K, V>, Error>
+where
+    for<'de> K: Deserialize<'de>,
+    for<'de> V: Deserialize<'de>,
+{
+    while let Node::Internal(node) = (*cursor.as_ptr()).access(pager)? {
+        cursor = node.children[node.children.len() - 1];
+    }
+    Ok(cursor)
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> IntoIterator for &'a BPTree<K, V, S, C>
+where
+    for<'de> K: Deserialize<'de>,
+    for<'de> V: Deserialize<'de>,
+{
+    type IntoIter = Iter<'a, K, V, S, C>;
     type Item = Result<(&'a K, &'a V), Error>;
 
     fn into_iter(self) -> Self::IntoIter {
@@ -56,16 +95,22 @@ where
     }
 }
 
-pub struct Iter<'a, K, V> {
-    pub(crate) cursor: Option<Link<K, V>>,
-    pub(crate) index: usize,
+// The back of an iterator is tracked with an index that counts entries from
+// the end of its leaf, so stepping back to the previous leaf doesn't need to
+// load it.
+pub struct Iter<'a, K, V, S = DirStore, C = Bincode> {
+    pub(crate) front: Option<Link<K, V>>,
+    pub(crate) front_index: usize,
+    pub(crate) back: Option<Link<K, V>>,
+    pub(crate) back_index: usize,
     pub(crate) len: usize,
     pub(crate) errored: bool,
-    pub(crate) at_leaves: bool,
-    pub(crate) path: &'a PathBuf,
+    pub(crate) front_at_leaves: bool,
+    pub(crate) back_at_leaves: bool,
+    pub(crate) pager: &'a Pager<K, V, S, C>,
 }
 
-impl<'a, K, V> Iterator for Iter<'a, K, V>
+impl<'a, K, V, S: NodeStore, C: Codec> Iterator for Iter<'a, K, V, S, C>
 where
     for<'de> K: Deserialize<'de> + 'a,
     for<'de> V: Deserialize<'de> + 'a,
@@ -77,44 +122,35 @@ where
             return None;
         }
 
-        let mut cursor = self.cursor?;
-
-        if !self.at_leaves {
-            loop {
-                unsafe {
-                    match (*cursor.as_ptr()).access(&self.path) {
-                        Ok(node) => match node {
-                            Node::Internal(node) => {
-                                cursor = node.children[0];
-                            }
-                            Node::Leaf(_) => {
-                                self.cursor = Some(cursor);
-                                self.at_leaves = true;
-                                break;
-                            }
-                        },
-                        Err(err) => {
-                            self.errored = true;
-                            return Some(Err(err));
-                        }
-                    }
+        let mut cursor = self.front?;
+
+        if !self.front_at_leaves {
+            match unsafe { first_leaf(cursor, self.pager) } {
+                Ok(leaf) => {
+                    cursor = leaf;
+                    self.front = Some(cursor);
+                    self.front_at_leaves = true;
+                }
+                Err(err) => {
+                    self.errored = true;
+                    return Some(Err(err));
                 }
             }
         }
 
         unsafe {
-            match (*cursor.as_ptr()).access(&self.path) {
+            match (*cursor.as_ptr()).access(self.pager) {
                 Ok(node) => match node {
                     Node::Internal(_) => None,
                     Node::Leaf(node) => {
-                        let result = (&node.keys[self.index], &node.values[self.index]);
+                        let result = (&node.keys[self.front_index], &node.values[self.front_index]);
 
                         self.len -= 1;
-                        self.index += 1;
+                        self.front_index += 1;
 
-                        if self.index >= node.keys.len() {
-                            self.index = 0;
-                            self.cursor = node.next_leaf;
+                        if self.front_index >= node.keys.len() {
+                            self.front_index = 0;
+                            self.front = node.next_leaf;
                         }
 
                         Some(Ok(result))
@@ -133,7 +169,61 @@ where
     }
 }
 
-impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V>
+impl<'a, K, V, S: NodeStore, C: Codec> DoubleEndedIterator for Iter<'a, K, V, S, C>
+where
+    for<'de> K: Deserialize<'de> + 'a,
+    for<'de> V: Deserialize<'de> + 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 || self.errored {
+            return None;
+        }
+
+        let mut cursor = self.back?;
+
+        if !self.back_at_leaves {
+            match unsafe { last_leaf(cursor, self.pager) } {
+                Ok(leaf) => {
+                    cursor = leaf;
+                    self.back = Some(cursor);
+                    self.back_at_leaves = true;
+                }
+                Err(err) => {
+                    self.errored = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        unsafe {
+            match (*cursor.as_ptr()).access(self.pager) {
+                Ok(node) => match node {
+                    Node::Internal(_) => None,
+                    Node::Leaf(node) => {
+                        let index = node.keys.len() - 1 - self.back_index;
+                        let result = (&node.keys[index], &node.values[index]);
+
+                        self.len -= 1;
+                        self.back_index += 1;
+
+                        if self.back_index >= node.keys.len() {
+                            self.back_index = 0;
+                            self.back = node.prev_leaf;
+                        }
+
+                        Some(Ok(result))
+                    }
+                },
+                Err(err) => {
+                    self.errored = true;
+                    Some(Err(err))
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> ExactSizeIterator for Iter<'a, K, V, S, C>
 where
     for<'de> K: Deserialize<'de> + 'a,
     for<'de> V: Deserialize<'de> + 'a,
@@ -143,72 +233,67 @@ where
     }
 }
 
-pub struct IterMut<'a, K, V> {
-    pub(crate) cursor: Option<Link<K, V>>,
-    pub(crate) index: usize,
+pub struct IterMut<'a, K, V, S = DirStore, C = Bincode> {
+    pub(crate) front: Option<Link<K, V>>,
+    pub(crate) front_index: usize,
+    pub(crate) back: Option<Link<K, V>>,
+    pub(crate) back_index: usize,
     pub(crate) len: usize,
     pub(crate) errored: bool,
-    pub(crate) at_leaves: bool,
-    pub(crate) path: &'a PathBuf,
+    pub(crate) front_at_leaves: bool,
+    pub(crate) back_at_leaves: bool,
+    pub(crate) pager: &'a Pager<K, V, S, C>,
 }
 
-impl<'a, K, V> Iterator for IterMut<'a, K, V>
+impl<'a, K, V, S: NodeStore, C: Codec> Iterator for IterMut<'a, K, V, S, C>
 where
     for<'de> K: Deserialize<'de> + 'a,
     for<'de> V: Deserialize<'de> + 'a,
 {
-    type Item = Result<(&'a K, ValueMutationGuard<'a, K, V>), Error>;
+    type Item = Result<(&'a K, ValueMutationGuard<'a, K, V, S, C>), Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.len == 0 || self.errored {
             return None;
         }
 
-        let mut cursor = self.cursor?;
-
-        if !self.at_leaves {
-            loop {
-                unsafe {
-                    match (*cursor.as_ptr()).access(&self.path) {
-                        Ok(node) => match node {
-                            Node::Internal(node) => {
-                                cursor = node.children[0];
-                            }
-                            Node::Leaf(_) => {
-                                self.cursor = Some(cursor);
-                                self.at_leaves = true;
-                                break;
-                            }
-                        },
-                        Err(err) => {
-                            self.errored = true;
-                            return Some(Err(err));
-                        }
-                    }
+        let mut cursor = self.front?;
+
+        if !self.front_at_leaves {
+            match unsafe { first_leaf(cursor, self.pager) } {
+                Ok(leaf) => {
+                    cursor = leaf;
+                    self.front = Some(cursor);
+                    self.front_at_leaves = true;
+                }
+                Err(err) => {
+                    self.errored = true;
+                    return Some(Err(err));
                 }
             }
         }
 
         unsafe {
-            match (*cursor.as_ptr()).access_mut(&self.path) {
+            match (*cursor.as_ptr()).access_mut(self.pager) {
                 Ok(node) => match node {
                     Node::Internal(_) => None,
                     Node::Leaf(node) => {
                         let result = (
-                            &node.keys[self.index],
+                            &node.keys[self.front_index],
                             ValueMutationGuard {
-                                value: &mut node.values[self.index],
+                                key: &node.keys[self.front_index],
+                                value: &mut node.values[self.front_index],
                                 cursor,
-                                path: &self.path,
+                                pager: self.pager,
                             },
                         );
 
                         self.len -= 1;
-                        self.index += 1;
+                        self.front_index += 1;
 
-                        if self.index >= node.keys.len() {
-                            self.index = 0;
-                            self.cursor = node.next_leaf;
+                        if self.front_index >= node.keys.len() {
+                            self.front_index = 0;
+                            self.front = node.next_leaf;
                         }
 
                         Some(Ok(result))
@@ -227,7 +312,69 @@ where
     }
 }
 
-impl<'a, K, V> ExactSizeIterator for IterMut<'a, K, V>
+impl<'a, K, V, S: NodeStore, C: Codec> DoubleEndedIterator for IterMut<'a, K, V, S, C>
+where
+    for<'de> K: Deserialize<'de> + 'a,
+    for<'de> V: Deserialize<'de> + 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 || self.errored {
+            return None;
+        }
+
+        let mut cursor = self.back?;
+
+        if !self.back_at_leaves {
+            match unsafe { last_leaf(cursor, self.pager) } {
+                Ok(leaf) => {
+                    cursor = leaf;
+                    self.back = Some(cursor);
+                    self.back_at_leaves = true;
+                }
+                Err(err) => {
+                    self.errored = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        unsafe {
+            match (*cursor.as_ptr()).access_mut(self.pager) {
+                Ok(node) => match node {
+                    Node::Internal(_) => None,
+                    Node::Leaf(node) => {
+                        let index = node.keys.len() - 1 - self.back_index;
+                        let result = (
+                            &node.keys[index],
+                            ValueMutationGuard {
+                                key: &node.keys[index],
+                                value: &mut node.values[index],
+                                cursor,
+                                pager: self.pager,
+                            },
+                        );
+
+                        self.len -= 1;
+                        self.back_index += 1;
+
+                        if self.back_index >= node.keys.len() {
+                            self.back_index = 0;
+                            self.back = node.prev_leaf;
+                        }
+
+                        Some(Ok(result))
+                    }
+                },
+                Err(err) => {
+                    self.errored = true;
+                    Some(Err(err))
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> ExactSizeIterator for IterMut<'a, K, V, S, C>
 where
     for<'de> K: Deserialize<'de> + 'a,
     for<'de> V: Deserialize<'de> + 'a,
@@ -236,9 +383,9 @@ where
         self.len
     }
 }
-pub struct Keys<'a, K, V>(pub(crate) Iter<'a, K, V>);
+pub struct Keys<'a, K, V, S = DirStore, C = Bincode>(pub(crate) Iter<'a, K, V, S, C>);
 
-impl<'a, K, V> Iterator for Keys<'a, K, V>
+impl<'a, K, V, S: NodeStore, C: Codec> Iterator for Keys<'a, K, V, S, C>
 where
     for<'de> K: Deserialize<'de> + 'a,
     for<'de> V: Deserialize<'de> + 'a,
@@ -246,13 +393,23 @@ where
     type Item = Result<&'a K, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(|res| res.and_then(|(key, _)| Ok(key)))
+        self.0.next().map(|res| res.map(|(key, _)| key))
     }
 }
 
-pub struct Values<'a, K, V>(pub(crate) Iter<'a, K, V>);
+impl<'a, K, V, S: NodeStore, C: Codec> DoubleEndedIterator for Keys<'a, K, V, S, C>
+where
+    for<'de> K: Deserialize<'de> + 'a,
+    for<'de> V: Deserialize<'de> + 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|res| res.map(|(key, _)| key))
+    }
+}
 
-impl<'a, K, V> Iterator for Values<'a, K, V>
+pub struct Values<'a, K, V, S = DirStore, C = Bincode>(pub(crate) Iter<'a, K, V, S, C>);
+
+impl<'a, K, V, S: NodeStore, C: Codec> Iterator for Values<'a, K, V, S, C>
 where
     for<'de> K: Deserialize<'de> + 'a,
     for<'de> V: Deserialize<'de> + 'a,
@@ -260,24 +417,40 @@ where
     type Item = Result<&'a V, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0
-            .next()
-            .map(|res| res.and_then(|(_, value)| Ok(value)))
+        self.0.next().map(|res| res.map(|(_, value)| value))
+    }
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> DoubleEndedIterator for Values<'a, K, V, S, C>
+where
+    for<'de> K: Deserialize<'de> + 'a,
+    for<'de> V: Deserialize<'de> + 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|res| res.map(|(_, value)| value))
     }
 }
 
-pub struct ValuesMut<'a, K, V>(pub(crate) IterMut<'a, K, V>);
+pub struct ValuesMut<'a, K, V, S = DirStore, C = Bincode>(pub(crate) IterMut<'a, K, V, S, C>);
 
-impl<'a, K, V> Iterator for ValuesMut<'a, K, V>
+impl<'a, K, V, S: NodeStore, C: Codec> Iterator for ValuesMut<'a, K, V, S, C>
 where
     for<'de> K: Deserialize<'de> + 'a,
     for<'de> V: Deserialize<'de> + 'a,
 {
-    type Item = Result<ValueMutationGuard<'a, K, V>, Error>;
+    type Item = Result<ValueMutationGuard<'a, K, V, S, C>, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0
-            .next()
-            .map(|res| res.and_then(|(_, value)| Ok(value)))
+        self.0.next().map(|res| res.map(|(_, value)| value))
+    }
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> DoubleEndedIterator for ValuesMut<'a, K, V, S, C>
+where
+    for<'de> K: Deserialize<'de> + 'a,
+    for<'de> V: Deserialize<'de> + 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|res| res.map(|(_, value)| value))
     }
 }
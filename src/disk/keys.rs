@@ -0,0 +1,149 @@
+use super::{
+    codec::Codec,
+    encryption::{self, Key},
+    error::Error,
+    format::NODE_KEY_VERSION,
+    node::Node,
+    pager::Pager,
+    persist::{ROOT_KEY_METADATA, ROOT_METADATA},
+    store::NodeStore,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+// Per-node keys for encrypted trees.
+//
+// Each node is encrypted under a key of its own, which is kept in its parent
+// next to the child's entry, and the root's key is kept in the metadata under
+// the tree's key. A node gets a fresh key whenever it's written, and its
+// parent is rewritten along with it, so rewriting a node or removing it from
+// the tree leaves nothing that can decrypt its old versions once the tree's
+// key is rotated.
+//
+// Keys are tracked here by node rather than in the parents themselves, so
+// they follow nodes around as they're split, merged and moved between parents,
+// and are written out with whichever parent the node ends up in.
+impl<K, V, S: NodeStore, C: Codec> Pager<K, V, S, C> {
+    pub fn has_node_keys(&self) -> bool {
+        self.cipher.is_some() && self.version >= NODE_KEY_VERSION
+    }
+
+    // The key a node was last written with, or the one it's about to be
+    // written with if it hasn't been yet.
+    pub fn node_key(&self, uuid: Uuid) -> Key {
+        *self
+            .node_keys
+            .borrow_mut()
+            .entry(uuid)
+            .or_insert_with(encryption::random_key)
+    }
+
+    pub fn rotate_node_key(&self, uuid: Uuid) -> Key {
+        let key = encryption::random_key();
+        self.node_keys.borrow_mut().insert(uuid, key);
+        key
+    }
+
+    pub fn forget_node_key(&self, uuid: Uuid) {
+        self.node_keys.borrow_mut().remove(&uuid);
+    }
+
+    // Records the keys of a node's children as read from the node. Keys
+    // already known are newer than anything in the store, so they're kept.
+    pub fn learn_node_keys(&self, node: &Node<K, V>, child_keys: Vec<Key>) -> Result<(), Error> {
+        let children = match node {
+            Node::Internal(node) => &node.children[..],
+            Node::Leaf(_) => &[],
+        };
+
+        if children.len() != child_keys.len() {
+            return Err(Error::Corruption { node: node.uuid() });
+        }
+
+        let mut node_keys = self.node_keys.borrow_mut();
+        for (child, key) in children.iter().zip(child_keys) {
+            node_keys
+                .entry(unsafe { (*child.as_ptr()).uuid() })
+                .or_insert(key);
+        }
+
+        Ok(())
+    }
+
+    pub fn learn_root_key(&self, root: Uuid, key: Key) {
+        self.node_keys.borrow_mut().entry(root).or_insert(key);
+    }
+
+    // Finds the key of a node about to be read. Nodes are usually reached
+    // through their parents, which hold their keys, but sibling leaves can be
+    // reached directly, before their parents ever were. Their keys are found
+    // by walking the persisted tree from the root the first time that happens.
+    pub fn find_node_key(&self, uuid: Uuid) -> Result<Key, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        if let Some(key) = self.node_keys.borrow().get(&uuid) {
+            return Ok(*key);
+        }
+
+        if !self.keys_discovered.replace(true) {
+            if let Err(err) = self.discover_node_keys() {
+                self.keys_discovered.set(false);
+                return Err(err);
+            }
+        }
+
+        self.node_keys
+            .borrow()
+            .get(&uuid)
+            .copied()
+            .ok_or(Error::Corruption { node: uuid })
+    }
+
+    fn discover_node_keys(&self) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let root: Option<Uuid> = self.load_metadata(ROOT_METADATA)?;
+        let root_key: Option<Key> = self.load_metadata(ROOT_KEY_METADATA)?;
+
+        if let (Some(root), Some(root_key)) = (root, root_key) {
+            self.learn_root_key(root, root_key);
+            self.discover_node_keys_recursive(root)?;
+        }
+
+        Ok(())
+    }
+
+    // Returns whether the node is internal.
+    fn discover_node_keys_recursive(&self, uuid: Uuid) -> Result<bool, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let node = self.read(uuid)?;
+
+        let children = match &node {
+            Node::Internal(node) => node
+                .children
+                .iter()
+                .map(|child| unsafe { (*child.as_ptr()).uuid() })
+                .collect::<Vec<_>>(),
+            Node::Leaf(_) => Vec::new(),
+        };
+        let is_internal = matches!(node, Node::Internal(_));
+        node.free_links();
+
+        // Every leaf is at the same depth, so if the first child is a leaf,
+        // the rest are too and hold no keys.
+        for child in children {
+            if !self.discover_node_keys_recursive(child)? {
+                break;
+            }
+        }
+
+        Ok(is_internal)
+    }
+}
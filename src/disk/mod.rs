@@ -1,49 +1,172 @@
+mod bulk;
+mod checksum;
+mod codec;
+mod compression;
+mod directory;
+mod encryption;
+mod entry;
 pub mod error;
+mod extract;
+mod format;
 mod get;
 mod guard;
 mod insert;
 mod iter;
+mod keys;
 mod node;
+mod page_file;
+mod pager;
 mod persist;
+mod range;
 mod remove;
+mod set;
+mod snapshot;
+mod split;
+mod stats;
+mod store;
+mod transaction;
+mod unversioned;
+mod wal;
+
+pub use self::{
+    codec::{Bincode, Codec, Ron},
+    compression::Compression,
+    directory::DirStore,
+    encryption::Key,
+    entry::{Entry, OccupiedEntry, VacantEntry},
+    format::{type_fingerprint, LoadOptions, Migration},
+    page_file::PageFileStore,
+    set::BPTreeDiskSet,
+    snapshot::Snapshot,
+    stats::Stats,
+    store::{MemStore, NodeStore},
+    transaction::Transaction,
+    unversioned::migrate_unversioned,
+};
 
 use self::{
+    encryption::Cipher,
     error::Error,
-    node::{Link, Node, NodeRef},
+    format::FORMAT_VERSION,
+    node::{Link, Node},
+    pager::Pager,
 };
 use serde::Deserialize;
 use std::{
     borrow::Borrow,
+    cell::{Ref, RefCell},
     fmt::{self, Debug},
-    path::{Path, PathBuf},
+    path::Path,
 };
 
 const DEFAULT_ORDER: usize = 3;
 
-pub struct BPTree<K, V> {
-    path: PathBuf,
+pub struct BPTree<K, V, S = DirStore, C = Bincode> {
+    pager: Pager<K, V, S, C>,
     root: Option<Link<K, V>>,
     root_is_dirty: bool,
     order: usize,
     order_is_dirty: bool,
     len: usize,
     len_is_dirty: bool,
+    schema: Option<String>,
+    format_is_dirty: bool,
+    has_persisted: bool,
 }
 
-impl<K, V> BPTree<K, V> {
+impl<K, V> BPTree<K, V, DirStore> {
     pub fn new(path: impl AsRef<Path>) -> Self {
         Self::with_order(path, DEFAULT_ORDER)
     }
 
     pub fn with_order(path: impl AsRef<Path>, order: usize) -> Self {
+        Self::with_store(DirStore::new(path), order)
+    }
+}
+
+impl<K, V, S: NodeStore, C: Codec> BPTree<K, V, S, C> {
+    pub fn with_store(store: S, order: usize) -> Self
+    where
+        C: Default,
+    {
+        Self::with_codec(store, order, C::default())
+    }
+
+    pub fn with_codec(store: S, order: usize, codec: C) -> Self {
         Self {
-            path: path.as_ref().into(),
+            pager: Pager::new(store, codec),
             root: None,
             root_is_dirty: true,
             order,
             order_is_dirty: true,
             len: 0,
             len_is_dirty: true,
+            schema: None,
+            format_is_dirty: true,
+            has_persisted: false,
+        }
+    }
+
+    pub fn store(&self) -> Ref<'_, S> {
+        RefCell::borrow(&self.pager.store)
+    }
+
+    pub fn schema(&self) -> Option<&str> {
+        self.schema.as_deref()
+    }
+
+    // Tags the tree with a schema that loads can be checked against, recorded
+    // the next time the tree is persisted.
+    pub fn set_schema(&mut self, schema: impl Into<String>) {
+        self.schema = Some(schema.into());
+        self.format_is_dirty = true;
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.pager.compression
+    }
+
+    // Compresses the nodes written from now on. Trees laid out before node
+    // files could be compressed can't be, short of a migration.
+    pub fn set_compression(&mut self, compression: Compression) -> Result<(), Error> {
+        if !self.pager.is_compressible() {
+            return Err(Error::UnsupportedVersion {
+                found: self.pager.version,
+                supported: FORMAT_VERSION,
+            });
+        }
+
+        self.pager.compression = compression;
+        self.format_is_dirty = true;
+
+        Ok(())
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.pager.cipher.is_some()
+    }
+
+    // Encrypts the tree under `key`, which must then be passed to load it.
+    // Only trees that have never been persisted can be encrypted, since the
+    // nodes and metadata they already persisted would stay in the clear.
+    pub fn set_key(&mut self, key: Key) -> Result<(), Error> {
+        if self.has_persisted {
+            return Err(Error::AlreadyPersisted);
+        }
+
+        self.pager.cipher = Some(Cipher::new(&key));
+        self.format_is_dirty = true;
+
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            len: self.len,
+            order: self.order,
+            cached_nodes: self.pager.loaded(),
+            encoded_bytes: self.pager.encoded_bytes(),
+            stored_bytes: self.pager.stored_bytes(),
         }
     }
 
@@ -55,6 +178,30 @@ impl<K, V> BPTree<K, V> {
         self.len == 0
     }
 
+    pub fn cached_nodes(&self) -> usize {
+        self.pager.loaded()
+    }
+
+    pub fn cache_capacity(&self) -> Option<usize> {
+        self.pager.capacity()
+    }
+
+    // Bounds the number of nodes kept in memory, or lifts the bound with
+    // `None`. Clean nodes past the bound are evicted in least-recently-used
+    // order whenever the tree is mutated or persisted, and dirty nodes stay
+    // loaded until they are persisted.
+    pub fn set_cache_capacity(&mut self, capacity: Option<usize>) {
+        self.pager.set_capacity(capacity);
+        self.pager.evict();
+    }
+
+    // Evicts clean nodes until the cache is within its capacity. Reads only
+    // borrow the tree immutably and so never evict, which makes this useful
+    // for read-heavy workloads.
+    pub fn shrink_cache(&mut self) {
+        self.pager.evict();
+    }
+
     pub fn contains_key<Q>(&self, key: &Q) -> Result<bool, Error>
     where
         for<'de> K: Deserialize<'de> + Borrow<Q>,
@@ -82,7 +229,7 @@ impl<K, V> BPTree<K, V> {
                 for child in &node.children {
                     unsafe {
                         self.pretty_print_recursive(
-                            (*child.as_ptr()).access(&self.path)?,
+                            (*child.as_ptr()).access(&self.pager)?,
                             depth + 1,
                         )?;
                     }
@@ -110,39 +257,14 @@ impl<K, V> BPTree<K, V> {
     {
         unsafe {
             if let Some(root) = self.root {
-                self.pretty_print_recursive((*root.as_ptr()).access(&self.path)?, 0)?;
+                self.pretty_print_recursive((*root.as_ptr()).access(&self.pager)?, 0)?;
             }
             Ok(())
         }
     }
 }
 
-impl<K, V> Drop for BPTree<K, V> {
-    fn drop(&mut self) {
-        fn recursive_drop<K, V>(node: Link<K, V>) {
-            unsafe {
-                match &(*node.as_ptr()) {
-                    NodeRef::Loaded(node) => {
-                        if let Node::Internal(node) = node {
-                            for child in &node.children {
-                                recursive_drop(*child);
-                            }
-                        }
-                    }
-                    NodeRef::Unloaded(_) => {}
-                }
-
-                node.free();
-            }
-        }
-
-        if let Some(root) = self.root {
-            recursive_drop(root);
-        }
-    }
-}
-
-impl<K, V> fmt::Debug for BPTree<K, V>
+impl<K, V, S: NodeStore, C: Codec> fmt::Debug for BPTree<K, V, S, C>
 where
     for<'de> K: Deserialize<'de> + Debug,
     for<'de> V: Deserialize<'de> + Debug,
@@ -161,8 +283,45 @@ where
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use std::fs;
+    use super::{
+        format::{Format, KEYS_ONLY_VERSION},
+        range::Range,
+        *,
+    };
+    use std::{
+        collections::{BTreeMap, HashMap},
+        fs, io,
+        ops::Bound,
+    };
+
+    // Counts the nodes reachable from the root.
+    fn count_nodes<K, V, S: NodeStore, C: Codec>(tree: &BPTree<K, V, S, C>) -> usize
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        fn count<K, V, S: NodeStore, C: Codec>(
+            pager: &Pager<K, V, S, C>,
+            cursor: Link<K, V>,
+        ) -> usize
+        where
+            for<'de> K: Deserialize<'de>,
+            for<'de> V: Deserialize<'de>,
+        {
+            match unsafe { (*cursor.as_ptr()).access(pager).unwrap() } {
+                Node::Internal(node) => {
+                    1 + node
+                        .children
+                        .iter()
+                        .map(|child| count(pager, *child))
+                        .sum::<usize>()
+                }
+                Node::Leaf(_) => 1,
+            }
+        }
+
+        tree.root.map_or(0, |root| count(&tree.pager, root))
+    }
 
     #[test]
     fn it_works() -> Result<(), Error> {
@@ -238,4 +397,1698 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn range() -> Result<(), Error> {
+        let _ = fs::remove_dir_all("/tmp/bptree-range");
+
+        let mut tree: BPTree<usize, usize> = BPTree::new("/tmp/bptree-range");
+
+        for n in (0..100).step_by(2) {
+            tree.insert(n, n)?;
+        }
+
+        for res in tree.range_mut(40..50) {
+            *res?.1 += 1;
+        }
+
+        tree.persist()?;
+
+        let tree: BPTree<usize, usize> = BPTree::load("/tmp/bptree-range")?;
+
+        for n in (0..100).step_by(2) {
+            let expected = if (40..50).contains(&n) { n + 1 } else { n };
+            assert_eq!(tree.get(&n)?, Some(&expected));
+        }
+
+        let keys = |range: Range<usize, usize>| -> Result<Vec<usize>, Error> {
+            range.map(|res| res.map(|(key, _)| *key)).collect()
+        };
+
+        assert_eq!(keys(tree.range(10..20))?, vec![10, 12, 14, 16, 18]);
+        assert_eq!(keys(tree.range(9..=19))?, vec![10, 12, 14, 16, 18]);
+        assert_eq!(keys(tree.range(..5))?, vec![0, 2, 4]);
+        assert_eq!(keys(tree.range(95..))?, vec![96, 98]);
+        assert_eq!(keys(tree.range(..))?.len(), 50);
+        assert_eq!(keys(tree.range(11..12))?, vec![]);
+        assert_eq!(keys(tree.range(100..))?, vec![]);
+        assert_eq!(
+            keys(tree.range((Bound::Excluded(10), Bound::Excluded(16))))?,
+            vec![12, 14]
+        );
+
+        let _ = fs::remove_dir_all("/tmp/bptree-range");
+
+        Ok(())
+    }
+
+    #[test]
+    fn double_ended() -> Result<(), Error> {
+        let _ = fs::remove_dir_all("/tmp/bptree-double-ended");
+
+        let mut tree: BPTree<usize, usize> = BPTree::new("/tmp/bptree-double-ended");
+        let mut reference = BTreeMap::new();
+
+        for n in 0..200 {
+            tree.insert(n, n)?;
+            reference.insert(n, n);
+        }
+
+        tree.persist()?;
+
+        // Split and merge leaves that were loaded back from disk.
+        let mut tree: BPTree<usize, usize> = BPTree::load("/tmp/bptree-double-ended")?;
+
+        for n in (0..200).step_by(3) {
+            tree.remove(&n)?;
+            reference.remove(&n);
+        }
+
+        for n in 200..250 {
+            tree.insert(n, n)?;
+            reference.insert(n, n);
+        }
+
+        for res in tree.values_mut().rev().take(10) {
+            *res? += 1;
+        }
+
+        for value in reference.values_mut().rev().take(10) {
+            *value += 1;
+        }
+
+        tree.persist()?;
+
+        let tree: BPTree<usize, usize> = BPTree::load("/tmp/bptree-double-ended")?;
+
+        let entries = tree.iter().collect::<Result<Vec<_>, _>>()?;
+        assert!(entries.into_iter().eq(reference.iter()));
+
+        let entries = tree.iter().rev().collect::<Result<Vec<_>, _>>()?;
+        assert!(entries.into_iter().eq(reference.iter().rev()));
+
+        let entries = tree.range(50..150).rev().collect::<Result<Vec<_>, _>>()?;
+        assert!(entries.into_iter().eq(reference.range(50..150).rev()));
+
+        // Alternate ends until the two meet.
+        let mut range = tree.range(20..=40);
+        let mut reference_range = reference.range(20..=40);
+        loop {
+            let next = (range.next().transpose()?, reference_range.next());
+            assert_eq!(next.0, next.1);
+            let next_back = (range.next_back().transpose()?, reference_range.next_back());
+            assert_eq!(next_back.0, next_back.1);
+            if next.0.is_none() || next_back.0.is_none() {
+                break;
+            }
+        }
+
+        let _ = fs::remove_dir_all("/tmp/bptree-double-ended");
+
+        Ok(())
+    }
+
+    #[test]
+    fn cache_capacity() -> Result<(), Error> {
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, usize, _> = BPTree::with_store(store.clone(), 4);
+        tree.set_cache_capacity(Some(8));
+
+        for n in 0..500 {
+            tree.insert(n, n)?;
+        }
+
+        // Nothing has been persisted yet, so every node is dirty and pinned.
+        assert!(tree.cached_nodes() > 8);
+
+        tree.persist()?;
+        assert!(tree.cached_nodes() <= 8);
+
+        for n in (0..500).step_by(7) {
+            assert_eq!(tree.get(&n)?, Some(&n));
+        }
+
+        tree.shrink_cache();
+        assert!(tree.cached_nodes() <= 8);
+
+        for n in (0..500).step_by(2) {
+            assert_eq!(tree.remove(&n)?, Some(n));
+        }
+
+        for res in tree.values_mut() {
+            *res? += 1;
+        }
+
+        tree.persist()?;
+        assert!(tree.cached_nodes() <= 8);
+
+        let entries = tree
+            .iter()
+            .map(|res| res.map(|(key, value)| (*key, *value)))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert!(entries
+            .into_iter()
+            .eq((1..500).step_by(2).map(|n| (n, n + 1))));
+
+        let tree: BPTree<usize, usize, _> = BPTree::load_from_store(store)?;
+
+        for n in 0..500 {
+            let expected = (n % 2 == 1).then_some(n + 1);
+            assert_eq!(tree.get(&n)?.copied(), expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn cache_capacity_leaf_links() -> Result<(), Error> {
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, usize, _> = BPTree::with_store(store.clone(), 3);
+        for n in (0..400).step_by(2) {
+            tree.insert(n, n)?;
+        }
+        tree.persist()?;
+        tree.set_cache_capacity(Some(2));
+
+        // Splitting a leaf changes the leaf after it, which is reached through
+        // the leaf links and so may have been loaded without its parent.
+        for n in (1..400).step_by(2) {
+            tree.insert(n, n)?;
+            tree.persist()?;
+
+            let loaded: BPTree<usize, usize, _> = BPTree::load_from_store(store.clone())?;
+            let keys = loaded.keys().rev().collect::<Result<Vec<_>, _>>()?;
+            assert!(keys
+                .into_iter()
+                .copied()
+                .eq((0..400).rev().filter(|&key| key % 2 == 0 || key <= n)));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn page_file() -> Result<(), Error> {
+        let _ = fs::remove_file("/tmp/bptree-page-file");
+
+        let mut tree: BPTree<usize, String, _> =
+            BPTree::with_store(PageFileStore::create("/tmp/bptree-page-file"), 4);
+        let mut reference = BTreeMap::new();
+
+        for n in 0..1000 {
+            tree.insert(n, n.to_string())?;
+            reference.insert(n, n.to_string());
+        }
+
+        tree.persist()?;
+        assert!(fs::metadata("/tmp/bptree-page-file")?.is_file());
+
+        let mut tree: BPTree<usize, String, _> =
+            BPTree::load_from_store(PageFileStore::open("/tmp/bptree-page-file")?)?;
+        tree.set_cache_capacity(Some(16));
+
+        // Grow some nodes past their pages and free others entirely.
+        for n in (0..1000).step_by(3) {
+            tree.remove(&n)?;
+            reference.remove(&n);
+        }
+
+        for n in (1..1000).step_by(3) {
+            let value = n.to_string().repeat(10);
+            tree.insert(n, value.clone())?;
+            reference.insert(n, value);
+        }
+
+        tree.persist()?;
+
+        let tree: BPTree<usize, String, _> =
+            BPTree::load_from_store(PageFileStore::open("/tmp/bptree-page-file")?)?;
+        assert_eq!(tree.len(), reference.len());
+
+        let entries = tree.iter().collect::<Result<Vec<_>, _>>()?;
+        assert!(entries.into_iter().eq(reference.iter()));
+
+        fs::write("/tmp/bptree-page-file-garbage", [0xff; 8192])?;
+        assert!(matches!(
+            PageFileStore::open("/tmp/bptree-page-file-garbage"),
+            Err(Error::BadBPTree)
+        ));
+
+        let _ = fs::remove_file("/tmp/bptree-page-file-missing");
+        assert!(matches!(
+            PageFileStore::open("/tmp/bptree-page-file-missing"),
+            Err(Error::IO(err)) if err.kind() == io::ErrorKind::NotFound
+        ));
+
+        let _ = fs::remove_file("/tmp/bptree-page-file");
+        let _ = fs::remove_file("/tmp/bptree-page-file-garbage");
+
+        Ok(())
+    }
+
+    #[test]
+    fn page_file_failed_sync() -> Result<(), Error> {
+        let _ = fs::remove_file("/tmp/bptree-page-file-failed-sync");
+
+        let mut store = PageFileStore::create("/tmp/bptree-page-file-failed-sync");
+        let committed = uuid::Uuid::new_v4();
+        store.write(committed, &[1; 100])?;
+        store.sync()?;
+
+        // The header can't hold this, so the commit fails and the first one is
+        // still the committed tree.
+        store.delete(committed)?;
+        store.put_metadata("oversized", &[0; 8192])?;
+        assert!(store.sync().is_err());
+
+        // This would reuse the deleted node's page if the failed sync had
+        // freed it.
+        store.write(uuid::Uuid::new_v4(), &[2; 100])?;
+        drop(store);
+
+        let store = PageFileStore::open("/tmp/bptree-page-file-failed-sync")?;
+        assert_eq!(store.read(committed)?, vec![1; 100]);
+
+        let _ = fs::remove_file("/tmp/bptree-page-file-failed-sync");
+
+        Ok(())
+    }
+
+    #[test]
+    fn persist_key() -> Result<(), Error> {
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, usize, _> = BPTree::with_store(store.clone(), 3);
+        for n in (0..40).step_by(2) {
+            tree.insert(n, n)?;
+        }
+        tree.persist()?;
+
+        // Only the path to 20 has changed, so only it is written.
+        tree.insert(20, 0)?;
+        tree.persist_key(&20)?;
+        drop(tree);
+
+        let mut tree: BPTree<usize, usize, _> = BPTree::load_from_store(store.clone())?;
+        assert_eq!(tree.get(&20)?, Some(&0));
+
+        // These split leaves off the path to 5, which has to be written too.
+        for n in [5, 7, 9] {
+            tree.insert(n, n)?;
+        }
+        tree.persist_key(&5)?;
+
+        let snapshot = tree.snapshot()?;
+        assert_eq!(snapshot.len(), 23);
+        assert_eq!(snapshot.iter().count(), 23);
+        drop(snapshot);
+        drop(tree);
+
+        let tree: BPTree<usize, usize, _> = BPTree::load_from_store(store)?;
+        assert_eq!(tree.len(), 23);
+        let keys = tree
+            .iter()
+            .map(|pair| pair.map(|(key, _)| *key))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(keys.len(), 23);
+        for n in [5, 6, 7, 9] {
+            assert_eq!(tree.get(&n)?, Some(&n));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn uncommitted_changes() -> Result<(), Error> {
+        let _ = fs::remove_dir_all("/tmp/bptree-uncommitted");
+
+        let mut tree: BPTree<usize, usize> = BPTree::with_order("/tmp/bptree-uncommitted", 4);
+
+        for n in 0..100 {
+            tree.insert(n, n)?;
+        }
+
+        tree.persist()?;
+
+        // Removing merges and frees nodes, but the persisted tree still needs
+        // them until the next persist.
+        for n in 0..100 {
+            tree.remove(&n)?;
+        }
+
+        drop(tree);
+
+        // Changes that were staged but never committed are thrown away.
+        let mut store = DirStore::new("/tmp/bptree-uncommitted");
+        store.write(uuid::Uuid::new_v4(), b"garbage")?;
+        store.put_metadata("len", b"garbage")?;
+        drop(store);
+
+        let tree: BPTree<usize, usize> = BPTree::load("/tmp/bptree-uncommitted")?;
+        assert_eq!(tree.len(), 100);
+
+        let keys = tree.keys().collect::<Result<Vec<_>, _>>()?;
+        assert!(keys.into_iter().copied().eq(0..100));
+
+        let staged = fs::read_dir("/tmp/bptree-uncommitted")?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "staged"))
+            .count();
+        assert_eq!(staged, 0);
+
+        let _ = fs::remove_dir_all("/tmp/bptree-uncommitted");
+
+        Ok(())
+    }
+
+    #[test]
+    fn torn_page_file_header() -> Result<(), Error> {
+        let _ = fs::remove_file("/tmp/bptree-torn-header");
+
+        let mut tree: BPTree<usize, usize, _> =
+            BPTree::with_store(PageFileStore::create("/tmp/bptree-torn-header"), 4);
+
+        for n in 0..100 {
+            tree.insert(n, n)?;
+        }
+
+        tree.persist()?;
+
+        for n in 0..50 {
+            tree.remove(&n)?;
+        }
+
+        for n in 100..120 {
+            tree.insert(n, n)?;
+        }
+
+        tree.persist()?;
+        drop(tree);
+
+        // The second commit went to the first header slot. Tearing it leaves
+        // the file as it was after the first commit.
+        let mut data = fs::read("/tmp/bptree-torn-header")?;
+        data[100..200].fill(0xff);
+        fs::write("/tmp/bptree-torn-header", data)?;
+
+        let tree: BPTree<usize, usize, _> =
+            BPTree::load_from_store(PageFileStore::open("/tmp/bptree-torn-header")?)?;
+        assert_eq!(tree.len(), 100);
+
+        let keys = tree.keys().collect::<Result<Vec<_>, _>>()?;
+        assert!(keys.into_iter().copied().eq(0..100));
+
+        let _ = fs::remove_file("/tmp/bptree-torn-header");
+
+        Ok(())
+    }
+
+    #[test]
+    fn wal() -> Result<(), Error> {
+        let _ = fs::remove_dir_all("/tmp/bptree-wal");
+
+        let mut tree: BPTree<usize, usize> = BPTree::with_order("/tmp/bptree-wal", 4);
+        let mut reference = BTreeMap::new();
+        tree.enable_wal()?;
+
+        for n in 0..100 {
+            tree.insert(n, n)?;
+            reference.insert(n, n);
+        }
+
+        tree.persist()?;
+        assert_eq!(fs::metadata("/tmp/bptree-wal/wal")?.len(), 0);
+
+        for n in 100..150 {
+            tree.insert(n, n)?;
+            reference.insert(n, n);
+        }
+
+        for n in 0..20 {
+            tree.remove(&n)?;
+            reference.remove(&n);
+        }
+
+        *tree.get_mut(&50)?.unwrap() += 1000;
+        *reference.get_mut(&50).unwrap() += 1000;
+
+        for res in tree.range_mut(120..130) {
+            let (key, mut value) = res?;
+            *value = key * 2;
+            reference.insert(*key, key * 2);
+        }
+
+        // Crash without persisting, part way through appending a record.
+        tree.sync_wal()?;
+        drop(tree);
+
+        let mut wal = fs::OpenOptions::new()
+            .append(true)
+            .open("/tmp/bptree-wal/wal")?;
+        std::io::Write::write_all(&mut wal, &[42, 0, 0, 0, 1, 2])?;
+        drop(wal);
+
+        let mut tree: BPTree<usize, usize> = BPTree::load("/tmp/bptree-wal")?;
+        assert_eq!(tree.len(), reference.len());
+
+        let entries = tree.iter().collect::<Result<Vec<_>, _>>()?;
+        assert!(entries.into_iter().eq(reference.iter()));
+
+        tree.persist()?;
+        assert_eq!(fs::metadata("/tmp/bptree-wal/wal")?.len(), 0);
+
+        let tree: BPTree<usize, usize> = BPTree::load("/tmp/bptree-wal")?;
+        let entries = tree.iter().collect::<Result<Vec<_>, _>>()?;
+        assert!(entries.into_iter().eq(reference.iter()));
+
+        let _ = fs::remove_dir_all("/tmp/bptree-wal");
+
+        Ok(())
+    }
+
+    #[test]
+    fn corruption() -> Result<(), Error> {
+        let _ = fs::remove_dir_all("/tmp/bptree-corruption");
+
+        let mut tree: BPTree<usize, usize> = BPTree::with_order("/tmp/bptree-corruption", 4);
+
+        for n in 0..100 {
+            tree.insert(n, n)?;
+        }
+
+        tree.persist()?;
+        drop(tree);
+
+        let node = fs::read_dir("/tmp/bptree-corruption")?
+            .filter_map(Result::ok)
+            .find_map(|entry| entry.file_name().to_str()?.parse::<uuid::Uuid>().ok())
+            .unwrap();
+
+        let path = format!("/tmp/bptree-corruption/{node}");
+        let mut data = fs::read(&path)?;
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        fs::write(&path, data)?;
+
+        let tree: BPTree<usize, usize> = BPTree::load("/tmp/bptree-corruption")?;
+        // Every node is on the path to some key.
+        assert!((0..100).any(|n| {
+            matches!(tree.get(&n), Err(Error::Corruption { node: corrupted }) if corrupted == node)
+        }));
+
+        fs::write("/tmp/bptree-corruption/len", [0, 1])?;
+        assert!(matches!(
+            BPTree::<usize, usize>::load("/tmp/bptree-corruption"),
+            Err(Error::MetadataCorruption { name }) if name == "len"
+        ));
+
+        let _ = fs::remove_dir_all("/tmp/bptree-corruption");
+
+        Ok(())
+    }
+
+    #[test]
+    fn unversioned_layout() -> Result<(), Error> {
+        // Nodes as they were written before the format was recorded.
+        #[derive(serde::Serialize)]
+        enum Unversioned {
+            Internal(uuid::Uuid, Vec<usize>, Vec<uuid::Uuid>, Option<uuid::Uuid>),
+            Leaf(
+                uuid::Uuid,
+                Vec<usize>,
+                Vec<String>,
+                Option<uuid::Uuid>,
+                Option<uuid::Uuid>,
+            ),
+        }
+
+        let _ = fs::remove_dir_all("/tmp/bptree-unversioned-layout");
+        fs::create_dir_all("/tmp/bptree-unversioned-layout")?;
+
+        let root = uuid::Uuid::new_v4();
+        let leaves = [
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+            uuid::Uuid::new_v4(),
+        ];
+        let write = |uuid: uuid::Uuid, node: Unversioned| {
+            fs::write(
+                format!("/tmp/bptree-unversioned-layout/{uuid}"),
+                bincode::serialize(&node).unwrap(),
+            )
+        };
+        write(
+            root,
+            Unversioned::Internal(root, vec![4, 8], leaves.to_vec(), None),
+        )?;
+        for (index, leaf) in leaves.into_iter().enumerate() {
+            let keys = (index * 4..index * 4 + 4).collect::<Vec<_>>();
+            let values = keys.iter().map(|key| key.to_string()).collect();
+            let next_leaf = leaves.get(index + 1).copied();
+            write(
+                leaf,
+                Unversioned::Leaf(leaf, keys, values, Some(root), next_leaf),
+            )?;
+        }
+        for (name, data) in [
+            ("root", bincode::serialize(&Some(root)).unwrap()),
+            ("order", bincode::serialize(&4usize).unwrap()),
+            ("len", bincode::serialize(&12usize).unwrap()),
+        ] {
+            fs::write(format!("/tmp/bptree-unversioned-layout/{name}"), data)?;
+        }
+
+        // The migration can only write plaintext bincode, so loads expecting
+        // anything else fail before the old tree is touched.
+        assert!(matches!(
+            BPTree::<usize, String>::load_with(
+                "/tmp/bptree-unversioned-layout",
+                LoadOptions::new().key([7; 32])
+            ),
+            Err(Error::EncryptionMismatch {
+                expected: true,
+                found: false
+            })
+        ));
+        assert!(matches!(
+            BPTree::<usize, String, DirStore, Ron>::load_with_codec(
+                "/tmp/bptree-unversioned-layout",
+                Ron::default(),
+                LoadOptions::new().migration(migrate_unversioned::<usize, String>)
+            ),
+            Err(Error::CodecMismatch { .. })
+        ));
+        assert!(Path::new(&format!("/tmp/bptree-unversioned-layout/{root}")).exists());
+
+        let mut tree: BPTree<usize, String> = BPTree::load("/tmp/bptree-unversioned-layout")?;
+        assert_eq!(tree.len(), 12);
+        let entries = tree.iter().collect::<Result<Vec<_>, _>>()?;
+        assert!(entries
+            .into_iter()
+            .map(|(key, value)| (*key, value.clone()))
+            .eq((0..12).map(|n| (n, n.to_string()))));
+
+        // The old nodes went in the same commit as the new tree.
+        for uuid in [root].into_iter().chain(leaves) {
+            assert!(!Path::new(&format!("/tmp/bptree-unversioned-layout/{uuid}")).exists());
+        }
+
+        tree.insert(12, "12".into())?;
+        tree.persist()?;
+        let tree: BPTree<usize, String> = BPTree::load("/tmp/bptree-unversioned-layout")?;
+        assert_eq!(tree.get(&12)?.map(String::as_str), Some("12"));
+        assert_eq!(tree.iter().rev().count(), 13);
+
+        let _ = fs::remove_dir_all("/tmp/bptree-unversioned-layout");
+
+        Ok(())
+    }
+
+    #[test]
+    fn mem_store() -> Result<(), Error> {
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, usize, _> = BPTree::with_store(store.clone(), 4);
+
+        for n in 0..100 {
+            tree.insert(n, n)?;
+        }
+
+        tree.persist()?;
+        let nodes = store.nodes();
+        assert!(nodes > 1);
+
+        for n in 0..50 {
+            tree.remove(&n)?;
+        }
+
+        tree.persist()?;
+        assert!(store.nodes() < nodes);
+
+        let tree: BPTree<usize, usize, _> = BPTree::load_from_store(store)?;
+        assert_eq!(tree.len(), 50);
+
+        let keys = tree.keys().collect::<Result<Vec<_>, _>>()?;
+        assert!(keys.into_iter().copied().eq(50..100));
+
+        Ok(())
+    }
+
+    #[test]
+    fn format() -> Result<(), Error> {
+        let mut store = MemStore::new();
+        let mut tree: BPTree<usize, usize, _> = BPTree::with_store(store.clone(), 4);
+        tree.set_schema(type_fingerprint::<usize, usize>());
+        tree.insert(0, 0)?;
+        tree.persist()?;
+
+        let options = LoadOptions::new().schema(type_fingerprint::<usize, usize>());
+        let tree: BPTree<usize, usize, _> = BPTree::load_from_store_with(store.clone(), options)?;
+        assert_eq!(
+            tree.schema(),
+            Some(type_fingerprint::<usize, usize>().as_str())
+        );
+
+        let options = LoadOptions::new().schema(type_fingerprint::<String, usize>());
+        assert!(matches!(
+            BPTree::<String, usize, _>::load_from_store_with(store.clone(), options),
+            Err(Error::SchemaMismatch { found: Some(_), .. })
+        ));
+
+        let format = |magic: &[u8; 8], version: u32| {
+            checksum::seal(
+                &bincode::serialize(&(magic, version, "bincode", None::<String>)).unwrap(),
+            )
+        };
+
+        store.put_metadata("format", &format(b"NOTATREE", 1))?;
+        assert!(matches!(
+            BPTree::<usize, usize, _>::load_from_store(store.clone()),
+            Err(Error::BadMagic)
+        ));
+
+        store.put_metadata("format", &format(b"BPLUSTRE", FORMAT_VERSION + 1))?;
+        assert!(matches!(
+            BPTree::<usize, usize, _>::load_from_store(store.clone()),
+            Err(Error::UnsupportedVersion { found, .. }) if found == FORMAT_VERSION + 1
+        ));
+
+        // A tree from before the format was recorded.
+        store.put_metadata("format", &format(b"BPLUSTRE", 0))?;
+        assert!(matches!(
+            BPTree::<usize, usize, _>::load_from_store(store.clone()),
+            Err(Error::UnsupportedVersion { found: 0, .. })
+        ));
+
+        // A migration has to record the format it leaves the store in.
+        let options = LoadOptions::new().migration(|_, _| Ok(()));
+        assert!(matches!(
+            BPTree::<usize, usize, _>::load_from_store_with(store.clone(), options),
+            Err(Error::UnsupportedVersion { found: 0, .. })
+        ));
+
+        let options = LoadOptions::new().migration(|store, version| {
+            assert_eq!(version, 0);
+            Format::new(FORMAT_VERSION, Bincode::NAME).store(store)
+        });
+        let mut tree: BPTree<usize, usize, _> =
+            BPTree::load_from_store_with(store.clone(), options)?;
+        assert_eq!(tree.get(&0)?, Some(&0));
+        tree.persist()?;
+
+        BPTree::<usize, usize, _>::load_from_store(store)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn codec() -> Result<(), Error> {
+        let mut store = MemStore::new();
+        let mut tree = BPTree::with_codec(store.clone(), 4, Ron::default());
+
+        for n in 0..100 {
+            tree.insert(n, n.to_string())?;
+        }
+
+        tree.persist()?;
+
+        let len = store.get_metadata("len")?.unwrap();
+        assert_eq!(checksum::unseal(&len), Some(&b"100"[..]));
+
+        let tree: BPTree<usize, String, _, Ron> = BPTree::load_from_store(store.clone())?;
+        assert_eq!(tree.get(&42)?, Some(&"42".to_string()));
+
+        assert!(matches!(
+            BPTree::<usize, String, _>::load_from_store(store.clone()),
+            Err(Error::CodecMismatch { .. })
+        ));
+
+        store.put_metadata("len", &checksum::seal(b"a hundred"))?;
+        let err = BPTree::<usize, String, _, Ron>::load_from_store(store).unwrap_err();
+        assert!(matches!(err, Error::Serde(_)));
+        assert!(std::error::Error::source(&err).is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn compression() -> Result<(), Error> {
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, String, _> = BPTree::with_store(store.clone(), 16);
+        tree.set_compression(Compression::Lz4)?;
+
+        for n in 0..1000 {
+            tree.insert(n, format!("value {n}").repeat(10))?;
+        }
+
+        tree.persist()?;
+        assert!(tree.stats().compression_ratio().unwrap() > 2.0);
+
+        let tree: BPTree<usize, String, _> = BPTree::load_from_store(store)?;
+        assert_eq!(tree.compression(), Compression::Lz4);
+        assert_eq!(tree.get(&500)?, Some(&"value 500".repeat(10)));
+        assert!(tree.stats().compression_ratio().unwrap() > 2.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn uncompressed_layout() -> Result<(), Error> {
+        let _ = fs::remove_dir_all("/tmp/bptree-uncompressed-layout");
+
+        let mut tree: BPTree<usize, usize> =
+            BPTree::with_order("/tmp/bptree-uncompressed-layout", 4);
+        for n in 0..100 {
+            tree.insert(n, n)?;
+        }
+        tree.persist()?;
+        drop(tree);
+
+        // Rewrite the tree in the layout from before node files had a
+        // compression flag.
+        for entry in fs::read_dir("/tmp/bptree-uncompressed-layout")? {
+            let path = entry?.path();
+            if path
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .parse::<uuid::Uuid>()
+                .is_ok()
+            {
+                let data = fs::read(&path)?;
+                fs::write(
+                    &path,
+                    checksum::seal(&checksum::unseal(&data).unwrap()[1..]),
+                )?;
+            }
+        }
+
+        let format = (*b"BPLUSTRE", 1u32, "bincode", None::<String>);
+        fs::write(
+            "/tmp/bptree-uncompressed-layout/format",
+            checksum::seal(&bincode::serialize(&format).unwrap()),
+        )?;
+
+        let mut tree: BPTree<usize, usize> = BPTree::load("/tmp/bptree-uncompressed-layout")?;
+        assert!(matches!(
+            tree.set_compression(Compression::Lz4),
+            Err(Error::UnsupportedVersion { found: 1, .. })
+        ));
+
+        for n in 100..200 {
+            tree.insert(n, n)?;
+        }
+        tree.set_schema("numbers");
+        tree.persist()?;
+
+        let tree: BPTree<usize, usize> = BPTree::load("/tmp/bptree-uncompressed-layout")?;
+        assert_eq!(tree.schema(), Some("numbers"));
+        let keys = tree.keys().collect::<Result<Vec<_>, _>>()?;
+        assert!(keys.into_iter().copied().eq(0..200));
+
+        let _ = fs::remove_dir_all("/tmp/bptree-uncompressed-layout");
+
+        Ok(())
+    }
+
+    #[test]
+    fn encryption() -> Result<(), Error> {
+        let _ = fs::remove_dir_all("/tmp/bptree-encryption");
+
+        let key = [7; 32];
+        let mut tree: BPTree<usize, String> = BPTree::with_order("/tmp/bptree-encryption", 4);
+        tree.set_key(key)?;
+        tree.enable_wal()?;
+
+        for n in 0..100 {
+            tree.insert(n, format!("secret {n}"))?;
+        }
+        tree.persist()?;
+
+        tree.insert(100, "secret 100".into())?;
+        tree.sync_wal()?;
+        assert!(matches!(
+            tree.set_key([8; 32]),
+            Err(Error::AlreadyPersisted)
+        ));
+        drop(tree);
+
+        for entry in fs::read_dir("/tmp/bptree-encryption")? {
+            let data = fs::read(entry?.path())?;
+            assert!(!data.windows(6).any(|window| window == b"secret"));
+        }
+
+        assert!(matches!(
+            BPTree::<usize, String>::load("/tmp/bptree-encryption"),
+            Err(Error::EncryptionMismatch {
+                expected: false,
+                found: true
+            })
+        ));
+
+        assert!(matches!(
+            BPTree::<usize, String>::load_with(
+                "/tmp/bptree-encryption",
+                LoadOptions::new().key([8; 32])
+            ),
+            Err(Error::MetadataAuthentication { .. })
+        ));
+
+        let mut tree: BPTree<usize, String> =
+            BPTree::load_with("/tmp/bptree-encryption", LoadOptions::new().key(key))?;
+        assert!(tree.is_encrypted());
+        assert_eq!(tree.len(), 101);
+        assert_eq!(tree.get(&100)?, Some(&"secret 100".to_string()));
+        assert!(matches!(
+            tree.set_key([8; 32]),
+            Err(Error::AlreadyPersisted)
+        ));
+        tree.insert(101, "secret 101".into())?;
+        tree.sync_wal()?;
+        drop(tree);
+
+        // Log records can't be reordered or repeated.
+        let wal = fs::read("/tmp/bptree-encryption/wal")?;
+        let len = u32::from_le_bytes(wal[..4].try_into().unwrap()) as usize;
+        let (first, rest) = wal.split_at(8 + len);
+        for (tampered, record) in [([rest, first].concat(), 0), ([first, first].concat(), 1)] {
+            fs::write("/tmp/bptree-encryption/wal", tampered)?;
+            assert!(matches!(
+                BPTree::<usize, String>::load_with(
+                    "/tmp/bptree-encryption",
+                    LoadOptions::new().key(key)
+                ),
+                Err(Error::LogAuthentication { record: found }) if found == record
+            ));
+        }
+        fs::write("/tmp/bptree-encryption/wal", &wal)?;
+
+        // Replaying the log reads nodes, so empty it before damaging one.
+        let mut tree: BPTree<usize, String> =
+            BPTree::load_with("/tmp/bptree-encryption", LoadOptions::new().key(key))?;
+        assert_eq!(tree.get(&101)?, Some(&"secret 101".to_string()));
+        tree.persist()?;
+        drop(tree);
+
+        // Copy one node over another.
+        let nodes = fs::read_dir("/tmp/bptree-encryption")?
+            .filter_map(Result::ok)
+            .filter_map(|entry| entry.file_name().to_str()?.parse::<uuid::Uuid>().ok())
+            .collect::<Vec<_>>();
+        fs::copy(
+            format!("/tmp/bptree-encryption/{}", nodes[0]),
+            format!("/tmp/bptree-encryption/{}", nodes[1]),
+        )?;
+
+        let tree: BPTree<usize, String> =
+            BPTree::load_with("/tmp/bptree-encryption", LoadOptions::new().key(key))?;
+        assert!((0..102).any(|n| {
+            matches!(tree.get(&n), Err(Error::Authentication { node }) if node == nodes[1])
+        }));
+
+        let _ = fs::remove_dir_all("/tmp/bptree-encryption");
+
+        Ok(())
+    }
+
+    #[test]
+    fn node_keys() -> Result<(), Error> {
+        let _ = fs::remove_dir_all("/tmp/bptree-node-keys");
+
+        let key = [7; 32];
+        let mut tree: BPTree<usize, usize> = BPTree::with_order("/tmp/bptree-node-keys", 4);
+        tree.set_key(key)?;
+
+        for n in 0..200 {
+            tree.insert(n, n)?;
+        }
+        tree.persist()?;
+
+        let nodes = |path| -> Result<HashMap<uuid::Uuid, Vec<u8>>, Error> {
+            let mut nodes = HashMap::new();
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                if let Some(uuid) = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.parse().ok())
+                {
+                    nodes.insert(uuid, fs::read(entry.path())?);
+                }
+            }
+            Ok(nodes)
+        };
+        let old_nodes = nodes("/tmp/bptree-node-keys")?;
+
+        // Merge away most of the nodes, and rewrite the rest.
+        for n in 0..150 {
+            tree.remove(&n)?;
+        }
+        for n in 150..200 {
+            tree.insert(n, n + 1)?;
+        }
+        tree.persist()?;
+
+        let new_key = [8; 32];
+        tree.rotate_key(new_key)?;
+        drop(tree);
+
+        assert!(matches!(
+            BPTree::<usize, usize>::load_with("/tmp/bptree-node-keys", LoadOptions::new().key(key)),
+            Err(Error::MetadataAuthentication { .. })
+        ));
+
+        // Leaves are reached through their siblings as well as their parents.
+        let mut tree: BPTree<usize, usize> =
+            BPTree::load_with("/tmp/bptree-node-keys", LoadOptions::new().key(new_key))?;
+        let pairs = tree.iter().collect::<Result<Vec<_>, _>>()?;
+        assert!(pairs
+            .into_iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((150..200).map(|n| (n, n + 1))));
+        let pairs = tree.iter().rev().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(pairs.len(), 50);
+
+        tree.set_cache_capacity(Some(1));
+        for n in 150..200 {
+            assert_eq!(tree.get(&n)?, Some(&(n + 1)));
+        }
+        drop(tree);
+
+        // Old versions of the nodes still in the tree were written under keys
+        // that are gone.
+        let rewritten = nodes("/tmp/bptree-node-keys")?
+            .into_keys()
+            .filter(|uuid| old_nodes.contains_key(uuid))
+            .collect::<Vec<_>>();
+        assert!(!rewritten.is_empty());
+        fs::write(
+            format!("/tmp/bptree-node-keys/{}", rewritten[0]),
+            &old_nodes[&rewritten[0]],
+        )?;
+
+        let tree: BPTree<usize, usize> =
+            BPTree::load_with("/tmp/bptree-node-keys", LoadOptions::new().key(new_key))?;
+        assert!((150..200).any(|n| {
+            matches!(tree.get(&n), Err(Error::Authentication { node }) if node == rewritten[0])
+        }));
+
+        let _ = fs::remove_dir_all("/tmp/bptree-node-keys");
+
+        Ok(())
+    }
+
+    #[test]
+    fn bulk_load() -> Result<(), Error> {
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, usize, _> =
+            BPTree::bulk_load_with_store(store.clone(), 4, 1.0, (0..500).map(|n| (n * 2, n)))?;
+        let mut reference = (0..500).map(|n| (n * 2, n)).collect::<BTreeMap<_, _>>();
+        tree.persist()?;
+
+        let mut tree: BPTree<usize, usize, _> = BPTree::load_from_store(store)?;
+        assert_eq!(tree.len(), 500);
+        let pairs = tree.iter().rev().collect::<Result<Vec<_>, _>>()?;
+        assert!(pairs.into_iter().eq(reference.iter().rev()));
+
+        for n in 0..500 {
+            tree.insert(n * 2 + 1, n)?;
+            reference.insert(n * 2 + 1, n);
+        }
+        for n in (0..1000).step_by(3) {
+            tree.remove(&n)?;
+            reference.remove(&n);
+        }
+        let pairs = tree.iter().collect::<Result<Vec<_>, _>>()?;
+        assert!(pairs.into_iter().eq(reference.iter()));
+
+        let tree: BPTree<usize, usize, _> =
+            BPTree::bulk_load_with_store(MemStore::new(), 5, 0.5, (0..100).map(|n| (n, n)))?;
+        assert_eq!(tree.get(&99)?, Some(&99));
+
+        assert!(matches!(
+            BPTree::<_, _, _>::bulk_load_with_store(
+                MemStore::new(),
+                4,
+                1.0,
+                [(1, ()), (3, ()), (3, ())]
+            ),
+            Err(Error::Unsorted { index: 2 })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn entry() -> Result<(), Error> {
+        let wal = "/tmp/bptree-entry-wal";
+        let _ = fs::remove_file(wal);
+
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, usize, _> = BPTree::with_store(store.clone(), 4);
+        tree.set_cache_capacity(Some(4));
+        tree.enable_wal_at(wal)?;
+        let mut reference = BTreeMap::new();
+
+        assert!(matches!(tree.entry(1)?, Entry::Vacant(_)));
+
+        for n in 0..500 {
+            let key = n * 7 % 100;
+            match tree.entry(key)? {
+                Entry::Occupied(mut entry) => *entry.get_mut() += 1,
+                Entry::Vacant(entry) => {
+                    assert_eq!(*entry.key(), key);
+                    *entry.insert(0)? += 1;
+                }
+            }
+            *reference.entry(key).or_insert(0) += 1;
+        }
+        tree.persist()?;
+
+        for key in (0..100).step_by(3) {
+            if let Entry::Occupied(entry) = tree.entry(key)? {
+                assert_eq!(
+                    entry.remove_entry()?,
+                    (key, reference.remove(&key).unwrap())
+                );
+            }
+        }
+        for key in (1..100).step_by(3) {
+            if let Entry::Occupied(mut entry) = tree.entry(key)? {
+                assert_eq!(*entry.get(), reference[&key]);
+                assert_eq!(entry.insert(0)?, reference.insert(key, 0).unwrap());
+            }
+        }
+        tree.sync_wal()?;
+        drop(tree);
+
+        // Everything since the persist is replayed from the log.
+        let mut tree: BPTree<usize, usize, _> = BPTree::load_from_store(store)?;
+        tree.enable_wal_at(wal)?;
+        assert_eq!(tree.len(), reference.len());
+        let pairs = tree.iter().collect::<Result<Vec<_>, _>>()?;
+        assert!(pairs.into_iter().eq(reference.iter()));
+
+        let _ = fs::remove_file(wal);
+
+        Ok(())
+    }
+
+    #[test]
+    fn disk_set() -> Result<(), Error> {
+        let store = MemStore::new();
+        let mut set: BPTreeDiskSet<usize, _> = BPTreeDiskSet::with_store(store.clone(), 4);
+        for n in 0..500 {
+            assert!(set.insert(n * 2)?);
+        }
+        assert!(!set.insert(10)?);
+        set.persist()?;
+
+        assert!(set.remove(&10)?);
+        assert!(!set.remove(&11)?);
+        set.insert(11)?;
+        set.persist_key(&11)?;
+        drop(set);
+
+        let set: BPTreeDiskSet<usize, _> = BPTreeDiskSet::load_from_store(store)?;
+        assert_eq!(set.len(), 500);
+        assert!(set.contains(&11)? && !set.contains(&10)?);
+        let values = set.range(5..15).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(values, [&6, &8, &11, &12, &14]);
+        let values = set.iter().rev().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(values.len(), 500);
+        assert_eq!(values[0], &998);
+
+        Ok(())
+    }
+
+    #[test]
+    fn first_last_pop() -> Result<(), Error> {
+        let wal = "/tmp/bptree-first-last-pop-wal";
+        let _ = fs::remove_file(wal);
+
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, usize, _> = BPTree::with_store(store.clone(), 4);
+        assert_eq!(tree.first_key_value()?, None);
+        assert_eq!(tree.pop_last()?, None);
+
+        for n in 0..1000 {
+            tree.insert(n * 7 % 1000, n)?;
+        }
+        tree.persist()?;
+        drop(tree);
+
+        // Only the path down to the leaf is loaded.
+        let mut tree: BPTree<usize, usize, _> = BPTree::load_from_store(store.clone())?;
+        assert_eq!(tree.first_key_value()?, Some((&0, &0)));
+        let cached_nodes = tree.stats().cached_nodes;
+        assert!(cached_nodes < 10);
+        assert_eq!(tree.last_key_value()?, Some((&999, &857)));
+        assert!(tree.stats().cached_nodes < 2 * cached_nodes);
+
+        tree.set_cache_capacity(Some(4));
+        tree.enable_wal_at(wal)?;
+        let mut reference = (0..1000)
+            .map(|n| (n * 7 % 1000, n))
+            .collect::<BTreeMap<_, _>>();
+
+        for n in 0..400 {
+            if n % 3 == 0 {
+                assert_eq!(tree.pop_last()?, reference.pop_last());
+            } else {
+                assert_eq!(tree.pop_first()?, reference.pop_first());
+            }
+        }
+        assert_eq!(tree.len(), reference.len());
+        tree.sync_wal()?;
+        drop(tree);
+
+        // Pops are replayed from the log like any other removal.
+        let mut tree: BPTree<usize, usize, _> = BPTree::load_from_store(store)?;
+        tree.enable_wal_at(wal)?;
+        let pairs = tree.iter().collect::<Result<Vec<_>, _>>()?;
+        assert!(pairs.into_iter().eq(reference.iter()));
+
+        while let Some(entry) = tree.pop_first()? {
+            assert_eq!(Some(entry), reference.pop_first());
+        }
+        assert!(tree.is_empty());
+        assert_eq!(tree.last_key_value()?, None);
+        drop(tree);
+        let _ = fs::remove_file(wal);
+
+        let mut set: BPTreeDiskSet<usize, _> = BPTreeDiskSet::with_store(MemStore::new(), 4);
+        for n in 0..50 {
+            set.insert(n)?;
+        }
+        assert_eq!(set.first()?, Some(&0));
+        assert_eq!(set.last()?, Some(&49));
+        assert_eq!(set.pop_first()?, Some(0));
+        assert_eq!(set.pop_last()?, Some(49));
+        assert_eq!(set.len(), 48);
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove_range() -> Result<(), Error> {
+        let wal = "/tmp/bptree-remove-range-wal";
+        let _ = fs::remove_file(wal);
+
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, usize, _> = BPTree::with_store(store.clone(), 4);
+        let mut reference = BTreeMap::new();
+        for n in 0..2000 {
+            tree.insert(n * 7 % 2000, n)?;
+            reference.insert(n * 7 % 2000, n);
+        }
+        tree.persist()?;
+        tree.set_cache_capacity(Some(8));
+        tree.enable_wal_at(wal)?;
+
+        let ranges = [
+            (Bound::Included(100), Bound::Excluded(900)),
+            (Bound::Excluded(50), Bound::Included(60)),
+            (Bound::Included(1500), Bound::Unbounded),
+            (Bound::Unbounded, Bound::Excluded(20)),
+            (Bound::Included(950), Bound::Included(950)),
+            (Bound::Included(100), Bound::Included(900)),
+        ];
+        for range in ranges {
+            let removed = reference.range(range).count();
+            assert_eq!(tree.remove_range(range)?, removed);
+            for key in reference
+                .range(range)
+                .map(|(key, _)| *key)
+                .collect::<Vec<_>>()
+            {
+                reference.remove(&key);
+            }
+            assert_eq!(tree.len(), reference.len());
+        }
+
+        // The tree has to stay balanced as it changes.
+        for n in (0..2000).step_by(5) {
+            tree.insert(n, n)?;
+            reference.insert(n, n);
+        }
+        for n in (0..2000).step_by(3) {
+            assert_eq!(tree.remove(&n)?, reference.remove(&n));
+        }
+        tree.sync_wal()?;
+        drop(tree);
+
+        // Everything since the persist is replayed from the log.
+        let mut tree: BPTree<usize, usize, _> = BPTree::load_from_store(store.clone())?;
+        tree.enable_wal_at(wal)?;
+        let pairs = tree.iter().collect::<Result<Vec<_>, _>>()?;
+        assert!(pairs.into_iter().eq(reference.iter()));
+        let pairs = tree.iter().rev().collect::<Result<Vec<_>, _>>()?;
+        assert!(pairs.into_iter().eq(reference.iter().rev()));
+
+        assert_eq!(tree.remove_range(..)?, reference.len());
+        assert!(tree.is_empty());
+        assert_eq!(tree.remove_range(10..20)?, 0);
+        tree.persist()?;
+
+        let tree: BPTree<usize, usize, _> = BPTree::load_from_store(store.clone())?;
+        assert!(tree.is_empty());
+        assert_eq!(tree.iter().count(), 0);
+
+        // Every node removed along the way was deleted from the store.
+        assert_eq!(store.nodes(), 0);
+
+        let _ = fs::remove_file(wal);
+
+        // Cutting off the tail leaves the leaf before it pointing nowhere,
+        // and that has to be persisted even when nothing else in it changed.
+        // Removing the key before the cut leaves a separator that sends the
+        // cut past that leaf for some of these.
+        for start in 1..100 {
+            let store = MemStore::new();
+            let mut tree: BPTree<usize, usize, _> = BPTree::with_store(store.clone(), 4);
+            for n in 0..100 {
+                tree.insert(n, n)?;
+            }
+            tree.remove(&(start - 1))?;
+            tree.persist()?;
+            tree.remove_range(start..)?;
+            tree.persist()?;
+
+            let mut tree: BPTree<usize, usize, _> = BPTree::load_from_store(store)?;
+            tree.retain(|_, _| true)?;
+            for n in 100..110 {
+                tree.insert(n, n)?;
+            }
+            let keys = tree.keys().collect::<Result<Vec<_>, _>>()?;
+            assert!(keys.into_iter().copied().eq((0..start - 1).chain(100..110)));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn retain_drain_extract_if() -> Result<(), Error> {
+        let wal = "/tmp/bptree-retain-wal";
+        let _ = fs::remove_file(wal);
+
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, usize, _> = BPTree::with_store(store.clone(), 4);
+        let mut reference = BTreeMap::new();
+        for n in 0..2000 {
+            tree.insert(n * 7 % 2000, n)?;
+            reference.insert(n * 7 % 2000, n);
+        }
+        tree.persist()?;
+        tree.set_cache_capacity(Some(8));
+        tree.enable_wal_at(wal)?;
+
+        tree.retain(|key, _| key % 3 != 0 && !(300..1200).contains(key))?;
+        reference.retain(|key, _| key % 3 != 0 && !(300..1200).contains(key));
+        assert_eq!(tree.len(), reference.len());
+
+        let extracted = tree.extract_if(|key, value| key % 2 == 0 || value % 5 == 0)?;
+        let reference_extracted = reference
+            .extract_if(.., |key, value| key % 2 == 0 || *value % 5 == 0)
+            .collect::<Vec<_>>();
+        assert_eq!(extracted, reference_extracted);
+        assert_eq!(tree.len(), reference.len());
+        assert!(tree.extract_if(|_, _| false)?.is_empty());
+
+        // The tree has to stay balanced as it changes.
+        for n in (0..2000).step_by(5) {
+            tree.insert(n, n)?;
+            reference.insert(n, n);
+        }
+        for n in (0..2000).step_by(7) {
+            assert_eq!(tree.remove(&n)?, reference.remove(&n));
+        }
+        tree.sync_wal()?;
+        drop(tree);
+
+        // Everything since the persist is replayed from the log.
+        let mut tree: BPTree<usize, usize, _> = BPTree::load_from_store(store.clone())?;
+        tree.enable_wal_at(wal)?;
+        let pairs = tree.iter().collect::<Result<Vec<_>, _>>()?;
+        assert!(pairs.into_iter().eq(reference.iter()));
+        let pairs = tree.iter().rev().collect::<Result<Vec<_>, _>>()?;
+        assert!(pairs.into_iter().eq(reference.iter().rev()));
+
+        // Leaves left empty are gone from the store once persisted.
+        tree.retain(|key, _| *key < 40)?;
+        reference.retain(|key, _| *key < 40);
+        tree.persist()?;
+        assert_eq!(store.nodes(), count_nodes(&tree));
+
+        let drained = tree.drain()?;
+        assert!(drained.into_iter().eq(reference.into_iter()));
+        assert!(tree.is_empty());
+        assert!(tree.drain()?.is_empty());
+        tree.persist()?;
+
+        let tree: BPTree<usize, usize, _> = BPTree::load_from_store(store.clone())?;
+        assert!(tree.is_empty());
+        assert_eq!(tree.iter().count(), 0);
+        assert_eq!(store.nodes(), 0);
+
+        let _ = fs::remove_file(wal);
+
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot() -> Result<(), Error> {
+        fn assert_sees<S: NodeStore, C: Codec>(
+            snapshot: &mut Snapshot<usize, String, S, C>,
+            reference: &BTreeMap<usize, String>,
+        ) -> Result<(), Error> {
+            assert_eq!(snapshot.len(), reference.len());
+            let pairs = snapshot.iter().collect::<Result<Vec<_>, _>>()?;
+            assert!(pairs.into_iter().eq(reference.iter()));
+            let pairs = snapshot.iter().rev().collect::<Result<Vec<_>, _>>()?;
+            assert!(pairs.into_iter().eq(reference.iter().rev()));
+            let pairs = snapshot.range(100..300).collect::<Result<Vec<_>, _>>()?;
+            assert!(pairs.into_iter().eq(reference.range(100..300)));
+            snapshot.shrink_cache();
+            for n in (0..1000).step_by(7) {
+                assert_eq!(snapshot.get(&n)?, reference.get(&n));
+            }
+            assert_eq!(snapshot.first_key_value()?, reference.first_key_value());
+            assert_eq!(snapshot.last_key_value()?, reference.last_key_value());
+            Ok(())
+        }
+
+        let _ = fs::remove_dir_all("/tmp/bptree-snapshot");
+
+        let mut tree: BPTree<usize, String> = BPTree::with_order("/tmp/bptree-snapshot", 4);
+        let mut reference = BTreeMap::new();
+        for n in 0..500 {
+            tree.insert(n * 2, format!("first {n}"))?;
+            reference.insert(n * 2, format!("first {n}"));
+        }
+
+        // Nothing has been persisted yet.
+        let mut empty = tree.snapshot()?;
+        assert_sees(&mut empty, &BTreeMap::new())?;
+
+        tree.persist()?;
+        tree.set_cache_capacity(Some(8));
+        let mut first = tree.snapshot()?;
+        first.set_cache_capacity(Some(4));
+        let first_reference = reference.clone();
+
+        for n in (0..1000).step_by(3) {
+            tree.insert(n, format!("second {n}"))?;
+            reference.insert(n, format!("second {n}"));
+        }
+        for n in (0..1000).step_by(5) {
+            assert_eq!(tree.remove(&n)?, reference.remove(&n));
+        }
+        assert_sees(&mut first, &first_reference)?;
+
+        tree.persist()?;
+        let mut second = tree.snapshot()?;
+        let second_reference = reference.clone();
+        assert_sees(&mut first, &first_reference)?;
+        assert_sees(&mut second, &second_reference)?;
+
+        // Removing whole subtrees deletes nodes the snapshots still use.
+        tree.remove_range(200..800)?;
+        tree.retain(|key, _| key % 4 != 1)?;
+        reference.retain(|key, _| !(200..800).contains(key) && key % 4 != 1);
+        tree.persist()?;
+        assert_sees(&mut first, &first_reference)?;
+        assert_sees(&mut second, &second_reference)?;
+        assert_sees(&mut tree.snapshot()?, &reference)?;
+
+        // Once the snapshots are gone, so are the versions kept for them. Only
+        // the record of which versions were kept waits for the next persist.
+        let files = || -> Result<usize, Error> {
+            Ok(fs::read_dir("/tmp/bptree-snapshot")?
+                .filter(|entry| {
+                    let name = entry.as_ref().unwrap().file_name();
+                    uuid::Uuid::parse_str(&name.to_string_lossy()).is_ok()
+                })
+                .count())
+        };
+        drop(first);
+        assert!(files()? > count_nodes(&tree) + 1);
+        drop((empty, second));
+        assert_eq!(files()?, count_nodes(&tree) + 1);
+        tree.persist()?;
+        assert_eq!(files()?, count_nodes(&tree));
+
+        // Versions kept for snapshots that outlive the tree are deleted once
+        // it's loaded again.
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, String, _> = BPTree::with_store(store.clone(), 4);
+        tree.set_key([7; 32])?;
+        for n in 0..300 {
+            tree.insert(n, format!("first {n}"))?;
+        }
+        tree.persist()?;
+        let nodes = store.nodes();
+
+        let mut snapshot = tree.snapshot()?;
+        let reference = (0..300)
+            .map(|n| (n, format!("first {n}")))
+            .collect::<BTreeMap<_, _>>();
+        for n in 0..300 {
+            tree.insert(n, format!("second {n}"))?;
+        }
+        tree.persist()?;
+        assert!(store.nodes() > nodes);
+        drop(tree);
+        assert_sees(&mut snapshot, &reference)?;
+        drop(snapshot);
+
+        let mut tree: BPTree<usize, String, _> =
+            BPTree::load_from_store_with(store.clone(), LoadOptions::new().key([7; 32]))?;
+        tree.persist()?;
+        assert_eq!(store.nodes(), count_nodes(&tree));
+        assert_eq!(tree.get(&10)?.map(String::as_str), Some("second 10"));
+
+        // There can be more versions than a page file has room for in its
+        // header.
+        let _ = fs::remove_file("/tmp/bptree-snapshot-page-file");
+        let mut tree: BPTree<usize, String, _> =
+            BPTree::with_store(PageFileStore::create("/tmp/bptree-snapshot-page-file"), 3);
+        for n in 0..2000 {
+            tree.insert(n, format!("first {n}"))?;
+        }
+        tree.persist()?;
+
+        let mut snapshot = tree.snapshot()?;
+        let reference = (0..2000)
+            .map(|n| (n, format!("first {n}")))
+            .collect::<BTreeMap<_, _>>();
+        for n in 0..2000 {
+            tree.insert(n, format!("second {n}"))?;
+        }
+        tree.persist()?;
+        drop(tree);
+        assert_sees(&mut snapshot, &reference)?;
+        drop(snapshot);
+
+        let mut tree: BPTree<usize, String, _> =
+            BPTree::load_from_store(PageFileStore::open("/tmp/bptree-snapshot-page-file")?)?;
+        tree.persist()?;
+        assert_eq!(tree.get(&10)?.map(String::as_str), Some("second 10"));
+
+        let _ = fs::remove_dir_all("/tmp/bptree-snapshot");
+        let _ = fs::remove_file("/tmp/bptree-snapshot-page-file");
+
+        Ok(())
+    }
+
+    #[test]
+    fn transaction() -> Result<(), Error> {
+        fn assert_holds<S: NodeStore, C: Codec>(
+            tree: &BPTree<usize, usize, S, C>,
+            reference: &BTreeMap<usize, usize>,
+        ) -> Result<(), Error> {
+            assert_eq!(tree.len(), reference.len());
+            let entries = tree.iter().collect::<Result<Vec<_>, _>>()?;
+            assert!(entries.into_iter().eq(reference.iter()));
+            Ok(())
+        }
+
+        let wal = "/tmp/bptree-transaction-wal";
+        let _ = fs::remove_file(wal);
+
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, usize, _> = BPTree::with_store(store.clone(), 4);
+        let mut reference = BTreeMap::new();
+        tree.enable_wal_at(wal)?;
+        tree.set_cache_capacity(Some(4));
+
+        for n in 0..200 {
+            tree.insert(n, n)?;
+            reference.insert(n, n);
+        }
+
+        let wal_len = fs::metadata(wal)?.len();
+        let mut transaction = tree.transaction()?;
+        for n in 200..300 {
+            transaction.insert(n, n)?;
+        }
+        transaction.remove_range(0..50)?;
+        *transaction.get_mut(&100)?.unwrap() += 1;
+        assert_eq!(transaction.len(), 250);
+        assert_eq!(transaction.get(&100)?, Some(&101));
+
+        // Nothing is logged until the transaction commits.
+        assert_eq!(fs::metadata(wal)?.len(), wal_len);
+        transaction.rollback()?;
+        assert_holds(&tree, &reference)?;
+
+        // Dropping a transaction rolls it back too.
+        let mut transaction = tree.transaction()?;
+        transaction.retain(|key, _| key % 2 == 0)?;
+        transaction.pop_first()?;
+        for value in transaction.values_mut() {
+            *value? += 1;
+        }
+        for pair in transaction.range_mut(50..60) {
+            *pair?.1 = 0;
+        }
+        for pair in transaction.iter_mut().rev().take(3) {
+            *pair?.1 = 0;
+        }
+        assert_eq!(transaction.first_key_value()?, Some((&2, &3)));
+        assert_eq!(transaction.last_key_value()?, Some((&198, &0)));
+        assert_eq!(transaction.extract_if(|key, _| *key < 10)?.len(), 4);
+        assert_eq!(transaction.drain()?.len(), 95);
+        assert!(transaction.is_empty());
+        drop(transaction);
+        assert_holds(&tree, &reference)?;
+
+        tree.insert(1000, 1000)?;
+        reference.insert(1000, 1000);
+
+        let mut transaction = tree.transaction()?;
+        for n in 0..100 {
+            transaction.remove(&n)?;
+            reference.remove(&n);
+        }
+        if let Entry::Vacant(entry) = transaction.entry(500)? {
+            entry.insert(500)?;
+        }
+        reference.insert(500, 500);
+        transaction.commit()?;
+        assert_holds(&tree, &reference)?;
+
+        drop(tree);
+        let mut tree: BPTree<usize, usize, _> = BPTree::load_from_store(store)?;
+        tree.enable_wal_at(wal)?;
+        assert_holds(&tree, &reference)?;
+        drop(tree);
+
+        // Rolling back forgets the nodes the transaction removed, which the
+        // tree still has.
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, usize, _> = BPTree::with_store(store.clone(), 3);
+        tree.set_key([3; 32])?;
+        let reference = (0..100).map(|n| (n, n)).collect::<BTreeMap<_, _>>();
+        for n in 0..100 {
+            tree.insert(n, n)?;
+        }
+
+        let mut transaction = tree.transaction()?;
+        transaction.remove_range(20..80)?;
+        transaction.rollback()?;
+        assert_holds(&tree, &reference)?;
+
+        tree.insert(100, 100)?;
+        tree.persist()?;
+        assert_eq!(store.nodes(), count_nodes(&tree));
+
+        // Changes made before the transaction aren't persisted by it, and
+        // survive it being rolled back.
+        let store = MemStore::new();
+        let mut tree: BPTree<usize, usize, _> = BPTree::with_store(store.clone(), 3);
+        let mut reference = (0..10).map(|n| (n * 2, n)).collect::<BTreeMap<_, _>>();
+        for (key, value) in &reference {
+            tree.insert(*key, *value)?;
+        }
+        tree.persist()?;
+        tree.set_cache_capacity(Some(2));
+        let persisted = reference.clone();
+        for n in [5, 7, 9, 11] {
+            tree.insert(n, n)?;
+            reference.insert(n, n);
+        }
+        tree.remove(&0)?;
+        reference.remove(&0);
+
+        let mut transaction = tree.transaction()?;
+        transaction.remove_range(4..12)?;
+        transaction.insert(100, 100)?;
+        transaction.rollback()?;
+        assert_holds(&tree, &reference)?;
+        assert_holds(
+            &BPTree::<_, _, _>::load_from_store(store.clone())?,
+            &persisted,
+        )?;
+
+        let mut transaction = tree.transaction()?;
+        transaction.pop_last()?;
+        drop(transaction);
+        assert_holds(&tree, &reference)?;
+
+        tree.persist()?;
+        let tree: BPTree<usize, usize, _> = BPTree::load_from_store(store.clone())?;
+        assert_holds(&tree, &reference)?;
+        assert_eq!(store.nodes(), count_nodes(&tree));
+
+        let _ = fs::remove_file(wal);
+
+        Ok(())
+    }
+
+    #[test]
+    fn keys_only_layout() -> Result<(), Error> {
+        let _ = fs::remove_dir_all("/tmp/bptree-keys-only-layout");
+
+        let dir_size = || -> Result<u64, Error> {
+            let mut size = 0;
+            for entry in fs::read_dir("/tmp/bptree-keys-only-layout")? {
+                size += entry?.metadata()?.len();
+            }
+            Ok(size)
+        };
+
+        let mut tree: BPTree<usize, ()> = BPTree::with_order("/tmp/bptree-keys-only-layout", 4);
+        for n in 0..100 {
+            tree.insert(n, ())?;
+        }
+        tree.persist()?;
+        let size = dir_size()?;
+        drop(tree);
+        let _ = fs::remove_dir_all("/tmp/bptree-keys-only-layout");
+
+        // Lay the tree out the way it was before leaves left out zero-sized
+        // values.
+        let mut tree: BPTree<usize, ()> = BPTree::with_order("/tmp/bptree-keys-only-layout", 4);
+        tree.pager.version = KEYS_ONLY_VERSION - 1;
+        for n in 0..100 {
+            tree.insert(n, ())?;
+        }
+        tree.persist()?;
+        assert!(dir_size()? > size);
+        drop(tree);
+
+        let mut set: BPTreeDiskSet<usize> = BPTreeDiskSet::load("/tmp/bptree-keys-only-layout")?;
+        for n in 100..200 {
+            set.insert(n)?;
+        }
+        set.persist()?;
+
+        let set: BPTreeDiskSet<usize> = BPTreeDiskSet::load("/tmp/bptree-keys-only-layout")?;
+        let values = set.iter().collect::<Result<Vec<_>, _>>()?;
+        assert!(values.into_iter().copied().eq(0..200));
+
+        let _ = fs::remove_dir_all("/tmp/bptree-keys-only-layout");
+
+        Ok(())
+    }
 }
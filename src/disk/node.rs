@@ -1,10 +1,19 @@
-use super::error::Error;
-use path_macro::path;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use super::{
+    checksum,
+    codec::Codec,
+    compression,
+    encryption::{self, Cipher},
+    error::Error,
+    pager::Pager,
+    store::NodeStore,
+};
+use serde::{
+    de::{self, IntoDeserializer},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
 use std::{
-    fs,
+    mem,
     ops::{Deref, DerefMut},
-    path::Path,
     ptr::NonNull,
 };
 use uuid::Uuid;
@@ -20,24 +29,28 @@ impl<K, V> Link<K, V> {
         }
     }
 
-    pub fn free(self) {
+    pub fn unloaded(uuid: Uuid) -> Self {
         unsafe {
-            let _ = Box::from_raw(self.as_ptr());
+            Self(NonNull::new_unchecked(Box::into_raw(Box::new(
+                NodeRef::Unloaded(uuid),
+            ))))
         }
     }
 
-    pub fn reclaim(self, path: &Path) -> Result<(), Error> {
+    pub fn free(self) {
         unsafe {
-            (*self.as_ptr()).reclaim(path)?;
-            self.free();
-            Ok(())
+            let _ = Box::from_raw(self.as_ptr());
         }
     }
+
+    pub fn reclaim<S: NodeStore, C: Codec>(self, pager: &mut Pager<K, V, S, C>) {
+        pager.reclaim(self)
+    }
 }
 
 impl<K, V> Clone for Link<K, V> {
     fn clone(&self) -> Self {
-        Self(self.0)
+        *self
     }
 }
 
@@ -91,55 +104,51 @@ pub enum NodeRef<K, V> {
 }
 
 impl<K, V> NodeRef<K, V> {
-    pub unsafe fn access(&mut self, path: &Path) -> Result<&Node<K, V>, Error>
+    pub unsafe fn access<S: NodeStore, C: Codec>(
+        &mut self,
+        pager: &Pager<K, V, S, C>,
+    ) -> Result<&Node<K, V>, Error>
     where
         for<'de> K: Deserialize<'de>,
         for<'de> V: Deserialize<'de>,
     {
         match self {
-            Self::Loaded(node) => Ok(node),
+            Self::Loaded(node) => {
+                pager.touch(node.uuid());
+                Ok(node)
+            }
             Self::Unloaded(uuid) => {
-                let path = path![path / uuid.to_string()];
-                let ser = fs::read(&path)?;
-                let node = bincode::deserialize(&ser).map_err(|_| Error::Serde)?;
-                *self = Self::Loaded(node);
-                self.access(&path)
+                *self = Self::Loaded(pager.load(*uuid)?);
+                self.access(pager)
             }
         }
     }
 
-    pub unsafe fn access_mut(&mut self, path: &Path) -> Result<&mut Node<K, V>, Error>
+    pub unsafe fn access_mut<S: NodeStore, C: Codec>(
+        &mut self,
+        pager: &Pager<K, V, S, C>,
+    ) -> Result<&mut Node<K, V>, Error>
     where
         for<'de> K: Deserialize<'de>,
         for<'de> V: Deserialize<'de>,
     {
         match self {
-            Self::Loaded(node) => Ok(node),
+            Self::Loaded(node) => {
+                pager.touch(node.uuid());
+                Ok(node)
+            }
             Self::Unloaded(uuid) => {
-                let path = path![path / uuid.to_string()];
-                let data = fs::read(&path)?;
-                let node = bincode::deserialize(&data).map_err(|_| Error::Serde)?;
-                *self = Self::Loaded(node);
-                self.access_mut(&path)
+                *self = Self::Loaded(pager.load(*uuid)?);
+                self.access_mut(pager)
             }
         }
     }
 
-    pub fn reclaim(&self, path: &Path) -> Result<(), Error> {
+    pub fn uuid(&self) -> Uuid {
         match self {
-            Self::Loaded(node) => match node {
-                Node::Internal(node) => {
-                    let _ = fs::remove_file(path![path / node.uuid.to_string()]);
-                }
-                Node::Leaf(node) => {
-                    let _ = fs::remove_file(path![path / node.uuid.to_string()]);
-                }
-            },
-            Self::Unloaded(uuid) => {
-                let _ = fs::remove_file(path![path / uuid.to_string()]);
-            }
+            Self::Loaded(node) => node.uuid(),
+            Self::Unloaded(uuid) => *uuid,
         }
-        Ok(())
     }
 }
 
@@ -198,19 +207,88 @@ pub(crate) enum Node<K, V> {
 }
 
 impl<K, V> Node<K, V> {
-    pub fn persist(&mut self, path: &Path) -> Result<(), Error>
+    pub fn uuid(&self) -> Uuid {
+        match self {
+            Node::Internal(node) => node.uuid,
+            Node::Leaf(node) => node.uuid,
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        match self {
+            Node::Internal(node) => node.is_dirty,
+            Node::Leaf(node) => node.is_dirty,
+        }
+    }
+
+    // Frees the links of a node that was read without being interned.
+    pub fn free_links(self) {
+        match self {
+            Node::Internal(node) => {
+                for child in node.children {
+                    child.free();
+                }
+                if let Some(parent) = node.parent {
+                    parent.free();
+                }
+            }
+            Node::Leaf(node) => {
+                for link in [node.parent, node.next_leaf, node.prev_leaf]
+                    .into_iter()
+                    .flatten()
+                {
+                    link.free();
+                }
+            }
+        }
+    }
+
+    pub fn persist<S: NodeStore, C: Codec>(
+        &mut self,
+        pager: &mut Pager<K, V, S, C>,
+    ) -> Result<(), Error>
     where
         K: Serialize,
         V: Serialize,
     {
-        let ser = bincode::serialize(self).map_err(|_| Error::Serde)?;
+        let ser = if pager.has_legacy_leaves() {
+            pager.codec.encode(&LegacyNodeRef::from(&*self))?
+        } else {
+            pager.codec.encode(self)?
+        };
+        let encoded = ser.len();
 
-        let path = match self {
-            Node::Internal(node) => path![path / node.uuid.to_string()],
-            Node::Leaf(node) => path![path / node.uuid.to_string()],
+        let data = if pager.is_compressible() {
+            compression::compress(pager.compression, &ser)
+        } else {
+            ser
+        };
+        pager.count_bytes(encoded, data.len());
+
+        let data = match &pager.cipher {
+            Some(_) if pager.has_node_keys() => {
+                let child_keys = match self {
+                    Node::Internal(node) => node
+                        .children
+                        .iter()
+                        .map(|child| pager.node_key(unsafe { (*child.as_ptr()).uuid() }))
+                        .collect(),
+                    Node::Leaf(_) => Vec::new(),
+                };
+
+                // Every write gets a fresh key, so that once the parent is
+                // rewritten with it, nothing left can decrypt the old version.
+                let key = pager.rotate_node_key(self.uuid());
+                Cipher::new(&key).encrypt(
+                    self.uuid().as_bytes(),
+                    &encryption::prepend_keys(&child_keys, &data),
+                )
+            }
+            Some(cipher) => cipher.encrypt(self.uuid().as_bytes(), &data),
+            None => data,
         };
 
-        fs::write(&path, &ser)?;
+        pager.write_node(self.uuid(), &checksum::seal(&data))?;
 
         match self {
             Node::Internal(node) => node.is_dirty = false,
@@ -245,13 +323,18 @@ impl<K, V> Internal<K, V> {
     }
 }
 
+// Zero-sized values are all alike, so leaves holding them only store their
+// keys. Sets are trees with `()` values, and store nothing else.
 #[derive(Deserialize, Serialize)]
+#[serde(try_from = "StoredLeaf<K, V>")]
 pub(crate) struct Leaf<K, V> {
     pub(crate) uuid: Uuid,
     pub(crate) keys: Vec<K>,
+    #[serde(serialize_with = "serialize_values")]
     pub(crate) values: Vec<V>,
     pub(crate) parent: Option<Link<K, V>>,
     pub(crate) next_leaf: Option<Link<K, V>>,
+    pub(crate) prev_leaf: Option<Link<K, V>>,
     #[serde(skip)]
     pub(crate) is_dirty: bool,
 }
@@ -269,3 +352,137 @@ impl<K, V> Leaf<K, V> {
         self.keys.len() > order.div_ceil(2)
     }
 }
+
+fn serialize_values<V, S>(values: &Vec<V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    V: Serialize,
+    S: Serializer,
+{
+    if mem::size_of::<V>() == 0 {
+        serializer.serialize_unit()
+    } else {
+        values.serialize(serializer)
+    }
+}
+
+fn deserialize_values<'de, V, D>(deserializer: D) -> Result<Option<Vec<V>>, D::Error>
+where
+    V: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    if mem::size_of::<V>() == 0 {
+        <()>::deserialize(deserializer)?;
+        Ok(None)
+    } else {
+        Vec::deserialize(deserializer).map(Some)
+    }
+}
+
+// A leaf as stored, before any zero-sized values are filled back in.
+#[derive(Deserialize)]
+struct StoredLeaf<K, V> {
+    uuid: Uuid,
+    keys: Vec<K>,
+    #[serde(deserialize_with = "deserialize_values")]
+    values: Option<Vec<V>>,
+    parent: Option<Link<K, V>>,
+    next_leaf: Option<Link<K, V>>,
+    prev_leaf: Option<Link<K, V>>,
+}
+
+impl<'de, K, V> TryFrom<StoredLeaf<K, V>> for Leaf<K, V>
+where
+    V: Deserialize<'de>,
+{
+    type Error = de::value::Error;
+
+    fn try_from(leaf: StoredLeaf<K, V>) -> Result<Self, Self::Error> {
+        let values = match leaf.values {
+            Some(values) => values,
+            None => (0..leaf.keys.len())
+                .map(|_| V::deserialize(().into_deserializer()))
+                .collect::<Result<_, _>>()?,
+        };
+
+        Ok(Leaf {
+            uuid: leaf.uuid,
+            keys: leaf.keys,
+            values,
+            parent: leaf.parent,
+            next_leaf: leaf.next_leaf,
+            prev_leaf: leaf.prev_leaf,
+            is_dirty: false,
+        })
+    }
+}
+
+// Nodes as laid out before leaves left out zero-sized values, which trees
+// written back then keep using.
+#[derive(Deserialize)]
+#[serde(rename = "Node")]
+pub(crate) enum LegacyNode<K, V> {
+    Internal(Internal<K, V>),
+    Leaf(LegacyLeaf<K, V>),
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Leaf")]
+pub(crate) struct LegacyLeaf<K, V> {
+    uuid: Uuid,
+    keys: Vec<K>,
+    values: Vec<V>,
+    parent: Option<Link<K, V>>,
+    next_leaf: Option<Link<K, V>>,
+    prev_leaf: Option<Link<K, V>>,
+}
+
+impl<K, V> From<LegacyNode<K, V>> for Node<K, V> {
+    fn from(node: LegacyNode<K, V>) -> Self {
+        match node {
+            LegacyNode::Internal(node) => Node::Internal(node),
+            LegacyNode::Leaf(leaf) => Node::Leaf(Leaf {
+                uuid: leaf.uuid,
+                keys: leaf.keys,
+                values: leaf.values,
+                parent: leaf.parent,
+                next_leaf: leaf.next_leaf,
+                prev_leaf: leaf.prev_leaf,
+                is_dirty: false,
+            }),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename = "Node")]
+enum LegacyNodeRef<'a, K, V> {
+    Internal(&'a Internal<K, V>),
+    Leaf(LegacyLeafRef<'a, K, V>),
+}
+
+#[derive(Serialize)]
+#[serde(rename = "Leaf")]
+struct LegacyLeafRef<'a, K, V> {
+    uuid: &'a Uuid,
+    keys: &'a Vec<K>,
+    values: &'a Vec<V>,
+    parent: &'a Option<Link<K, V>>,
+    next_leaf: &'a Option<Link<K, V>>,
+    prev_leaf: &'a Option<Link<K, V>>,
+}
+
+impl<'a, K, V> From<&'a Node<K, V>> for LegacyNodeRef<'a, K, V> {
+    fn from(node: &'a Node<K, V>) -> Self {
+        match node {
+            Node::Internal(node) => LegacyNodeRef::Internal(node),
+            Node::Leaf(leaf) => LegacyNodeRef::Leaf(LegacyLeafRef {
+                uuid: &leaf.uuid,
+                keys: &leaf.keys,
+                values: &leaf.values,
+                parent: &leaf.parent,
+                next_leaf: &leaf.next_leaf,
+                prev_leaf: &leaf.prev_leaf,
+            }),
+        }
+    }
+}
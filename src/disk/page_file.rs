@@ -0,0 +1,335 @@
+use super::{error::Error, store::NodeStore};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    mem,
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+const MAGIC: &[u8; 8] = b"BPLUSPF1";
+
+// The file starts with two header slots, each holding the tree's metadata and
+// where the page table lives. Commits alternate between the slots, so a torn
+// header write leaves the other slot, and the tree it describes, intact.
+const HEADER_SIZE: u64 = 4096;
+const HEADER_SLOTS: u64 = 2;
+
+// Each slot is the magic, a checksum and length of the header, then the
+// header itself.
+const HEADER_PREFIX: usize = MAGIC.len() + 4 + 4;
+
+const MIN_PAGE_CAPACITY: u64 = 64;
+
+// Bytes each extent takes up in the serialized page table.
+const EXTENT_SIZE: u64 = 16;
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+struct Extent {
+    offset: u64,
+    capacity: u64,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+struct Page {
+    extent: Extent,
+    len: u64,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct Header {
+    sequence: u64,
+    table: Option<Page>,
+    metadata: BTreeMap<String, Vec<u8>>,
+}
+
+// Maps node ids to the pages holding them, and tracks the extents that no
+// committed page uses so they can be reused.
+#[derive(Deserialize, Serialize)]
+struct PageTable {
+    pages: HashMap<Uuid, Page>,
+    free: Vec<Extent>,
+    end: u64,
+}
+
+impl Default for PageTable {
+    fn default() -> Self {
+        Self {
+            pages: HashMap::new(),
+            free: Vec::new(),
+            end: HEADER_SIZE * HEADER_SLOTS,
+        }
+    }
+}
+
+impl PageTable {
+    fn alloc(&mut self, len: u64) -> Extent {
+        let capacity = len.next_power_of_two().max(MIN_PAGE_CAPACITY);
+
+        // First fit, splitting off whatever the page doesn't need.
+        if let Some(index) = self.free.iter().position(|free| free.capacity >= capacity) {
+            let free = &mut self.free[index];
+            let extent = Extent {
+                offset: free.offset,
+                capacity,
+            };
+
+            if free.capacity == capacity {
+                self.free.swap_remove(index);
+            } else {
+                free.offset += capacity;
+                free.capacity -= capacity;
+            }
+
+            return extent;
+        }
+
+        let extent = Extent {
+            offset: self.end,
+            capacity,
+        };
+        self.end += capacity;
+        extent
+    }
+}
+
+// Stores every node of a tree in a single file. Nodes are written to pages
+// found through the page table, which is itself written to a page whenever the
+// file is synced.
+//
+// Pages are copy-on-write: a node is never written over a page the last
+// committed table points to, and extents given up since the last commit only
+// become free once the next one lands. Until the new header is written, the
+// file still holds the previous tree in full.
+pub struct PageFileStore {
+    path: PathBuf,
+    file: Option<File>,
+    header: Header,
+    table: PageTable,
+    pending_free: Vec<Extent>,
+}
+
+impl PageFileStore {
+    // The file isn't created (or truncated) until something is written to it.
+    pub fn create(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().into(),
+            file: None,
+            header: Header::default(),
+            table: PageTable::default(),
+            pending_free: Vec::new(),
+        }
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path.as_ref())?;
+
+        let mut headers = Vec::new();
+        for slot in 0..HEADER_SLOTS {
+            headers.extend(Self::read_header(&mut file, slot)?);
+        }
+
+        let header = headers
+            .into_iter()
+            .max_by_key(|header| header.sequence)
+            .ok_or(Error::BadBPTree)?;
+
+        let mut this = Self {
+            path: path.as_ref().into(),
+            file: Some(file),
+            header,
+            table: PageTable::default(),
+            pending_free: Vec::new(),
+        };
+
+        if let Some(page) = this.header.table {
+            this.table = bincode::deserialize(&this.read_page(page)?)?;
+        }
+
+        Ok(this)
+    }
+
+    // Reads the header in a slot, or `None` if the slot was never written or
+    // its write was torn. Failing to read the file at all is an error.
+    fn read_header(file: &mut File, slot: u64) -> Result<Option<Header>, Error> {
+        let mut data = vec![0; HEADER_SIZE as usize];
+        file.seek(SeekFrom::Start(slot * HEADER_SIZE))?;
+        match file.read_exact(&mut data) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+
+        if &data[..MAGIC.len()] != MAGIC {
+            return Ok(None);
+        }
+
+        let checksum = u32::from_le_bytes(data[MAGIC.len()..MAGIC.len() + 4].try_into().unwrap());
+        let len = u32::from_le_bytes(data[MAGIC.len() + 4..HEADER_PREFIX].try_into().unwrap());
+        let header = match data.get(HEADER_PREFIX..HEADER_PREFIX + len as usize) {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+
+        if crc32fast::hash(header) != checksum {
+            return Ok(None);
+        }
+
+        Ok(bincode::deserialize(header).ok())
+    }
+
+    fn file(&mut self) -> Result<&mut File, Error> {
+        if self.file.is_none() {
+            self.file = Some(
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&self.path)?,
+            );
+        }
+
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    fn read_page(&self, page: Page) -> Result<Vec<u8>, Error> {
+        // Pages are only ever read after being written, by which point the
+        // file is open.
+        let mut file = self
+            .file
+            .as_ref()
+            .ok_or_else(|| io::Error::from(ErrorKind::NotFound))?;
+        let mut data = vec![0; page.len as usize];
+        file.seek(SeekFrom::Start(page.extent.offset))?;
+        file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn write_page(&mut self, extent: Extent, data: &[u8]) -> Result<(), Error> {
+        let file = self.file()?;
+        file.seek(SeekFrom::Start(extent.offset))?;
+        file.write_all(data)?;
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> Result<(), Error> {
+        let header = bincode::serialize(&self.header)?;
+        if HEADER_PREFIX + header.len() > HEADER_SIZE as usize {
+            return Err(Error::BadBPTree);
+        }
+
+        let mut data = Vec::with_capacity(HEADER_SIZE as usize);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&crc32fast::hash(&header).to_le_bytes());
+        data.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        data.extend_from_slice(&header);
+        data.resize(HEADER_SIZE as usize, 0);
+
+        let slot = self.header.sequence % HEADER_SLOTS;
+        let file = self.file()?;
+        file.seek(SeekFrom::Start(slot * HEADER_SIZE))?;
+        file.write_all(&data)?;
+
+        Ok(())
+    }
+
+    // Writes the table to `extent`, then the header that points to it.
+    fn commit(&mut self, extent: Extent) -> Result<(), Error> {
+        let table = bincode::serialize(&self.table)?;
+        self.write_page(extent, &table)?;
+        self.file()?.sync_data()?;
+
+        self.header.sequence += 1;
+        self.header.table = Some(Page {
+            extent,
+            len: table.len() as u64,
+        });
+        self.write_header()?;
+        self.file()?.sync_data()?;
+
+        Ok(())
+    }
+}
+
+impl NodeStore for PageFileStore {
+    fn read(&self, id: Uuid) -> Result<Vec<u8>, Error> {
+        let page = *self
+            .table
+            .pages
+            .get(&id)
+            .ok_or_else(|| io::Error::from(ErrorKind::NotFound))?;
+        self.read_page(page)
+    }
+
+    fn write(&mut self, id: Uuid, data: &[u8]) -> Result<(), Error> {
+        let extent = self.table.alloc(data.len() as u64);
+        self.write_page(extent, data)?;
+
+        let page = Page {
+            extent,
+            len: data.len() as u64,
+        };
+
+        if let Some(old) = self.table.pages.insert(id, page) {
+            self.pending_free.push(old.extent);
+        }
+
+        Ok(())
+    }
+
+    fn delete(&mut self, id: Uuid) -> Result<(), Error> {
+        if let Some(page) = self.table.pages.remove(&id) {
+            self.pending_free.push(page.extent);
+        }
+        Ok(())
+    }
+
+    fn get_metadata(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.header.metadata.get(name).cloned())
+    }
+
+    fn put_metadata(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        self.header.metadata.insert(name.into(), data.into());
+        Ok(())
+    }
+
+    // Writes out the page table, then commits by writing the header that
+    // points to it into the slot the last commit didn't use.
+    fn sync(&mut self) -> Result<(), Error> {
+        // Once this commit lands, nothing refers to the extents given up since
+        // the last one, including the old table's. The new table lists them as
+        // free, but they're only handed out again once the commit lands, since
+        // the old tree still uses them until then.
+        let pending = mem::take(&mut self.pending_free);
+        let mut released = pending.clone();
+        released.extend(self.header.table.map(|page| page.extent));
+
+        // Allocating can only shrink the table, so reserve room for it with
+        // the released extents counted up front.
+        let len = bincode::serialized_size(&self.table)? + released.len() as u64 * EXTENT_SIZE;
+        let extent = self.table.alloc(len);
+        let free = self.table.free.len();
+        self.table.free.extend(released);
+
+        let (sequence, table) = (self.header.sequence, self.header.table);
+        if let Err(err) = self.commit(extent) {
+            // The old tree may still be the committed one, so none of what it
+            // uses can be reused yet. Neither can the new table's extent, in
+            // case its header did land, until a later commit replaces it.
+            self.table.free.truncate(free);
+            self.pending_free = pending;
+            self.pending_free.push(extent);
+            self.header.sequence = sequence;
+            self.header.table = table;
+            return Err(err);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,762 @@
+use super::{
+    checksum,
+    codec::Codec,
+    compression::{self, Compression},
+    encryption::{self, Cipher, Key},
+    error::Error,
+    format::{COMPRESSION_VERSION, FORMAT_VERSION, KEYS_ONLY_VERSION},
+    node::{LegacyNode, Link, Node, NodeRef},
+    persist::VERSIONS_METADATA,
+    store::NodeStore,
+    wal::Wal,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    mem,
+    rc::Rc,
+};
+use uuid::Uuid;
+
+// Loads nodes on demand and keeps exactly one link per node.
+//
+// Deserializing a node produces fresh links for its children, parent and
+// sibling leaves. Left alone, a node reachable from more than one of those
+// links would be loaded (and mutated) as several independent copies, so every
+// link is interned here and duplicates are swapped for the canonical one.
+//
+// The pager also bounds how many nodes stay loaded. Clean nodes are evicted in
+// least-recently-used order by turning their link back into an unloaded one.
+// Dirty nodes are pinned until they are persisted, and internal nodes are
+// pinned while any of their children are loaded.
+pub(crate) struct Pager<K, V, S, C> {
+    // Shared with the tree's snapshots, which read from it too.
+    pub(crate) store: Rc<RefCell<S>>,
+    pub(crate) codec: C,
+    pub(crate) compression: Compression,
+    pub(crate) cipher: Option<Cipher>,
+
+    // The format version the tree's node files are laid out in.
+    pub(crate) version: u32,
+
+    // The keys of the nodes in an encrypted tree, learned from their parents
+    // as those are read.
+    pub(crate) node_keys: RefCell<HashMap<Uuid, Key>>,
+    pub(crate) keys_discovered: Cell<bool>,
+
+    // The sizes of the nodes written or read so far, before and after
+    // compression.
+    encoded_bytes: Cell<u64>,
+    stored_bytes: Cell<u64>,
+
+    entries: RefCell<HashMap<Uuid, Entry<K, V>>>,
+    capacity: Option<usize>,
+    loaded: Cell<usize>,
+    clock: Cell<u64>,
+    reclaimed: Vec<Uuid>,
+    pub(crate) wal: RefCell<Option<Wal<K, V>>>,
+
+    // The versions kept for snapshots, and where the set of them was last
+    // recorded.
+    pub(crate) versions: Rc<RefCell<KeptVersions>>,
+    versions_record: Option<Uuid>,
+}
+
+// Where a snapshot finds the nodes the tree has overwritten or deleted since
+// the snapshot was taken, by node id. Deleted nodes are simply kept, so they
+// map to themselves.
+pub(crate) type Versions = Rc<RefCell<HashMap<Uuid, Uuid>>>;
+
+// The versions kept for each snapshot. Snapshots share this with the tree, so
+// that the last one to need a version can delete it when it's dropped.
+#[derive(Default)]
+pub(crate) struct KeptVersions {
+    snapshots: Vec<Versions>,
+    // Versions kept since the store was last synced. The tree as last
+    // committed may still use them, so they wait for the next persist.
+    uncommitted: HashSet<Uuid>,
+    // Whether the versions kept have changed since they were last recorded.
+    pub(crate) is_dirty: bool,
+}
+
+impl KeptVersions {
+    // Deletes the versions only a dropped snapshot needed. Nothing the tree
+    // uses is among them, so they go straight away rather than with the next
+    // commit.
+    pub fn release<S: NodeStore>(
+        &mut self,
+        versions: &Versions,
+        store: &mut S,
+    ) -> Result<(), Error> {
+        // Other snapshots may share the same versions, on top of the
+        // reference held here.
+        if Rc::strong_count(versions) > 2 {
+            return Ok(());
+        }
+
+        let kept = self
+            .snapshots
+            .iter()
+            .filter(|snapshot| !Rc::ptr_eq(snapshot, versions))
+            .filter(|snapshot| Rc::strong_count(snapshot) > 1)
+            .flat_map(|snapshot| snapshot.borrow().values().copied().collect::<Vec<_>>())
+            .collect::<HashSet<_>>();
+
+        let mut released = versions.borrow_mut();
+        for (uuid, version) in released.clone() {
+            if !self.uncommitted.contains(&version) {
+                if !kept.contains(&version) {
+                    store.discard(version)?;
+                }
+                released.remove(&uuid);
+                self.is_dirty = true;
+            }
+        }
+
+        // Whatever is left is deleted by the next persist.
+        if released.is_empty() {
+            self.snapshots
+                .retain(|snapshot| !Rc::ptr_eq(snapshot, versions));
+        }
+
+        Ok(())
+    }
+}
+
+struct Entry<K, V> {
+    link: Link<K, V>,
+    last_used: u64,
+}
+
+// The nodes changed since the last persist, encoded, along with the pager's
+// own record of what changed.
+pub(crate) struct Changes {
+    nodes: Vec<Vec<u8>>,
+    reclaimed: Vec<Uuid>,
+    node_keys: HashMap<Uuid, Key>,
+    keys_discovered: bool,
+}
+
+impl<K, V, S: NodeStore, C: Codec> Pager<K, V, S, C> {
+    pub fn new(store: S, codec: C) -> Self {
+        Self {
+            store: Rc::new(RefCell::new(store)),
+            codec,
+            compression: Compression::None,
+            cipher: None,
+            version: FORMAT_VERSION,
+            node_keys: RefCell::new(HashMap::new()),
+            keys_discovered: Cell::new(false),
+            encoded_bytes: Cell::new(0),
+            stored_bytes: Cell::new(0),
+            entries: RefCell::new(HashMap::new()),
+            capacity: None,
+            loaded: Cell::new(0),
+            clock: Cell::new(0),
+            reclaimed: Vec::new(),
+            wal: RefCell::new(None),
+            versions: Rc::default(),
+            versions_record: None,
+        }
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+    }
+
+    pub fn loaded(&self) -> usize {
+        self.loaded.get()
+    }
+
+    pub fn is_compressible(&self) -> bool {
+        self.version >= COMPRESSION_VERSION
+    }
+
+    // Whether leaves store zero-sized values, as they did in older versions.
+    pub fn has_legacy_leaves(&self) -> bool {
+        self.version < KEYS_ONLY_VERSION && mem::size_of::<V>() == 0
+    }
+
+    pub fn encoded_bytes(&self) -> u64 {
+        self.encoded_bytes.get()
+    }
+
+    pub fn stored_bytes(&self) -> u64 {
+        self.stored_bytes.get()
+    }
+
+    pub fn count_bytes(&self, encoded: usize, stored: usize) {
+        self.encoded_bytes
+            .set(self.encoded_bytes.get() + encoded as u64);
+        self.stored_bytes
+            .set(self.stored_bytes.get() + stored as u64);
+    }
+
+    fn tick(&self) -> u64 {
+        let tick = self.clock.get() + 1;
+        self.clock.set(tick);
+        tick
+    }
+
+    pub fn alloc(&self, node: Node<K, V>) -> Link<K, V> {
+        let uuid = node.uuid();
+        let link = Link::new(node);
+
+        self.entries.borrow_mut().insert(
+            uuid,
+            Entry {
+                link,
+                last_used: self.tick(),
+            },
+        );
+        self.loaded.set(self.loaded.get() + 1);
+
+        link
+    }
+
+    pub fn intern(&self, link: Link<K, V>) -> Link<K, V> {
+        let uuid = unsafe { (*link.as_ptr()).uuid() };
+        let mut entries = self.entries.borrow_mut();
+
+        match entries.get(&uuid) {
+            Some(entry) if entry.link != link => {
+                link.free();
+                entry.link
+            }
+            Some(entry) => entry.link,
+            None => {
+                entries.insert(uuid, Entry { link, last_used: 0 });
+                link
+            }
+        }
+    }
+
+    // Marks a node as recently used.
+    pub fn touch(&self, uuid: Uuid) {
+        let tick = self.tick();
+        if let Some(entry) = self.entries.borrow_mut().get_mut(&uuid) {
+            entry.last_used = tick;
+        }
+    }
+
+    pub fn load(&self, uuid: Uuid) -> Result<Node<K, V>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let mut node = self.read(uuid)?;
+        self.intern_links(&mut node);
+        self.loaded.set(self.loaded.get() + 1);
+
+        Ok(node)
+    }
+
+    fn intern_links(&self, node: &mut Node<K, V>) {
+        match node {
+            Node::Internal(node) => {
+                for child in node.children.iter_mut() {
+                    *child = self.intern(*child);
+                }
+                node.parent = node.parent.map(|parent| self.intern(parent));
+            }
+            Node::Leaf(node) => {
+                node.parent = node.parent.map(|parent| self.intern(parent));
+                node.next_leaf = node.next_leaf.map(|next_leaf| self.intern(next_leaf));
+                node.prev_leaf = node.prev_leaf.map(|prev_leaf| self.intern(prev_leaf));
+            }
+        }
+    }
+
+    // Reads a node from the store without interning its links.
+    pub fn read(&self, uuid: Uuid) -> Result<Node<K, V>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let data = self.store.borrow().read(uuid)?;
+        let stored = checksum::unseal(&data).ok_or(Error::Corruption { node: uuid })?;
+
+        let decrypted;
+        let mut child_keys = None;
+        let stored = match &self.cipher {
+            Some(_) if self.has_node_keys() => {
+                let key = self.find_node_key(uuid)?;
+                decrypted = Cipher::new(&key)
+                    .decrypt(uuid.as_bytes(), stored)
+                    .ok_or(Error::Authentication { node: uuid })?;
+
+                let (keys, stored) =
+                    encryption::split_keys(&decrypted).ok_or(Error::Corruption { node: uuid })?;
+                child_keys = Some(keys);
+                stored
+            }
+            Some(cipher) => {
+                decrypted = cipher
+                    .decrypt(uuid.as_bytes(), stored)
+                    .ok_or(Error::Authentication { node: uuid })?;
+                &decrypted[..]
+            }
+            None => stored,
+        };
+
+        let data = if self.is_compressible() {
+            compression::decompress(stored).ok_or(Error::Corruption { node: uuid })?
+        } else {
+            stored.to_vec()
+        };
+        self.count_bytes(data.len(), stored.len());
+
+        let node: Node<K, V> = if self.has_legacy_leaves() {
+            self.codec.decode::<LegacyNode<K, V>>(&data)?.into()
+        } else {
+            self.codec.decode(&data)?
+        };
+
+        // A node file holding some other node is as damaged as a torn one.
+        if node.uuid() != uuid {
+            node.free_links();
+            return Err(Error::Corruption { node: uuid });
+        }
+
+        if let Some(child_keys) = child_keys {
+            if let Err(err) = self.learn_node_keys(&node, child_keys) {
+                node.free_links();
+                return Err(err);
+            }
+        }
+
+        Ok(node)
+    }
+
+    pub fn load_metadata<T>(&self, name: &str) -> Result<T, Error>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let data = self
+            .store
+            .borrow()
+            .get_metadata(name)?
+            .ok_or(Error::BadBPTree)?;
+        self.open_metadata(name, &data)
+    }
+
+    pub fn store_metadata<T: Serialize>(&mut self, name: &str, value: &T) -> Result<(), Error> {
+        let data = self.seal_metadata(name, value)?;
+        self.store.borrow_mut().put_metadata(name, &data)
+    }
+
+    fn open_metadata<T>(&self, name: &str, data: &[u8]) -> Result<T, Error>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        let data = checksum::unseal(data)
+            .ok_or_else(|| Error::MetadataCorruption { name: name.into() })?;
+
+        match &self.cipher {
+            Some(cipher) => {
+                let data = cipher
+                    .decrypt(name.as_bytes(), data)
+                    .ok_or_else(|| Error::MetadataAuthentication { name: name.into() })?;
+                self.codec.decode(&data)
+            }
+            None => self.codec.decode(data),
+        }
+    }
+
+    fn seal_metadata<T: Serialize>(&self, name: &str, value: &T) -> Result<Vec<u8>, Error> {
+        let data = self.codec.encode(value)?;
+        let data = match &self.cipher {
+            Some(cipher) => cipher.encrypt(name.as_bytes(), &data),
+            None => data,
+        };
+        Ok(checksum::seal(&data))
+    }
+
+    // Frees a node that has been removed from the tree. It stays in the store
+    // until the next persist, since the last persisted tree may still use it.
+    pub fn reclaim(&mut self, link: Link<K, V>) {
+        let uuid = unsafe { (*link.as_ptr()).uuid() };
+        self.reclaimed.push(uuid);
+        self.forget_node_key(uuid);
+
+        self.entries.borrow_mut().remove(&uuid);
+        if unsafe { matches!(*link.as_ptr(), NodeRef::Loaded(_)) } {
+            self.loaded.set(self.loaded.get() - 1);
+        }
+
+        link.free();
+    }
+
+    // Deletes the nodes reclaimed since the last persist from the store.
+    pub fn delete_reclaimed(&mut self) -> Result<(), Error> {
+        for uuid in mem::take(&mut self.reclaimed) {
+            self.node_keys.get_mut().remove(&uuid);
+            if !self.keep_version(uuid, true)? {
+                self.store.borrow_mut().delete(uuid)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Whether any node besides `nodes` has changed since the last persist,
+    // counting nodes removed from the tree.
+    pub fn is_dirty_outside(&self, nodes: &[Uuid]) -> bool {
+        !self.reclaimed.is_empty()
+            || self.entries.borrow().iter().any(|(uuid, entry)| unsafe {
+                matches!(&*entry.link.as_ptr(), NodeRef::Loaded(node) if node.is_dirty())
+                    && !nodes.contains(uuid)
+            })
+    }
+
+    // Copies out everything changed since the last persist, so it can be put
+    // back after later changes are thrown away.
+    pub fn save_changes(&self) -> Result<Changes, Error>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        let mut nodes = Vec::new();
+        for entry in self.entries.borrow().values() {
+            if let NodeRef::Loaded(node) = unsafe { &*entry.link.as_ptr() } {
+                if node.is_dirty() {
+                    nodes.push(self.codec.encode(node)?);
+                }
+            }
+        }
+
+        Ok(Changes {
+            nodes,
+            reclaimed: self.reclaimed.clone(),
+            node_keys: self.node_keys.borrow().clone(),
+            keys_discovered: self.keys_discovered.get(),
+        })
+    }
+
+    // Forgets every loaded node and puts back the changes saved earlier, as
+    // long as nothing has been persisted since. Every other node is read back
+    // from the store as it was last persisted.
+    pub fn restore_changes(&mut self, changes: Changes) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        for (_, entry) in self.entries.get_mut().drain() {
+            entry.link.free();
+        }
+        self.loaded.set(0);
+        self.reclaimed = changes.reclaimed;
+        *self.node_keys.get_mut() = changes.node_keys;
+        self.keys_discovered.set(changes.keys_discovered);
+
+        for data in changes.nodes {
+            let mut node: Node<K, V> = self.codec.decode(&data)?;
+            match &mut node {
+                Node::Internal(node) => node.is_dirty = true,
+                Node::Leaf(node) => node.is_dirty = true,
+            }
+            self.intern_links(&mut node);
+
+            // Nodes restored earlier may already link to this one.
+            let link = self
+                .entries
+                .get_mut()
+                .get(&node.uuid())
+                .map(|entry| entry.link);
+            match link {
+                Some(link) => {
+                    unsafe { *link.as_ptr() = NodeRef::Loaded(node) };
+                    self.loaded.set(self.loaded.get() + 1);
+                }
+                None => {
+                    self.alloc(node);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn write_node(&mut self, uuid: Uuid, data: &[u8]) -> Result<(), Error> {
+        self.keep_version(uuid, false)?;
+        self.store.borrow_mut().write(uuid, data)
+    }
+
+    // Starts keeping the versions of nodes a new snapshot will need. Until
+    // some node is overwritten or deleted, snapshots need the same versions,
+    // so they share them.
+    pub fn track_versions(&self) -> Versions {
+        let snapshots = &mut self.versions.borrow_mut().snapshots;
+        match snapshots.last() {
+            Some(last) if last.borrow().is_empty() => Rc::clone(last),
+            _ => {
+                let new = Versions::default();
+                snapshots.push(Rc::clone(&new));
+                new
+            }
+        }
+    }
+
+    // Keeps the stored version of a node about to be overwritten or deleted
+    // for the snapshots that don't have one of it yet. Returns whether any
+    // did, in which case a deleted node has to stay in the store.
+    fn keep_version(&mut self, uuid: Uuid, deleting: bool) -> Result<bool, Error> {
+        // Snapshots dropped since the last persist don't need anything.
+        let needed = self
+            .versions
+            .borrow()
+            .snapshots
+            .iter()
+            .filter(|versions| Rc::strong_count(versions) > 1)
+            .filter(|versions| !versions.borrow().contains_key(&uuid))
+            .cloned()
+            .collect::<Vec<_>>();
+        if needed.is_empty() {
+            return Ok(false);
+        }
+
+        let version = if deleting {
+            uuid
+        } else {
+            let data = match self.store.borrow().read(uuid) {
+                Ok(data) => data,
+                // A node that was never persisted has no version to keep.
+                Err(Error::IO(err)) if err.kind() == ErrorKind::NotFound => return Ok(false),
+                Err(err) => return Err(err),
+            };
+            let version = Uuid::new_v4();
+            self.store.borrow_mut().write(version, &data)?;
+            version
+        };
+
+        for versions in needed {
+            versions.borrow_mut().insert(uuid, version);
+        }
+        let mut kept = self.versions.borrow_mut();
+        kept.uncommitted.insert(version);
+        kept.is_dirty = true;
+
+        Ok(true)
+    }
+
+    // Deletes the versions only dropped snapshots needed, and records which
+    // ones are still kept, so they can be deleted after a crash.
+    pub fn collect_versions(&mut self) -> Result<(), Error> {
+        let (live, dropped): (Vec<_>, Vec<_>) = self
+            .versions
+            .borrow_mut()
+            .snapshots
+            .drain(..)
+            .partition(|versions| Rc::strong_count(versions) > 1);
+        self.versions.borrow_mut().snapshots = live;
+
+        if dropped.is_empty() && !self.versions.borrow().is_dirty {
+            return Ok(());
+        }
+
+        let kept = self.kept_versions();
+        for versions in dropped {
+            for version in versions.borrow().values() {
+                if !kept.contains(version) {
+                    self.store.borrow_mut().delete(*version)?;
+                }
+            }
+        }
+
+        // There can be more versions than a store has room for in its
+        // metadata, so the list is written like a node, and the metadata only
+        // says where.
+        let mut kept = kept.into_iter().collect::<Vec<_>>();
+        kept.sort_unstable();
+        let record = match kept.is_empty() {
+            true => None,
+            false => {
+                let uuid = Uuid::new_v4();
+                let data = self.seal_metadata(VERSIONS_METADATA, &kept)?;
+                self.store.borrow_mut().write(uuid, &data)?;
+                Some(uuid)
+            }
+        };
+        if let Some(uuid) = mem::replace(&mut self.versions_record, record) {
+            self.store.borrow_mut().delete(uuid)?;
+        }
+        self.store_metadata(VERSIONS_METADATA, &record)?;
+        self.versions.borrow_mut().is_dirty = false;
+
+        Ok(())
+    }
+
+    // Called once the store has been synced, after which the tree no longer
+    // uses any of the versions kept.
+    pub fn commit_versions(&mut self) {
+        self.versions.borrow_mut().uncommitted.clear();
+    }
+
+    // Versions recorded as kept when the tree was last persisted have no
+    // snapshots left to use them, so they're deleted by the next persist.
+    pub fn forget_versions(&mut self) -> Result<(), Error> {
+        if self
+            .store
+            .borrow()
+            .get_metadata(VERSIONS_METADATA)?
+            .is_none()
+        {
+            return Ok(());
+        }
+
+        let record: Option<Uuid> = self.load_metadata(VERSIONS_METADATA)?;
+        if let Some(uuid) = record {
+            let data = self.store.borrow().read(uuid)?;
+            let versions: Vec<Uuid> = self.open_metadata(VERSIONS_METADATA, &data)?;
+            let dropped = versions.into_iter().map(|version| (version, version));
+            self.versions
+                .borrow_mut()
+                .snapshots
+                .push(Rc::new(RefCell::new(dropped.collect())));
+            self.versions_record = Some(uuid);
+        }
+
+        Ok(())
+    }
+
+    fn kept_versions(&self) -> HashSet<Uuid> {
+        self.versions
+            .borrow()
+            .snapshots
+            .iter()
+            .flat_map(|versions| versions.borrow().values().copied().collect::<Vec<_>>())
+            .collect()
+    }
+
+    // Encodes a record of an insert if there's a log to append it to.
+    pub fn encode_insert(&self, key: &K, value: &V) -> Result<Option<Vec<u8>>, Error> {
+        self.wal
+            .borrow()
+            .as_ref()
+            .map(|wal| wal.encode_insert(key, value))
+            .transpose()
+    }
+
+    pub fn encode_remove(&self, key: &K) -> Result<Option<Vec<u8>>, Error> {
+        self.wal
+            .borrow()
+            .as_ref()
+            .map(|wal| wal.encode_remove(key))
+            .transpose()
+    }
+
+    pub fn log(&self, record: Option<Vec<u8>>) -> Result<(), Error> {
+        match (self.wal.borrow_mut().as_mut(), record) {
+            (Some(wal), Some(record)) => wal.append(&record),
+            _ => Ok(()),
+        }
+    }
+
+    // Evicts clean nodes until the cache is back within its capacity. This
+    // takes `&mut self` since evicting a node invalidates any references into
+    // it.
+    pub fn evict(&mut self) {
+        let capacity = match self.capacity {
+            Some(capacity) => capacity,
+            None => return,
+        };
+
+        // Evicting children can make their parents evictable, so keep going
+        // until nothing more can be evicted.
+        while self.loaded.get() > capacity {
+            if !self.evict_pass(capacity) {
+                break;
+            }
+        }
+    }
+
+    // Leaves can be loaded through their neighbours instead of their parents,
+    // so a leaf changed that way isn't always reachable through loaded nodes.
+    // Loads the ancestors of every dirty node, so that persisting from the
+    // root finds them all.
+    pub fn load_dirty_ancestors(&self) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let dirty = self
+            .entries
+            .borrow()
+            .values()
+            .filter(|entry| unsafe {
+                matches!(&*entry.link.as_ptr(), NodeRef::Loaded(node) if node.is_dirty())
+            })
+            .map(|entry| entry.link)
+            .collect::<Vec<_>>();
+
+        for link in dirty {
+            let mut cursor = link;
+            loop {
+                let parent = match unsafe { (*cursor.as_ptr()).access(self)? } {
+                    Node::Internal(node) => node.parent,
+                    Node::Leaf(node) => node.parent,
+                };
+                match parent {
+                    Some(parent) => cursor = parent,
+                    None => break,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn evict_pass(&mut self, capacity: usize) -> bool {
+        let mut candidates = self
+            .entries
+            .get_mut()
+            .values()
+            .filter(|entry| unsafe {
+                match &*entry.link.as_ptr() {
+                    NodeRef::Loaded(Node::Internal(node)) => {
+                        // Internal nodes go after their children so that
+                        // loaded nodes are always reachable through loaded
+                        // parents.
+                        !node.is_dirty
+                            && node
+                                .children
+                                .iter()
+                                .all(|child| matches!(*child.as_ptr(), NodeRef::Unloaded(_)))
+                    }
+                    NodeRef::Loaded(Node::Leaf(node)) => !node.is_dirty,
+                    NodeRef::Unloaded(_) => false,
+                }
+            })
+            .map(|entry| (entry.last_used, entry.link))
+            .collect::<Vec<_>>();
+
+        if candidates.is_empty() {
+            return false;
+        }
+
+        candidates.sort_unstable_by_key(|(last_used, _)| *last_used);
+
+        for (_, link) in candidates.into_iter().take(self.loaded.get() - capacity) {
+            unsafe {
+                let uuid = (*link.as_ptr()).uuid();
+                *link.as_ptr() = NodeRef::Unloaded(uuid);
+            }
+            self.loaded.set(self.loaded.get() - 1);
+        }
+
+        true
+    }
+}
+
+impl<K, V, S, C> Drop for Pager<K, V, S, C> {
+    fn drop(&mut self) {
+        for (_, entry) in self.entries.get_mut().drain() {
+            entry.link.free();
+        }
+    }
+}
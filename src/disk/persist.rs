@@ -1,118 +1,272 @@
-use super::{error::Error, node::Node, BPTree};
-use path_macro::path;
-use serde::{Deserialize, Serialize};
-use std::{
-    borrow::Borrow,
-    fs,
-    path::{Path, PathBuf},
+use super::{
+    codec::{Bincode, Codec},
+    directory::DirStore,
+    encryption::{Cipher, Key},
+    error::Error,
+    format::{Format, LoadOptions, FORMAT_VERSION},
+    node::{Link, Node, NodeRef},
+    pager::Pager,
+    store::NodeStore,
+    unversioned::migrate_unversioned,
+    BPTree,
 };
+use serde::{Deserialize, Serialize};
+use std::{borrow::Borrow, path::Path};
+
+pub(crate) const ROOT_METADATA: &str = "root";
+pub(crate) const ROOT_KEY_METADATA: &str = "root_key";
+pub(crate) const ORDER_METADATA: &str = "order";
+pub(crate) const LEN_METADATA: &str = "len";
+pub(crate) const VERSIONS_METADATA: &str = "versions";
+
+impl<K, V> BPTree<K, V, DirStore> {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error>
+    where
+        for<'de> K: Deserialize<'de> + Serialize + Ord + Clone,
+        for<'de> V: Deserialize<'de> + Serialize,
+    {
+        Self::load_with(path, LoadOptions::new())
+    }
+
+    pub fn load_with(
+        path: impl AsRef<Path>,
+        mut options: LoadOptions<DirStore>,
+    ) -> Result<Self, Error>
+    where
+        for<'de> K: Deserialize<'de> + Serialize + Ord + Clone,
+        for<'de> V: Deserialize<'de> + Serialize,
+    {
+        // Trees written before the format was recorded were always bincode
+        // directories, so they can be upgraded in place.
+        options.migration.get_or_insert(migrate_unversioned::<K, V>);
+        Self::load_with_codec(path, Bincode, options)
+    }
+}
+
+impl<K, V, C: Codec> BPTree<K, V, DirStore, C> {
+    pub fn load_with_codec(
+        path: impl AsRef<Path>,
+        codec: C,
+        options: LoadOptions<DirStore>,
+    ) -> Result<Self, Error>
+    where
+        for<'de> K: Deserialize<'de> + Serialize + Ord + Clone,
+        for<'de> V: Deserialize<'de> + Serialize,
+    {
+        let mut tree = Self::load_from_store_with_codec(DirStore::open(&path)?, codec, options)?;
 
-impl<K, V> BPTree<K, V> {
-    fn root_metadata_path(path: &Path) -> PathBuf {
-        path![path / "root"]
+        if Self::has_wal(path.as_ref()) {
+            tree.enable_wal()?;
+        }
+
+        Ok(tree)
     }
+}
 
-    fn order_metadata_path(path: &Path) -> PathBuf {
-        path![path / "order"]
+impl<K, V, S: NodeStore, C: Codec> BPTree<K, V, S, C> {
+    pub fn load_from_store(store: S) -> Result<Self, Error>
+    where
+        C: Default,
+    {
+        Self::load_from_store_with(store, LoadOptions::new())
     }
 
-    fn len_metadata_path(path: &Path) -> PathBuf {
-        path![path / "len"]
+    pub fn load_from_store_with(store: S, options: LoadOptions<S>) -> Result<Self, Error>
+    where
+        C: Default,
+    {
+        Self::load_from_store_with_codec(store, C::default(), options)
     }
 
-    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
-        let root = bincode::deserialize(
-            &fs::read(Self::root_metadata_path(path.as_ref())).map_err(|_| Error::BadBPTree)?,
-        )
-        .map_err(|_| Error::Serde)?;
+    pub fn load_from_store_with_codec(
+        mut store: S,
+        codec: C,
+        options: LoadOptions<S>,
+    ) -> Result<Self, Error> {
+        let format = Format::load(&mut store, C::NAME, &options)?;
+        let mut pager = Pager::new(store, codec);
+        pager.version = format.version;
+        pager.compression = format.compression;
+        pager.cipher = options.key.as_ref().map(Cipher::new);
 
-        let order = bincode::deserialize(
-            &fs::read(Self::order_metadata_path(path.as_ref())).map_err(|_| Error::BadBPTree)?,
-        )
-        .map_err(|_| Error::Serde)?;
+        let root: Option<Link<K, V>> = pager.load_metadata(ROOT_METADATA)?;
+        let order = pager.load_metadata(ORDER_METADATA)?;
+        let len = pager.load_metadata(LEN_METADATA)?;
 
-        let len = bincode::deserialize(
-            &fs::read(Self::len_metadata_path(path.as_ref())).map_err(|_| Error::BadBPTree)?,
-        )
-        .map_err(|_| Error::Serde)?;
+        // Snapshots don't survive the tree being reloaded, so any versions
+        // still kept for them are deleted by the next persist.
+        pager.forget_versions()?;
+
+        if let Some(root) = root {
+            if pager.has_node_keys() {
+                let key = pager
+                    .load_metadata::<Option<Key>>(ROOT_KEY_METADATA)?
+                    .ok_or(Error::BadBPTree)?;
+                pager.learn_root_key(unsafe { (*root.as_ptr()).uuid() }, key);
+            }
+        }
 
         Ok(BPTree {
-            path: path.as_ref().into(),
-            root,
+            root: root.map(|root| pager.intern(root)),
+            pager,
             root_is_dirty: false,
             order,
             order_is_dirty: false,
             len,
             len_is_dirty: false,
+            schema: format.schema,
+            format_is_dirty: false,
+            has_persisted: true,
         })
     }
 
     fn persist_metadata(&mut self) -> Result<(), Error> {
-        fs::create_dir_all(&self.path)?;
+        // Even a persist that fails part way may have written something.
+        self.has_persisted = true;
+
+        if self.format_is_dirty {
+            let mut format = Format::new(self.pager.version, C::NAME);
+            format.schema = self.schema.clone();
+            format.compression = self.pager.compression;
+            format.encrypted = self.pager.cipher.is_some();
+            format.store(&mut *self.pager.store.borrow_mut())?;
+            self.format_is_dirty = false;
+        }
 
         if self.root_is_dirty {
-            fs::write(
-                Self::root_metadata_path(&self.path),
-                bincode::serialize(&self.root).map_err(|_| Error::Serde)?,
-            )?;
+            self.pager.store_metadata(ROOT_METADATA, &self.root)?;
             self.root_is_dirty = false;
         }
 
         if self.order_is_dirty {
-            fs::write(
-                Self::order_metadata_path(&self.path),
-                bincode::serialize(&self.order).map_err(|_| Error::Serde)?,
-            )?;
+            self.pager.store_metadata(ORDER_METADATA, &self.order)?;
             self.order_is_dirty = false;
         }
 
         if self.len_is_dirty {
-            fs::write(
-                Self::len_metadata_path(&self.path),
-                bincode::serialize(&self.len).map_err(|_| Error::Serde)?,
-            )?;
+            self.pager.store_metadata(LEN_METADATA, &self.len)?;
             self.len_is_dirty = false;
         }
 
         Ok(())
     }
 
-    unsafe fn persist_recursive(&mut self, node: &mut Node<K, V>) -> Result<(), Error>
+    // Stores the key the root was last written with, under the tree's key.
+    fn persist_root_key(&mut self) -> Result<(), Error> {
+        let root_key = self
+            .root
+            .map(|root| self.pager.node_key(unsafe { (*root.as_ptr()).uuid() }));
+        self.pager.store_metadata(ROOT_KEY_METADATA, &root_key)
+    }
+
+    // Returns whether the node was written.
+    unsafe fn persist_recursive(&mut self, node: &mut Node<K, V>) -> Result<bool, Error>
     where
         for<'de> K: Deserialize<'de> + Serialize,
         for<'de> V: Deserialize<'de> + Serialize,
     {
+        let mut child_written = false;
+
         if let Node::Internal(node) = node {
             for child in &node.children {
-                self.persist_recursive((*child.as_ptr()).access_mut(&self.path)?)?;
+                // Only loaded nodes can be dirty, and the pager never evicts a
+                // node with loaded children, so unloaded subtrees are clean.
+                if let NodeRef::Loaded(child) = &mut *child.as_ptr() {
+                    child_written |= self.persist_recursive(child)?;
+                }
             }
         }
 
-        let is_dirty = match node {
-            Node::Internal(node) => node.is_dirty,
-            Node::Leaf(node) => node.is_dirty,
-        };
+        // A written child has a new key, which only its parent holds.
+        if node.is_dirty() || (child_written && self.pager.has_node_keys()) {
+            node.persist(&mut self.pager)?;
+            return Ok(true);
+        }
 
-        if is_dirty {
-            node.persist(&self.path)?;
+        Ok(false)
+    }
+
+    pub fn persist(&mut self) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Serialize,
+        for<'de> V: Deserialize<'de> + Serialize,
+    {
+        let root_is_dirty = self.root_is_dirty;
+        self.persist_metadata()?;
+        self.pager.load_dirty_ancestors()?;
+
+        let mut root_written = false;
+        if let Some(root) = self.root {
+            unsafe {
+                if let NodeRef::Loaded(root) = &mut *root.as_ptr() {
+                    root_written = self.persist_recursive(root)?;
+                }
+            }
+        }
+
+        if self.pager.has_node_keys() && (root_is_dirty || root_written) {
+            self.persist_root_key()?;
         }
 
+        // The store commits everything at once when synced, so the nodes the
+        // new tree no longer uses can go in the same commit.
+        self.pager.delete_reclaimed()?;
+        self.pager.collect_versions()?;
+        self.pager.store.borrow_mut().sync()?;
+        self.pager.commit_versions();
+
+        // Everything logged is now in the store.
+        if let Some(wal) = self.pager.wal.get_mut() {
+            wal.truncate()?;
+        }
+        self.pager.evict();
+
         Ok(())
     }
 
-    pub fn persist(&mut self) -> Result<(), Error>
+    // Moves the tree over to a new key, which must be passed to load it from
+    // now on. The root gets a new key too, so once the old key is destroyed,
+    // the nodes the tree removed or rewrote while under it can't be decrypted,
+    // even from copies of the store taken back then.
+    pub fn rotate_key(&mut self, key: Key) -> Result<(), Error>
     where
         for<'de> K: Deserialize<'de> + Serialize,
         for<'de> V: Deserialize<'de> + Serialize,
     {
-        let root = match self.root {
-            Some(root) => root,
-            None => return Ok(()),
-        };
+        if !self.pager.has_node_keys() {
+            return Err(match self.pager.cipher {
+                Some(_) => Error::UnsupportedVersion {
+                    found: self.pager.version,
+                    supported: FORMAT_VERSION,
+                },
+                None => Error::EncryptionMismatch {
+                    expected: true,
+                    found: false,
+                },
+            });
+        }
 
-        self.persist_metadata()?;
+        self.persist()?;
 
-        unsafe { self.persist_recursive((*root.as_ptr()).access_mut(&self.path)?) }
+        let cipher = Cipher::new(&key);
+        if let Some(wal) = self.pager.wal.get_mut() {
+            wal.cipher = Some(cipher.clone());
+        }
+        self.pager.cipher = Some(cipher);
+
+        if let Some(root) = self.root {
+            match unsafe { (*root.as_ptr()).access_mut(&self.pager)? } {
+                Node::Internal(node) => node.is_dirty = true,
+                Node::Leaf(node) => node.is_dirty = true,
+            }
+        }
+        self.root_is_dirty = true;
+        self.order_is_dirty = true;
+        self.len_is_dirty = true;
+        self.format_is_dirty = true;
+        self.pager.versions.borrow_mut().is_dirty = true;
+
+        self.persist()
     }
 
     pub fn persist_key<Q>(&mut self, key: &Q) -> Result<(), Error>
@@ -121,22 +275,20 @@ impl<K, V> BPTree<K, V> {
         for<'de> V: Deserialize<'de> + Serialize,
         Q: Ord,
     {
-        let mut key_persisted = false;
+        let mut path = Vec::new();
         let mut cursor = self.root.ok_or(Error::UnknownKey)?;
 
-        self.persist_metadata()?;
-
-        while !key_persisted {
-            let node = unsafe { (*cursor.as_ptr()).access_mut(&self.path)? };
+        loop {
+            let node = unsafe { (*cursor.as_ptr()).access_mut(&self.pager)? };
+            path.push(node as *mut Node<K, V>);
 
-            let is_dirty = match node {
+            match node {
                 Node::Internal(node) => {
                     let index = match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
                         Ok(index) => index + 1,
                         Err(index) => index,
                     };
                     cursor = node.children[index];
-                    node.is_dirty
                 }
                 Node::Leaf(node) => {
                     if node
@@ -146,16 +298,47 @@ impl<K, V> BPTree<K, V> {
                     {
                         return Err(Error::UnknownKey);
                     }
-                    key_persisted = true;
-                    node.is_dirty
+                    break;
                 }
+            }
+        }
+
+        // The root and length stored alongside the path describe the whole
+        // tree, so writing just the path is only consistent if nothing else
+        // has changed, as when a split or merge touched other nodes.
+        let uuids = path
+            .iter()
+            .map(|node| unsafe { (**node).uuid() })
+            .collect::<Vec<_>>();
+        if self.pager.is_dirty_outside(&uuids) {
+            return self.persist();
+        }
+
+        let root_is_dirty = self.root_is_dirty;
+        self.persist_metadata()?;
+
+        // Nodes are written from the leaf up, so that parents hold the keys
+        // their children were just written with.
+        let mut child_written = false;
+        for node in path.into_iter().rev() {
+            let node = unsafe { &mut *node };
+            child_written = if node.is_dirty() || (child_written && self.pager.has_node_keys()) {
+                node.persist(&mut self.pager)?;
+                true
+            } else {
+                false
             };
+        }
 
-            if is_dirty {
-                node.persist(&self.path)?;
-            }
+        if self.pager.has_node_keys() && (root_is_dirty || child_written) {
+            self.persist_root_key()?;
         }
 
+        self.pager.collect_versions()?;
+        self.pager.store.borrow_mut().sync()?;
+        self.pager.commit_versions();
+        self.pager.evict();
+
         Ok(())
     }
 }
@@ -0,0 +1,460 @@
+use super::{
+    codec::{Bincode, Codec},
+    directory::DirStore,
+    error::Error,
+    guard::ValueMutationGuard,
+    iter::last_leaf,
+    node::{Link, Node},
+    pager::Pager,
+    store::NodeStore,
+    BPTree,
+};
+use serde::Deserialize;
+use std::{
+    borrow::Borrow,
+    ops::{Bound, RangeBounds},
+};
+
+// A leaf and the index of an entry within it. The index of the back of a range
+// counts entries from the end of the leaf.
+type Position<K, V> = (Link<K, V>, usize);
+
+impl<K, V, S: NodeStore, C: Codec> BPTree<K, V, S, C> {
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, V, S, C>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+        R: RangeBounds<Q>,
+    {
+        let mut iter = Range {
+            front: None,
+            front_index: 0,
+            back: None,
+            back_index: 0,
+            error: None,
+            pager: &self.pager,
+        };
+
+        match self.seek_range(&range) {
+            Ok(Some(((front, front_index), (back, back_index)))) => {
+                iter.front = Some(front);
+                iter.front_index = front_index;
+                iter.back = Some(back);
+                iter.back_index = back_index;
+            }
+            Ok(None) => {}
+            Err(err) => iter.error = Some(err),
+        }
+
+        iter
+    }
+
+    pub fn range_mut<Q, R>(&mut self, range: R) -> RangeMut<'_, K, V, S, C>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+        R: RangeBounds<Q>,
+    {
+        let mut iter = RangeMut {
+            front: None,
+            front_index: 0,
+            back: None,
+            back_index: 0,
+            error: None,
+            pager: &self.pager,
+        };
+
+        match self.seek_range(&range) {
+            Ok(Some(((front, front_index), (back, back_index)))) => {
+                iter.front = Some(front);
+                iter.front_index = front_index;
+                iter.back = Some(back);
+                iter.back_index = back_index;
+            }
+            Ok(None) => {}
+            Err(err) => iter.error = Some(err),
+        }
+
+        iter
+    }
+
+    // Finds the leaf positions of the first and last entries in the range, or
+    // `None` if the range is empty.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn seek_range<Q, R>(
+        &self,
+        range: &R,
+    ) -> Result<Option<(Position<K, V>, Position<K, V>)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+        R: RangeBounds<Q>,
+    {
+        match (range.start_bound(), range.end_bound()) {
+            (Bound::Excluded(start), Bound::Excluded(end)) if start == end => {
+                panic!("range start and end are equal and excluded in BPTree")
+            }
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) if start > end => {
+                panic!("range start is greater than range end in BPTree")
+            }
+            _ => {}
+        }
+
+        unsafe {
+            let (front, front_index) = match self.seek_front(range.start_bound())? {
+                Some(front) => front,
+                None => return Ok(None),
+            };
+
+            // The first entry past the start bound may already be past the end
+            // bound, in which case the range is empty.
+            if let Node::Leaf(node) = (*front.as_ptr()).access(&self.pager)? {
+                let key = node.keys[front_index].borrow();
+                let in_range = match range.end_bound() {
+                    Bound::Included(end) => key <= end,
+                    Bound::Excluded(end) => key < end,
+                    Bound::Unbounded => true,
+                };
+
+                if !in_range {
+                    return Ok(None);
+                }
+            }
+
+            match self.seek_back(range.end_bound())? {
+                Some(back) => Ok(Some(((front, front_index), back))),
+                None => Ok(None),
+            }
+        }
+    }
+
+    // Descends to the first entry that lies past the start bound.
+    unsafe fn seek_front<Q>(&self, bound: Bound<&Q>) -> Result<Option<Position<K, V>>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+    {
+        let mut cursor = match self.root {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+
+        while let Node::Internal(node) = (*cursor.as_ptr()).access(&self.pager)? {
+            let index = match bound {
+                Bound::Included(key) | Bound::Excluded(key) => {
+                    match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
+                        Ok(index) => index + 1,
+                        Err(index) => index,
+                    }
+                }
+                Bound::Unbounded => 0,
+            };
+            cursor = node.children[index];
+        }
+
+        if let Node::Leaf(node) = (*cursor.as_ptr()).access(&self.pager)? {
+            let index = match bound {
+                Bound::Included(key) => node.keys.partition_point(|probe| probe.borrow() < key),
+                Bound::Excluded(key) => node.keys.partition_point(|probe| probe.borrow() <= key),
+                Bound::Unbounded => 0,
+            };
+
+            if index < node.keys.len() {
+                return Ok(Some((cursor, index)));
+            }
+
+            // Every key in this leaf is before the bound, so the first entry
+            // is the head of the next leaf.
+            return Ok(node.next_leaf.map(|next_leaf| (next_leaf, 0)));
+        }
+
+        Ok(None)
+    }
+
+    // Descends to the last entry that lies before the end bound.
+    unsafe fn seek_back<Q>(&self, bound: Bound<&Q>) -> Result<Option<Position<K, V>>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+    {
+        let mut cursor = match self.root {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+
+        // The closest subtree to the left of the descent. Its rightmost leaf
+        // precedes the leaf we end up in.
+        let mut left_subtree = None;
+
+        while let Node::Internal(node) = (*cursor.as_ptr()).access(&self.pager)? {
+            let index = match bound {
+                Bound::Included(key) => {
+                    match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
+                        Ok(index) => index + 1,
+                        Err(index) => index,
+                    }
+                }
+                Bound::Excluded(key) => {
+                    match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
+                        Ok(index) => index,
+                        Err(index) => index,
+                    }
+                }
+                Bound::Unbounded => node.children.len() - 1,
+            };
+
+            if index > 0 {
+                left_subtree = Some(node.children[index - 1]);
+            }
+
+            cursor = node.children[index];
+        }
+
+        if let Node::Leaf(node) = (*cursor.as_ptr()).access(&self.pager)? {
+            let index = match bound {
+                Bound::Included(key) => node.keys.partition_point(|probe| probe.borrow() <= key),
+                Bound::Excluded(key) => node.keys.partition_point(|probe| probe.borrow() < key),
+                Bound::Unbounded => node.keys.len(),
+            };
+
+            if index > 0 {
+                return Ok(Some((cursor, node.keys.len() - index)));
+            }
+        }
+
+        // Every key in the leaf is past the bound, so the last entry is the
+        // tail of the previous leaf.
+        match left_subtree {
+            Some(left_subtree) => Ok(Some((last_leaf(left_subtree, &self.pager)?, 0))),
+            None => Ok(None),
+        }
+    }
+}
+
+pub struct Range<'a, K, V, S = DirStore, C = Bincode> {
+    pub(crate) front: Option<Link<K, V>>,
+    pub(crate) front_index: usize,
+    pub(crate) back: Option<Link<K, V>>,
+    pub(crate) back_index: usize,
+    pub(crate) error: Option<Error>,
+    pub(crate) pager: &'a Pager<K, V, S, C>,
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> Iterator for Range<'a, K, V, S, C>
+where
+    for<'de> K: Deserialize<'de> + 'a,
+    for<'de> V: Deserialize<'de> + 'a,
+{
+    type Item = Result<(&'a K, &'a V), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            return Some(Err(err));
+        }
+
+        let cursor = self.front?;
+
+        unsafe {
+            match (*cursor.as_ptr()).access(self.pager) {
+                Ok(node) => match node {
+                    Node::Internal(_) => None,
+                    Node::Leaf(node) => {
+                        let result = (&node.keys[self.front_index], &node.values[self.front_index]);
+
+                        if self.front == self.back
+                            && self.front_index == node.keys.len() - 1 - self.back_index
+                        {
+                            self.front = None;
+                            self.back = None;
+                        } else {
+                            self.front_index += 1;
+
+                            if self.front_index >= node.keys.len() {
+                                self.front_index = 0;
+                                self.front = node.next_leaf;
+                            }
+                        }
+
+                        Some(Ok(result))
+                    }
+                },
+                Err(err) => {
+                    self.front = None;
+                    self.back = None;
+                    Some(Err(err))
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> DoubleEndedIterator for Range<'a, K, V, S, C>
+where
+    for<'de> K: Deserialize<'de> + 'a,
+    for<'de> V: Deserialize<'de> + 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            return Some(Err(err));
+        }
+
+        let cursor = self.back?;
+
+        unsafe {
+            match (*cursor.as_ptr()).access(self.pager) {
+                Ok(node) => match node {
+                    Node::Internal(_) => None,
+                    Node::Leaf(node) => {
+                        let index = node.keys.len() - 1 - self.back_index;
+                        let result = (&node.keys[index], &node.values[index]);
+
+                        if self.front == self.back && self.front_index == index {
+                            self.front = None;
+                            self.back = None;
+                        } else {
+                            self.back_index += 1;
+
+                            if self.back_index >= node.keys.len() {
+                                self.back_index = 0;
+                                self.back = node.prev_leaf;
+                            }
+                        }
+
+                        Some(Ok(result))
+                    }
+                },
+                Err(err) => {
+                    self.front = None;
+                    self.back = None;
+                    Some(Err(err))
+                }
+            }
+        }
+    }
+}
+
+pub struct RangeMut<'a, K, V, S = DirStore, C = Bincode> {
+    pub(crate) front: Option<Link<K, V>>,
+    pub(crate) front_index: usize,
+    pub(crate) back: Option<Link<K, V>>,
+    pub(crate) back_index: usize,
+    pub(crate) error: Option<Error>,
+    pub(crate) pager: &'a Pager<K, V, S, C>,
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> Iterator for RangeMut<'a, K, V, S, C>
+where
+    for<'de> K: Deserialize<'de> + 'a,
+    for<'de> V: Deserialize<'de> + 'a,
+{
+    type Item = Result<(&'a K, ValueMutationGuard<'a, K, V, S, C>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            return Some(Err(err));
+        }
+
+        let cursor = self.front?;
+
+        unsafe {
+            match (*cursor.as_ptr()).access_mut(self.pager) {
+                Ok(node) => match node {
+                    Node::Internal(_) => None,
+                    Node::Leaf(node) => {
+                        let result = (
+                            &node.keys[self.front_index],
+                            ValueMutationGuard {
+                                key: &node.keys[self.front_index],
+                                value: &mut node.values[self.front_index],
+                                cursor,
+                                pager: self.pager,
+                            },
+                        );
+
+                        if self.front == self.back
+                            && self.front_index == node.keys.len() - 1 - self.back_index
+                        {
+                            self.front = None;
+                            self.back = None;
+                        } else {
+                            self.front_index += 1;
+
+                            if self.front_index >= node.keys.len() {
+                                self.front_index = 0;
+                                self.front = node.next_leaf;
+                            }
+                        }
+
+                        Some(Ok(result))
+                    }
+                },
+                Err(err) => {
+                    self.front = None;
+                    self.back = None;
+                    Some(Err(err))
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> DoubleEndedIterator for RangeMut<'a, K, V, S, C>
+where
+    for<'de> K: Deserialize<'de> + 'a,
+    for<'de> V: Deserialize<'de> + 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            return Some(Err(err));
+        }
+
+        let cursor = self.back?;
+
+        unsafe {
+            match (*cursor.as_ptr()).access_mut(self.pager) {
+                Ok(node) => match node {
+                    Node::Internal(_) => None,
+                    Node::Leaf(node) => {
+                        let index = node.keys.len() - 1 - self.back_index;
+                        let result = (
+                            &node.keys[index],
+                            ValueMutationGuard {
+                                key: &node.keys[index],
+                                value: &mut node.values[index],
+                                cursor,
+                                pager: self.pager,
+                            },
+                        );
+
+                        if self.front == self.back && self.front_index == index {
+                            self.front = None;
+                            self.back = None;
+                        } else {
+                            self.back_index += 1;
+
+                            if self.back_index >= node.keys.len() {
+                                self.back_index = 0;
+                                self.back = node.prev_leaf;
+                            }
+                        }
+
+                        Some(Ok(result))
+                    }
+                },
+                Err(err) => {
+                    self.front = None;
+                    self.back = None;
+                    Some(Err(err))
+                }
+            }
+        }
+    }
+}
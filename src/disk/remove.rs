@@ -1,179 +1,351 @@
 use serde::Deserialize;
 
 use super::{
+    codec::Codec,
     error::Error,
+    iter::first_leaf,
     node::{Link, Node},
+    store::NodeStore,
     BPTree,
 };
-use std::{borrow::Borrow, mem};
+use std::{
+    borrow::Borrow,
+    mem,
+    ops::{Bound, RangeBounds},
+};
 
-impl<K, V> BPTree<K, V> {
+impl<K, V, S: NodeStore, C: Codec> BPTree<K, V, S, C> {
     pub fn remove_entry<Q>(&mut self, key: &Q) -> Result<Option<(K, V)>, Error>
     where
         for<'de> K: Deserialize<'de> + Borrow<Q> + Clone,
         for<'de> V: Deserialize<'de>,
         Q: Ord,
     {
-        if self.root.is_none() {
-            return Ok(None);
+        let entry = self.remove_entry_unlogged(key)?;
+        if let Some((key, _)) = &entry {
+            let record = self.pager.encode_remove(key)?;
+            self.pager.log(record)?;
         }
+        Ok(entry)
+    }
+
+    pub(crate) fn remove_entry_unlogged<Q>(&mut self, key: &Q) -> Result<Option<(K, V)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q> + Clone,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+    {
+        self.pager.evict();
+
+        match self.search_leaf(key)? {
+            Some((cursor, cursor_index, Ok(index))) => unsafe {
+                Ok(Some(self.remove_at(cursor, cursor_index, index)?))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn pop(&mut self, last: bool) -> Result<Option<(K, V)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.pager.evict();
+
+        let (cursor, cursor_index, index) = match self.edge_leaf(last)? {
+            Some(edge) => edge,
+            None => return Ok(None),
+        };
+
+        let entry = unsafe { self.remove_at::<K>(cursor, cursor_index, index)? };
+        let record = self.pager.encode_remove(&entry.0)?;
+        self.pager.log(record)?;
+
+        Ok(Some(entry))
+    }
+
+    // Removes the entry with the smallest key, so the tree can serve as a
+    // priority queue.
+    pub fn pop_first(&mut self) -> Result<Option<(K, V)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.pop(false)
+    }
+
+    pub fn pop_last(&mut self) -> Result<Option<(K, V)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.pop(true)
+    }
+
+    // Removes every entry in a range, returning how many there were.
+    //
+    // Rather than removing the entries one at a time, the tree is cut along
+    // the paths down to either end of the range. Whatever lies between the two
+    // paths is reclaimed whole, and the nodes along them are rebalanced as the
+    // two sides are joined back together.
+    pub fn remove_range<Q, R>(&mut self, range: R) -> Result<usize, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+        R: RangeBounds<Q>,
+    {
+        self.pager.evict();
 
-        let mut cursor = self.root.unwrap();
-        let mut cursor_index = 0;
+        let root = match (self.root, self.seek_range(&range)?) {
+            (Some(root), Some(_)) => root,
+            _ => return Ok(0),
+        };
 
         unsafe {
-            while let Node::Internal(node) = (*cursor.as_ptr()).access_mut(&self.path)? {
-                cursor_index = match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
-                    Ok(index) => index + 1,
-                    Err(index) => index,
-                };
-                cursor = node.children[cursor_index];
-                node.is_dirty = true;
+            let (left, rest) = self.cut(root, |key| match range.start_bound() {
+                Bound::Included(start) => key.borrow() < start,
+                Bound::Excluded(start) => key.borrow() <= start,
+                Bound::Unbounded => false,
+            })?;
+
+            self.join_right(rest)?;
+            let (middle, right) = match self.root {
+                Some(rest) => self.cut(rest, |key| match range.end_bound() {
+                    Bound::Included(end) => key.borrow() <= end,
+                    Bound::Excluded(end) => key.borrow() < end,
+                    Bound::Unbounded => true,
+                })?,
+                None => (Vec::new(), Vec::new()),
+            };
+
+            self.join_right(right)?;
+            let right = self.root.take();
+            self.join_left(left)?;
+            let left = self.root.take();
+
+            let mut removed = 0;
+            for piece in middle {
+                removed += self.reclaim_subtree(piece)?;
             }
 
-            if let Node::Leaf(node) = (*cursor.as_ptr()).access_mut(&self.path)? {
-                node.is_dirty = true;
+            self.join(left, right)?;
 
-                let index = node.keys.binary_search_by(|probe| probe.borrow().cmp(key));
-                if index.is_err() {
-                    return Ok(None);
+            // The first leaf may still point back across the cut.
+            if let Some(root) = self.root {
+                if let Node::Leaf(node) =
+                    (*first_leaf(root, &self.pager)?.as_ptr()).access_mut(&self.pager)?
+                {
+                    if node.prev_leaf.is_some() {
+                        node.prev_leaf = None;
+                        node.is_dirty = true;
+                    }
                 }
+            }
 
-                let index = index.unwrap();
-                let key = node.keys.remove(index);
-                let value = node.values.remove(index);
-
-                self.len -= 1;
-                self.len_is_dirty = true;
-
-                // Check if the node is now underfull or if its the root. The
-                // root is exceptional in that it is allowed to be underfull.
-                if !node.is_underfull(self.order) || Some(cursor) == self.root {
-                    // Clean out the root if we've emptied it.
-                    if Some(cursor) == self.root && node.keys.is_empty() {
-                        cursor.reclaim(&self.path)?;
-                        self.root = None;
-                        self.root_is_dirty = true;
-                    }
-                    return Ok(Some((key, value)));
+            self.len -= removed;
+            self.len_is_dirty = true;
+
+            Ok(removed)
+        }
+    }
+
+    // Reclaims every node under `cursor`, logging the removal of each entry
+    // along the way. Returns how many entries there were.
+    unsafe fn reclaim_subtree(&mut self, cursor: Link<K, V>) -> Result<usize, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let removed = match (*cursor.as_ptr()).access_mut(&self.pager)? {
+            Node::Internal(node) => {
+                let mut removed = 0;
+                for child in mem::take(&mut node.children) {
+                    removed += self.reclaim_subtree(child)?;
+                }
+                removed
+            }
+            Node::Leaf(node) => {
+                for key in &node.keys {
+                    let record = self.pager.encode_remove(key)?;
+                    self.pager.log(record)?;
                 }
+                node.keys.len()
+            }
+        };
 
-                // We have an underfull non-root leaf node.
-                if let Node::Internal(parent) =
-                    (*node.parent.unwrap().as_ptr()).access_mut(&self.path)?
-                {
-                    // Check if the left sibling has any extra keys.
-                    if cursor_index > 0 {
-                        if let Node::Leaf(left_sibling) =
-                            (*parent.children[cursor_index - 1].as_ptr()).access_mut(&self.path)?
-                        {
-                            if left_sibling.has_extra_keys(self.order) {
-                                left_sibling.is_dirty = true;
-                                parent.is_dirty = true;
-
-                                // We want the max key/value pair from the left
-                                // sibling.
-                                let max_key = left_sibling.keys.pop().unwrap();
-                                let max_value = left_sibling.values.pop().unwrap();
-
-                                // The max key/value pair from the left sibling
-                                // is smaller than any key/value in the cursor
-                                // node.
-                                node.keys.insert(0, max_key);
-                                node.values.insert(0, max_value);
-
-                                // Update parent key.
-                                parent.keys[cursor_index - 1] = node.keys[0].clone();
-
-                                return Ok(Some((key, value)));
-                            }
-                        }
-                    }
+        cursor.reclaim(&mut self.pager);
+        Ok(removed)
+    }
 
-                    // Check if the right sibling has any extra keys.
-                    if cursor_index + 1 < parent.children.len() {
-                        if let Node::Leaf(right_sibling) =
-                            (*parent.children[cursor_index + 1].as_ptr()).access_mut(&self.path)?
-                        {
-                            if right_sibling.has_extra_keys(self.order) {
-                                right_sibling.is_dirty = true;
-                                parent.is_dirty = true;
-
-                                // We want the min key/value pair from the right
-                                // sibling.
-                                let min_key = right_sibling.keys.remove(0);
-                                let min_value = right_sibling.values.remove(0);
-
-                                // The min key/value pair from the left sibling
-                                // is larger than any key/value in the cursor
-                                // node.
-                                node.keys.push(min_key);
-                                node.values.push(min_value);
-
-                                // Update parent key.
-                                parent.keys[cursor_index] = right_sibling.keys[0].clone();
-
-                                return Ok(Some((key, value)));
-                            }
-                        }
-                    }
+    // Removes the entry at `index` in a leaf, the child at `cursor_index` of
+    // its parent, merging or borrowing as needed.
+    pub(crate) unsafe fn remove_at<Q>(
+        &mut self,
+        cursor: Link<K, V>,
+        cursor_index: usize,
+        index: usize,
+    ) -> Result<(K, V), Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q> + Clone,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+    {
+        if let Node::Leaf(node) = (*cursor.as_ptr()).access_mut(&self.pager)? {
+            node.is_dirty = true;
+
+            let key = node.keys.remove(index);
+            let value = node.values.remove(index);
+
+            self.len -= 1;
+            self.len_is_dirty = true;
+
+            // Check if the node is now underfull or if its the root. The
+            // root is exceptional in that it is allowed to be underfull.
+            if !node.is_underfull(self.order) || Some(cursor) == self.root {
+                // Clean out the root if we've emptied it.
+                if Some(cursor) == self.root && node.keys.is_empty() {
+                    cursor.reclaim(&mut self.pager);
+                    self.root = None;
+                    self.root_is_dirty = true;
+                }
+                return Ok((key, value));
+            }
 
-                    // Check if we can merge into the left sibling.
-                    if cursor_index > 0 {
-                        if let Node::Leaf(left_sibling) =
-                            (*parent.children[cursor_index - 1].as_ptr()).access_mut(&self.path)?
-                        {
+            // We have an underfull non-root leaf node.
+            if let Node::Internal(parent) =
+                (*node.parent.unwrap().as_ptr()).access_mut(&self.pager)?
+            {
+                // Check if the left sibling has any extra keys.
+                if cursor_index > 0 {
+                    if let Node::Leaf(left_sibling) =
+                        (*parent.children[cursor_index - 1].as_ptr()).access_mut(&self.pager)?
+                    {
+                        if left_sibling.has_extra_keys(self.order) {
                             left_sibling.is_dirty = true;
+                            parent.is_dirty = true;
 
-                            // Take/merge in the keys and values.
-                            left_sibling.keys.append(&mut node.keys);
-                            left_sibling.values.append(&mut node.values);
+                            // We want the max key/value pair from the left
+                            // sibling.
+                            let max_key = left_sibling.keys.pop().unwrap();
+                            let max_value = left_sibling.values.pop().unwrap();
 
-                            // Relink the left sibling.
-                            left_sibling.next_leaf = node.next_leaf;
+                            // The max key/value pair from the left sibling
+                            // is smaller than any key/value in the cursor
+                            // node.
+                            node.keys.insert(0, max_key);
+                            node.values.insert(0, max_value);
 
-                            // Remove the split key.
-                            self.remove_entry_internal(
-                                parent.keys[cursor_index - 1].clone().borrow(),
-                                node.parent.unwrap(),
-                                cursor,
-                            )?;
+                            // Update parent key.
+                            parent.keys[cursor_index - 1] = node.keys[0].clone();
 
-                            return Ok(Some((key, value)));
+                            return Ok((key, value));
                         }
                     }
+                }
 
-                    // Check if we can merge the right sibling.
-                    if cursor_index + 1 < parent.children.len() {
-                        if let Node::Leaf(right_sibling) =
-                            (*parent.children[cursor_index + 1].as_ptr()).access_mut(&self.path)?
-                        {
+                // Check if the right sibling has any extra keys.
+                if cursor_index + 1 < parent.children.len() {
+                    if let Node::Leaf(right_sibling) =
+                        (*parent.children[cursor_index + 1].as_ptr()).access_mut(&self.pager)?
+                    {
+                        if right_sibling.has_extra_keys(self.order) {
                             right_sibling.is_dirty = true;
+                            parent.is_dirty = true;
+
+                            // We want the min key/value pair from the right
+                            // sibling.
+                            let min_key = right_sibling.keys.remove(0);
+                            let min_value = right_sibling.values.remove(0);
+
+                            // The min key/value pair from the left sibling
+                            // is larger than any key/value in the cursor
+                            // node.
+                            node.keys.push(min_key);
+                            node.values.push(min_value);
+
+                            // Update parent key.
+                            parent.keys[cursor_index] = right_sibling.keys[0].clone();
+
+                            return Ok((key, value));
+                        }
+                    }
+                }
+
+                // Check if we can merge into the left sibling.
+                if cursor_index > 0 {
+                    if let Node::Leaf(left_sibling) =
+                        (*parent.children[cursor_index - 1].as_ptr()).access_mut(&self.pager)?
+                    {
+                        left_sibling.is_dirty = true;
+
+                        // Take/merge in the keys and values.
+                        left_sibling.keys.append(&mut node.keys);
+                        left_sibling.values.append(&mut node.values);
+
+                        // Relink the left sibling.
+                        left_sibling.next_leaf = node.next_leaf;
+
+                        if let Some(next_leaf) = node.next_leaf {
+                            if let Node::Leaf(next_leaf) =
+                                (*next_leaf.as_ptr()).access_mut(&self.pager)?
+                            {
+                                next_leaf.prev_leaf = Some(parent.children[cursor_index - 1]);
+                                next_leaf.is_dirty = true;
+                            }
+                        }
 
-                            // Take/merge in the keys and values.
-                            node.keys.append(&mut right_sibling.keys);
-                            node.values.append(&mut right_sibling.values);
+                        // Remove the split key.
+                        self.remove_entry_internal(
+                            parent.keys[cursor_index - 1].clone().borrow(),
+                            node.parent.unwrap(),
+                            cursor,
+                        )?;
 
-                            // Relink the right sibling.
-                            node.next_leaf = right_sibling.next_leaf;
+                        return Ok((key, value));
+                    }
+                }
 
-                            // Remove the split key from the parent.
-                            // The clone is to satisfy miri's stacked borrow
-                            // check.
-                            self.remove_entry_internal(
-                                parent.keys[cursor_index].clone().borrow(),
-                                node.parent.unwrap(),
-                                parent.children[cursor_index + 1],
-                            )?;
+                // Check if we can merge the right sibling.
+                if cursor_index + 1 < parent.children.len() {
+                    if let Node::Leaf(right_sibling) =
+                        (*parent.children[cursor_index + 1].as_ptr()).access_mut(&self.pager)?
+                    {
+                        right_sibling.is_dirty = true;
+
+                        // Take/merge in the keys and values.
+                        node.keys.append(&mut right_sibling.keys);
+                        node.values.append(&mut right_sibling.values);
+
+                        // Relink the right sibling.
+                        node.next_leaf = right_sibling.next_leaf;
 
-                            return Ok(Some((key, value)));
+                        if let Some(next_leaf) = right_sibling.next_leaf {
+                            if let Node::Leaf(next_leaf) =
+                                (*next_leaf.as_ptr()).access_mut(&self.pager)?
+                            {
+                                next_leaf.prev_leaf = Some(cursor);
+                                next_leaf.is_dirty = true;
+                            }
                         }
+
+                        // Remove the split key from the parent.
+                        // The clone is to satisfy miri's stacked borrow
+                        // check.
+                        self.remove_entry_internal(
+                            parent.keys[cursor_index].clone().borrow(),
+                            node.parent.unwrap(),
+                            parent.children[cursor_index + 1],
+                        )?;
+
+                        return Ok((key, value));
                     }
                 }
             }
         }
 
-        Ok(None)
+        // Leaves other than the root always have a parent.
+        Err(Error::BadBPTree)
     }
 
     unsafe fn remove_entry_internal<Q>(
@@ -188,7 +360,7 @@ impl<K, V> BPTree<K, V> {
         Q: Ord,
     {
         if Some(cursor) == self.root {
-            if let Node::Internal(node) = (*cursor.as_ptr()).access_mut(&self.path)? {
+            if let Node::Internal(node) = (*cursor.as_ptr()).access_mut(&self.pager)? {
                 // Check if we're deleting the final key from the root.
                 if node.keys.len() == 1 {
                     // Decide which child is the new root.
@@ -199,16 +371,30 @@ impl<K, V> BPTree<K, V> {
                     };
                     self.root_is_dirty = true;
 
+                    // The new root no longer has a parent.
+                    if let Some(root) = self.root {
+                        match (*root.as_ptr()).access_mut(&self.pager)? {
+                            Node::Internal(root) => {
+                                root.parent = None;
+                                root.is_dirty = true;
+                            }
+                            Node::Leaf(root) => {
+                                root.parent = None;
+                                root.is_dirty = true;
+                            }
+                        }
+                    }
+
                     // Reclaim the resources used by the root and child.
-                    cursor.reclaim(&self.path)?;
-                    child.reclaim(&self.path)?;
+                    cursor.reclaim(&mut self.pager);
+                    child.reclaim(&mut self.pager);
 
                     return Ok(());
                 }
             }
         }
 
-        if let Node::Internal(node) = (*cursor.as_ptr()).access_mut(&self.path)? {
+        if let Node::Internal(node) = (*cursor.as_ptr()).access_mut(&self.pager)? {
             node.is_dirty = true;
 
             let index = node
@@ -222,14 +408,14 @@ impl<K, V> BPTree<K, V> {
                 .iter()
                 .position(|probe| *probe == child)
                 .unwrap();
-            node.children.remove(child_index).reclaim(&self.path)?;
+            node.children.remove(child_index).reclaim(&mut self.pager);
 
             if !node.is_underfull(self.order) || Some(cursor) == self.root {
                 return Ok(());
             }
 
             if let Node::Internal(parent) =
-                (*node.parent.unwrap().as_ptr()).access_mut(&self.path)?
+                (*node.parent.unwrap().as_ptr()).access_mut(&self.pager)?
             {
                 let cursor_index = parent
                     .children
@@ -240,7 +426,7 @@ impl<K, V> BPTree<K, V> {
                 // Check if there's a left sibling with extra keys.
                 if cursor_index > 0 {
                     if let Node::Internal(left_sibling) =
-                        (*parent.children[cursor_index - 1].as_ptr()).access_mut(&self.path)?
+                        (*parent.children[cursor_index - 1].as_ptr()).access_mut(&self.pager)?
                     {
                         // Does the left sibling have extra keys?
                         if left_sibling.has_extra_keys(self.order) {
@@ -257,9 +443,15 @@ impl<K, V> BPTree<K, V> {
                             node.children.insert(0, max_child);
 
                             // Fix max child's parent.
-                            match (*node.children[0].as_ptr()).access_mut(&self.path)? {
-                                Node::Internal(max_child) => max_child.parent = Some(cursor),
-                                Node::Leaf(max_child) => max_child.parent = Some(cursor),
+                            match (*node.children[0].as_ptr()).access_mut(&self.pager)? {
+                                Node::Internal(max_child) => {
+                                    max_child.parent = Some(cursor);
+                                    max_child.is_dirty = true;
+                                }
+                                Node::Leaf(max_child) => {
+                                    max_child.parent = Some(cursor);
+                                    max_child.is_dirty = true;
+                                }
                             }
 
                             return Ok(());
@@ -270,7 +462,7 @@ impl<K, V> BPTree<K, V> {
                 // Check if there's a right sibling with extra keys.
                 if cursor_index + 1 < parent.children.len() {
                     if let Node::Internal(right_sibling) =
-                        (*parent.children[cursor_index + 1].as_ptr()).access_mut(&self.path)?
+                        (*parent.children[cursor_index + 1].as_ptr()).access_mut(&self.pager)?
                     {
                         if right_sibling.has_extra_keys(self.order) {
                             right_sibling.is_dirty = true;
@@ -287,7 +479,7 @@ impl<K, V> BPTree<K, V> {
 
                             // Fix min child's parent.
                             match (*node.children[node.children.len() - 1].as_ptr())
-                                .access_mut(&self.path)?
+                                .access_mut(&self.pager)?
                             {
                                 Node::Internal(min_child) => {
                                     min_child.parent = Some(cursor);
@@ -307,7 +499,7 @@ impl<K, V> BPTree<K, V> {
                 // Check if there's a left sibling to merge with.
                 if cursor_index > 0 {
                     if let Node::Internal(left_sibling) =
-                        (*parent.children[cursor_index - 1].as_ptr()).access_mut(&self.path)?
+                        (*parent.children[cursor_index - 1].as_ptr()).access_mut(&self.pager)?
                     {
                         left_sibling.is_dirty = true;
 
@@ -319,7 +511,7 @@ impl<K, V> BPTree<K, V> {
 
                         // Update the parent for the to-be-merged children.
                         for child in node.children.iter_mut() {
-                            match (*child.as_ptr()).access_mut(&self.path)? {
+                            match (*child.as_ptr()).access_mut(&self.pager)? {
                                 Node::Internal(child) => {
                                     child.parent = Some(parent.children[cursor_index - 1]);
                                     child.is_dirty = true;
@@ -350,7 +542,7 @@ impl<K, V> BPTree<K, V> {
                 // Check if there's a right sibling to merge with.
                 if cursor_index + 1 < parent.children.len() {
                     if let Node::Internal(right_sibling) =
-                        (*parent.children[cursor_index + 1].as_ptr()).access_mut(&self.path)?
+                        (*parent.children[cursor_index + 1].as_ptr()).access_mut(&self.pager)?
                     {
                         right_sibling.is_dirty = true;
 
@@ -360,12 +552,14 @@ impl<K, V> BPTree<K, V> {
 
                         // Update the parent for the to-be-merged children.
                         for child in right_sibling.children.iter_mut() {
-                            match (*child.as_ptr()).access_mut(&self.path)? {
+                            match (*child.as_ptr()).access_mut(&self.pager)? {
                                 Node::Internal(child) => {
                                     child.parent = Some(cursor);
+                                    child.is_dirty = true;
                                 }
                                 Node::Leaf(child) => {
                                     child.parent = Some(cursor);
+                                    child.is_dirty = true;
                                 }
                             }
                         }
@@ -0,0 +1,243 @@
+use super::{
+    codec::{Bincode, Codec},
+    directory::DirStore,
+    entry::Entry,
+    error::Error,
+    format::LoadOptions,
+    iter::Keys,
+    range,
+    store::NodeStore,
+    BPTree,
+};
+use serde::{Deserialize, Serialize};
+use std::{borrow::Borrow, cell::Ref, ops::RangeBounds, path::Path};
+
+// A persistent set, kept as a tree with `()` values. Leaves leave zero-sized
+// values out, so the set's nodes only store its values as keys.
+pub struct BPTreeDiskSet<T, S = DirStore, C = Bincode> {
+    tree: BPTree<T, (), S, C>,
+}
+
+impl<T> BPTreeDiskSet<T, DirStore> {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            tree: BPTree::new(path),
+        }
+    }
+
+    pub fn with_order(path: impl AsRef<Path>, order: usize) -> Self {
+        Self {
+            tree: BPTree::with_order(path, order),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error>
+    where
+        for<'de> T: Deserialize<'de> + Serialize + Ord + Clone,
+    {
+        Ok(Self {
+            tree: BPTree::load(path)?,
+        })
+    }
+
+    pub fn load_with(path: impl AsRef<Path>, options: LoadOptions<DirStore>) -> Result<Self, Error>
+    where
+        for<'de> T: Deserialize<'de> + Serialize + Ord + Clone,
+    {
+        Ok(Self {
+            tree: BPTree::load_with(path, options)?,
+        })
+    }
+}
+
+impl<T, S: NodeStore, C: Codec> BPTreeDiskSet<T, S, C> {
+    pub fn with_store(store: S, order: usize) -> Self
+    where
+        C: Default,
+    {
+        Self {
+            tree: BPTree::with_store(store, order),
+        }
+    }
+
+    pub fn load_from_store(store: S) -> Result<Self, Error>
+    where
+        C: Default,
+    {
+        Ok(Self {
+            tree: BPTree::load_from_store(store)?,
+        })
+    }
+
+    pub fn load_from_store_with(store: S, options: LoadOptions<S>) -> Result<Self, Error>
+    where
+        C: Default,
+    {
+        Ok(Self {
+            tree: BPTree::load_from_store_with(store, options)?,
+        })
+    }
+
+    pub fn store(&self) -> Ref<'_, S> {
+        self.tree.store()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn set_cache_capacity(&mut self, capacity: Option<usize>) {
+        self.tree.set_cache_capacity(capacity);
+    }
+
+    // Returns whether the value was newly inserted. An equal value already in
+    // the set is left as is.
+    pub fn insert(&mut self, value: T) -> Result<bool, Error>
+    where
+        for<'de> T: Deserialize<'de> + Ord + Clone,
+    {
+        match self.tree.entry(value)? {
+            Entry::Vacant(entry) => {
+                entry.insert(())?;
+                Ok(true)
+            }
+            Entry::Occupied(_) => Ok(false),
+        }
+    }
+
+    pub fn contains<Q>(&self, value: &Q) -> Result<bool, Error>
+    where
+        for<'de> T: Deserialize<'de> + Borrow<Q>,
+        Q: Ord,
+    {
+        self.tree.contains_key(value)
+    }
+
+    pub fn get<Q>(&self, value: &Q) -> Result<Option<&T>, Error>
+    where
+        for<'de> T: Deserialize<'de> + Borrow<Q>,
+        Q: Ord,
+    {
+        Ok(self.tree.get_key_value(value)?.map(|(value, _)| value))
+    }
+
+    pub fn remove<Q>(&mut self, value: &Q) -> Result<bool, Error>
+    where
+        for<'de> T: Deserialize<'de> + Borrow<Q> + Clone,
+        Q: Ord,
+    {
+        Ok(self.take(value)?.is_some())
+    }
+
+    pub fn take<Q>(&mut self, value: &Q) -> Result<Option<T>, Error>
+    where
+        for<'de> T: Deserialize<'de> + Borrow<Q> + Clone,
+        Q: Ord,
+    {
+        Ok(self.tree.remove_entry(value)?.map(|(value, _)| value))
+    }
+
+    pub fn first(&self) -> Result<Option<&T>, Error>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        Ok(self.tree.first_key_value()?.map(|(value, _)| value))
+    }
+
+    pub fn last(&self) -> Result<Option<&T>, Error>
+    where
+        for<'de> T: Deserialize<'de>,
+    {
+        Ok(self.tree.last_key_value()?.map(|(value, _)| value))
+    }
+
+    pub fn pop_first(&mut self) -> Result<Option<T>, Error>
+    where
+        for<'de> T: Deserialize<'de> + Ord + Clone,
+    {
+        Ok(self.tree.pop_first()?.map(|(value, _)| value))
+    }
+
+    pub fn pop_last(&mut self) -> Result<Option<T>, Error>
+    where
+        for<'de> T: Deserialize<'de> + Ord + Clone,
+    {
+        Ok(self.tree.pop_last()?.map(|(value, _)| value))
+    }
+
+    pub fn iter(&self) -> Iter<'_, T, S, C> {
+        Iter(self.tree.keys())
+    }
+
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, T, S, C>
+    where
+        for<'de> T: Deserialize<'de> + Borrow<Q>,
+        Q: Ord,
+        R: RangeBounds<Q>,
+    {
+        Range(self.tree.range(range))
+    }
+
+    pub fn persist(&mut self) -> Result<(), Error>
+    where
+        for<'de> T: Deserialize<'de> + Serialize,
+    {
+        self.tree.persist()
+    }
+
+    pub fn persist_key<Q>(&mut self, value: &Q) -> Result<(), Error>
+    where
+        for<'de> T: Deserialize<'de> + Serialize + Borrow<Q>,
+        Q: Ord,
+    {
+        self.tree.persist_key(value)
+    }
+}
+
+pub struct Iter<'a, T, S = DirStore, C = Bincode>(Keys<'a, T, (), S, C>);
+
+impl<'a, T, S: NodeStore, C: Codec> Iterator for Iter<'a, T, S, C>
+where
+    for<'de> T: Deserialize<'de> + 'a,
+{
+    type Item = Result<&'a T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl<'a, T, S: NodeStore, C: Codec> DoubleEndedIterator for Iter<'a, T, S, C>
+where
+    for<'de> T: Deserialize<'de> + 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back()
+    }
+}
+
+pub struct Range<'a, T, S = DirStore, C = Bincode>(range::Range<'a, T, (), S, C>);
+
+impl<'a, T, S: NodeStore, C: Codec> Iterator for Range<'a, T, S, C>
+where
+    for<'de> T: Deserialize<'de> + 'a,
+{
+    type Item = Result<&'a T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|res| res.map(|(value, _)| value))
+    }
+}
+
+impl<'a, T, S: NodeStore, C: Codec> DoubleEndedIterator for Range<'a, T, S, C>
+where
+    for<'de> T: Deserialize<'de> + 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|res| res.map(|(value, _)| value))
+    }
+}
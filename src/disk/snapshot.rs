@@ -0,0 +1,239 @@
+use super::{
+    codec::{Bincode, Codec},
+    directory::DirStore,
+    encryption::Key,
+    error::Error,
+    iter::{Iter, Keys, Values},
+    node::Link,
+    pager::{KeptVersions, Pager, Versions},
+    persist::{LEN_METADATA, ORDER_METADATA, ROOT_KEY_METADATA, ROOT_METADATA},
+    range::Range,
+    store::NodeStore,
+    BPTree,
+};
+use serde::Deserialize;
+use std::{
+    borrow::Borrow,
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, ErrorKind},
+    ops::RangeBounds,
+    rc::Rc,
+};
+use uuid::Uuid;
+
+// A read-only view of a `BPTree` as it was last persisted.
+//
+// Snapshots read the same store as the tree. Whenever a persist is about to
+// overwrite or delete a node a live snapshot may still see, the tree keeps the
+// stored version of the node for it, so nothing the tree does afterwards
+// changes what the snapshot sees. Versions no snapshot needs any more are
+// deleted as soon as the last snapshot to need them is dropped.
+pub struct Snapshot<K, V, S: NodeStore = DirStore, C = Bincode> {
+    tree: BPTree<K, V, SnapshotStore<S>, C>,
+}
+
+// Reads nodes from the tree's store, or from the versions kept for the
+// snapshot once the tree has moved on, and metadata from a copy taken with the
+// snapshot.
+pub struct SnapshotStore<S: NodeStore> {
+    store: Rc<RefCell<S>>,
+    versions: Versions,
+    kept: Rc<RefCell<KeptVersions>>,
+    metadata: HashMap<String, Vec<u8>>,
+}
+
+impl<S: NodeStore> Drop for SnapshotStore<S> {
+    fn drop(&mut self) {
+        // Anything that can't be deleted now is deleted by the tree's next
+        // persist.
+        let _ = self
+            .kept
+            .borrow_mut()
+            .release(&self.versions, &mut *self.store.borrow_mut());
+    }
+}
+
+impl<S: NodeStore> NodeStore for SnapshotStore<S> {
+    fn read(&self, id: Uuid) -> Result<Vec<u8>, Error> {
+        let id = RefCell::borrow(&self.versions)
+            .get(&id)
+            .copied()
+            .unwrap_or(id);
+        RefCell::borrow(&self.store).read(id)
+    }
+
+    fn write(&mut self, _: Uuid, _: &[u8]) -> Result<(), Error> {
+        Err(io::Error::from(ErrorKind::PermissionDenied).into())
+    }
+
+    fn delete(&mut self, _: Uuid) -> Result<(), Error> {
+        Err(io::Error::from(ErrorKind::PermissionDenied).into())
+    }
+
+    fn get_metadata(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.metadata.get(name).cloned())
+    }
+
+    fn put_metadata(&mut self, _: &str, _: &[u8]) -> Result<(), Error> {
+        Err(io::Error::from(ErrorKind::PermissionDenied).into())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<K, V, S: NodeStore, C: Codec + Clone> BPTree<K, V, S, C> {
+    // Takes a snapshot of the tree as it was last persisted, leaving out
+    // anything changed since. A tree that was never persisted has nothing to
+    // show yet.
+    pub fn snapshot(&self) -> Result<Snapshot<K, V, S, C>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let mut metadata = HashMap::new();
+        for name in [
+            ROOT_METADATA,
+            ROOT_KEY_METADATA,
+            ORDER_METADATA,
+            LEN_METADATA,
+        ] {
+            if let Some(data) = RefCell::borrow(&self.pager.store).get_metadata(name)? {
+                metadata.insert(name.into(), data);
+            }
+        }
+        let is_persisted = metadata.contains_key(ROOT_METADATA);
+
+        let store = SnapshotStore {
+            store: Rc::clone(&self.pager.store),
+            versions: self.pager.track_versions(),
+            kept: Rc::clone(&self.pager.versions),
+            metadata,
+        };
+        let mut pager = Pager::new(store, self.pager.codec.clone());
+        pager.version = self.pager.version;
+        pager.compression = self.pager.compression;
+        pager.cipher = self.pager.cipher.clone();
+        pager.set_capacity(self.pager.capacity());
+
+        let (root, order, len): (Option<Link<K, V>>, _, _) = if is_persisted {
+            (
+                pager.load_metadata(ROOT_METADATA)?,
+                pager.load_metadata(ORDER_METADATA)?,
+                pager.load_metadata(LEN_METADATA)?,
+            )
+        } else {
+            (None, self.order, 0)
+        };
+
+        if let Some(root) = root {
+            if pager.has_node_keys() {
+                let key = pager
+                    .load_metadata::<Option<Key>>(ROOT_KEY_METADATA)?
+                    .ok_or(Error::BadBPTree)?;
+                pager.learn_root_key(unsafe { (*root.as_ptr()).uuid() }, key);
+            }
+        }
+
+        Ok(Snapshot {
+            tree: BPTree {
+                root: root.map(|root| pager.intern(root)),
+                pager,
+                root_is_dirty: false,
+                order,
+                order_is_dirty: false,
+                len,
+                len_is_dirty: false,
+                schema: self.schema.clone(),
+                format_is_dirty: false,
+                has_persisted: true,
+            },
+        })
+    }
+}
+
+impl<K, V, S: NodeStore, C: Codec> Snapshot<K, V, S, C> {
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn set_cache_capacity(&mut self, capacity: Option<usize>) {
+        self.tree.set_cache_capacity(capacity);
+    }
+
+    // Reads only borrow the snapshot immutably and so never evict.
+    pub fn shrink_cache(&mut self) {
+        self.tree.shrink_cache();
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> Result<bool, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+    {
+        self.tree.contains_key(key)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<&V>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+    {
+        self.tree.get(key)
+    }
+
+    pub fn get_key_value<Q>(&self, key: &Q) -> Result<Option<(&K, &V)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+    {
+        self.tree.get_key_value(key)
+    }
+
+    pub fn first_key_value(&self) -> Result<Option<(&K, &V)>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.tree.first_key_value()
+    }
+
+    pub fn last_key_value(&self) -> Result<Option<(&K, &V)>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.tree.last_key_value()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V, SnapshotStore<S>, C> {
+        self.tree.iter()
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V, SnapshotStore<S>, C> {
+        self.tree.keys()
+    }
+
+    pub fn values(&self) -> Values<'_, K, V, SnapshotStore<S>, C> {
+        self.tree.values()
+    }
+
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, V, SnapshotStore<S>, C>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+        R: RangeBounds<Q>,
+    {
+        self.tree.range(range)
+    }
+}
@@ -0,0 +1,427 @@
+use super::{
+    codec::Codec,
+    error::Error,
+    iter::{first_leaf, last_leaf},
+    node::{Internal, Leaf, Link, Node},
+    store::NodeStore,
+    BPTree,
+};
+use serde::Deserialize;
+use std::cmp::Ordering;
+use uuid::Uuid;
+
+// The subtrees either side of a cut, from the bottom of the tree up. Their
+// roots may be underfull, but nothing below them is.
+type Pieces<K, V> = (Vec<Link<K, V>>, Vec<Link<K, V>>);
+
+impl<K, V, S: NodeStore, C: Codec> BPTree<K, V, S, C> {
+    // Cuts the tree under `root` along the path down to where `is_left` stops
+    // holding for its keys. Every node on the path is split into the children
+    // left of the path and those right of it.
+    pub(crate) unsafe fn cut(
+        &mut self,
+        root: Link<K, V>,
+        is_left: impl Fn(&K) -> bool,
+    ) -> Result<Pieces<K, V>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let (mut left, mut right) = (Vec::new(), Vec::new());
+        let mut cursor = root;
+
+        while let Node::Internal(node) = (*cursor.as_ptr()).access_mut(&self.pager)? {
+            node.is_dirty = true;
+
+            let index = node.keys.partition_point(&is_left);
+            let child = node.children[index];
+
+            // Drop the child on the path along with the split keys either side
+            // of it.
+            let right_children = node.children.split_off(index + 1);
+            let mut right_keys = node.keys.split_off(index);
+            node.children.pop();
+            node.keys.pop();
+            if !right_keys.is_empty() {
+                right_keys.remove(0);
+            }
+
+            if !right_children.is_empty() {
+                let piece = self.pager.alloc(Node::Internal(Internal {
+                    uuid: Uuid::new_v4(),
+                    keys: right_keys,
+                    children: right_children.clone(),
+                    parent: None,
+                    is_dirty: true,
+                }));
+                for child in right_children {
+                    self.set_parent(child, Some(piece))?;
+                }
+                right.extend(self.trim(piece)?);
+            }
+
+            left.extend(self.trim(cursor)?);
+            cursor = child;
+        }
+
+        if let Node::Leaf(node) = (*cursor.as_ptr()).access_mut(&self.pager)? {
+            node.is_dirty = true;
+
+            let index = node.keys.partition_point(&is_left);
+            let right_keys = node.keys.split_off(index);
+            let right_values = node.values.split_off(index);
+
+            if !right_keys.is_empty() {
+                right.push(self.pager.alloc(Node::Leaf(Leaf {
+                    uuid: Uuid::new_v4(),
+                    keys: right_keys,
+                    values: right_values,
+                    parent: None,
+                    next_leaf: node.next_leaf,
+                    prev_leaf: None,
+                    is_dirty: true,
+                })));
+            }
+
+            node.next_leaf = None;
+            if node.keys.is_empty() {
+                cursor.reclaim(&mut self.pager);
+            } else {
+                left.push(cursor);
+            }
+        }
+
+        left.reverse();
+        right.reverse();
+        Ok((left, right))
+    }
+
+    // Joins the pieces left of a cut back into one tree, at the root.
+    pub(crate) unsafe fn join_left(&mut self, pieces: Vec<Link<K, V>>) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.root = None;
+
+        for piece in pieces {
+            let root = self.root.take();
+            self.join(Some(piece), root)?;
+
+            // The last leaf may still point across the cut, and merging it
+            // into another leaf in a later join would follow that link.
+            if let Some(root) = self.root {
+                if let Node::Leaf(node) =
+                    (*last_leaf(root, &self.pager)?.as_ptr()).access_mut(&self.pager)?
+                {
+                    if node.next_leaf.is_some() {
+                        node.next_leaf = None;
+                        node.is_dirty = true;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Joins the pieces right of a cut back into one tree, at the root. Its
+    // first leaf is left pointing back across the cut.
+    pub(crate) unsafe fn join_right(&mut self, pieces: Vec<Link<K, V>>) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.root = None;
+
+        for piece in pieces {
+            let root = self.root.take();
+            self.join(root, Some(piece))?;
+        }
+
+        Ok(())
+    }
+
+    // Joins two trees into one at the root, where every key under `left` is
+    // less than every key under `right`. Either root may be underfull, as
+    // roots are allowed to be, but nothing below them can be.
+    pub(crate) unsafe fn join(
+        &mut self,
+        left: Option<Link<K, V>>,
+        right: Option<Link<K, V>>,
+    ) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.root_is_dirty = true;
+
+        let (left, right) = match (left, right) {
+            (Some(left), Some(right)) => (left, right),
+            (root, None) | (None, root) => {
+                self.root = root;
+                if let Some(root) = root {
+                    self.set_parent(root, None)?;
+                }
+                return Ok(());
+            }
+        };
+
+        self.set_parent(left, None)?;
+        self.set_parent(right, None)?;
+
+        // Link up the leaves either side of the seam.
+        let (last, first) = (
+            last_leaf(left, &self.pager)?,
+            first_leaf(right, &self.pager)?,
+        );
+        if let Node::Leaf(node) = (*last.as_ptr()).access_mut(&self.pager)? {
+            node.next_leaf = Some(first);
+            node.is_dirty = true;
+        }
+        if let Node::Leaf(node) = (*first.as_ptr()).access_mut(&self.pager)? {
+            node.prev_leaf = Some(last);
+            node.is_dirty = true;
+        }
+
+        let (left_height, right_height) = (self.height(left)?, self.height(right)?);
+        match left_height.cmp(&right_height) {
+            Ordering::Equal => {
+                self.root = Some(left);
+
+                // The two roots either merge into one or become the children
+                // of a new root.
+                let split_key = self.first_key(right)?;
+                if let Some(split_key) = self.rebalance(left, right, split_key)? {
+                    let new_root = self.pager.alloc(Node::Internal(Internal {
+                        uuid: Uuid::new_v4(),
+                        keys: vec![split_key],
+                        children: vec![left, right],
+                        parent: None,
+                        is_dirty: true,
+                    }));
+
+                    self.set_parent(left, Some(new_root))?;
+                    self.set_parent(right, Some(new_root))?;
+                    self.root = Some(new_root);
+                }
+            }
+            Ordering::Greater => {
+                self.root = Some(left);
+
+                // Graft the right tree onto the right edge of the left one,
+                // next to the last node at the same height.
+                let parent = self.descend(left, left_height - right_height - 1, true)?;
+                let sibling = match (*parent.as_ptr()).access(&self.pager)? {
+                    Node::Internal(parent) => parent.children[parent.children.len() - 1],
+                    Node::Leaf(_) => return Err(Error::BadBPTree),
+                };
+
+                let split_key = self.first_key(right)?;
+                if let Some(split_key) = self.rebalance(sibling, right, split_key)? {
+                    if let Node::Internal(parent) = (*parent.as_ptr()).access_mut(&self.pager)? {
+                        parent.keys.push(split_key);
+                        parent.children.push(right);
+                        parent.is_dirty = true;
+                    }
+                    self.set_parent(right, Some(parent))?;
+                    self.split_internal(parent)?;
+                }
+            }
+            Ordering::Less => {
+                self.root = Some(right);
+
+                // Graft the left tree onto the left edge of the right one,
+                // next to the first node at the same height.
+                let parent = self.descend(right, right_height - left_height - 1, false)?;
+                let sibling = match (*parent.as_ptr()).access(&self.pager)? {
+                    Node::Internal(parent) => parent.children[0],
+                    Node::Leaf(_) => return Err(Error::BadBPTree),
+                };
+
+                let split_key = self.first_key(sibling)?;
+                let split_key = self.rebalance(left, sibling, split_key)?;
+                if let Node::Internal(parent) = (*parent.as_ptr()).access_mut(&self.pager)? {
+                    match split_key {
+                        // The sibling was merged into the left tree's root.
+                        None => parent.children[0] = left,
+                        Some(split_key) => {
+                            parent.keys.insert(0, split_key);
+                            parent.children.insert(0, left);
+                        }
+                    }
+                    parent.is_dirty = true;
+                }
+                self.set_parent(left, Some(parent))?;
+                self.split_internal(parent)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Merges `right` into `left`, two neighbouring nodes of the same height
+    // split by `split_key`, if everything fits in one node. Otherwise evens
+    // them out and returns the new split key. Either way neither is left
+    // underfull unless both were.
+    pub(crate) unsafe fn rebalance(
+        &mut self,
+        left: Link<K, V>,
+        right: Link<K, V>,
+        split_key: K,
+    ) -> Result<Option<K>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        match (
+            (*left.as_ptr()).access_mut(&self.pager)?,
+            (*right.as_ptr()).access_mut(&self.pager)?,
+        ) {
+            (Node::Leaf(left_node), Node::Leaf(right_node)) => {
+                left_node.is_dirty = true;
+                right_node.is_dirty = true;
+
+                left_node.keys.append(&mut right_node.keys);
+                left_node.values.append(&mut right_node.values);
+
+                if !left_node.is_overfull(self.order) {
+                    // Relink around the merged leaf.
+                    left_node.next_leaf = right_node.next_leaf;
+                    if let Some(next_leaf) = right_node.next_leaf {
+                        if let Node::Leaf(next_leaf) =
+                            (*next_leaf.as_ptr()).access_mut(&self.pager)?
+                        {
+                            next_leaf.prev_leaf = Some(left);
+                            next_leaf.is_dirty = true;
+                        }
+                    }
+
+                    right.reclaim(&mut self.pager);
+                    return Ok(None);
+                }
+
+                let split_index = left_node.keys.len() / 2;
+                right_node.keys = left_node.keys.split_off(split_index);
+                right_node.values = left_node.values.split_off(split_index);
+
+                Ok(Some(right_node.keys[0].clone()))
+            }
+            (Node::Internal(left_node), Node::Internal(right_node)) => {
+                left_node.is_dirty = true;
+                right_node.is_dirty = true;
+
+                // Left keys, split key, then right keys.
+                left_node.keys.push(split_key);
+                left_node.keys.append(&mut right_node.keys);
+                for child in right_node.children.iter() {
+                    self.set_parent(*child, Some(left))?;
+                }
+                left_node.children.append(&mut right_node.children);
+
+                if !left_node.is_overfull(self.order) {
+                    right.reclaim(&mut self.pager);
+                    return Ok(None);
+                }
+
+                // One key moves up as the new split key.
+                let split_index = (left_node.keys.len() - 1) / 2;
+                right_node.keys = left_node.keys.split_off(split_index + 1);
+                right_node.children = left_node.children.split_off(split_index + 1);
+                for child in right_node.children.iter() {
+                    self.set_parent(*child, Some(right))?;
+                }
+
+                Ok(left_node.keys.pop())
+            }
+            _ => Err(Error::BadBPTree),
+        }
+    }
+
+    // Reclaims a piece of a split internal node that's left with no children,
+    // and replaces one that's left with a single child by that child.
+    unsafe fn trim(&mut self, cursor: Link<K, V>) -> Result<Option<Link<K, V>>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let child = match (*cursor.as_ptr()).access(&self.pager)? {
+            Node::Internal(node) if node.children.len() < 2 => node.children.first().copied(),
+            _ => return Ok(Some(cursor)),
+        };
+
+        cursor.reclaim(&mut self.pager);
+        Ok(child)
+    }
+
+    pub(crate) unsafe fn set_parent(
+        &mut self,
+        cursor: Link<K, V>,
+        parent: Option<Link<K, V>>,
+    ) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        match (*cursor.as_ptr()).access_mut(&self.pager)? {
+            Node::Internal(node) if node.parent != parent => {
+                node.parent = parent;
+                node.is_dirty = true;
+            }
+            Node::Leaf(node) if node.parent != parent => {
+                node.parent = parent;
+                node.is_dirty = true;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // The number of internal nodes between `cursor` and its leaves.
+    unsafe fn height(&self, mut cursor: Link<K, V>) -> Result<usize, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        let mut height = 0;
+        while let Node::Internal(node) = (*cursor.as_ptr()).access(&self.pager)? {
+            cursor = node.children[0];
+            height += 1;
+        }
+        Ok(height)
+    }
+
+    // Descends `depth` levels down the left or right edge under `cursor`.
+    unsafe fn descend(
+        &self,
+        mut cursor: Link<K, V>,
+        depth: usize,
+        last: bool,
+    ) -> Result<Link<K, V>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        for _ in 0..depth {
+            if let Node::Internal(node) = (*cursor.as_ptr()).access(&self.pager)? {
+                cursor = if last {
+                    node.children[node.children.len() - 1]
+                } else {
+                    node.children[0]
+                };
+            }
+        }
+        Ok(cursor)
+    }
+
+    unsafe fn first_key(&self, cursor: Link<K, V>) -> Result<K, Error>
+    where
+        for<'de> K: Deserialize<'de> + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        match (*first_leaf(cursor, &self.pager)?.as_ptr()).access(&self.pager)? {
+            Node::Leaf(node) => Ok(node.keys[0].clone()),
+            Node::Internal(_) => Err(Error::BadBPTree),
+        }
+    }
+}
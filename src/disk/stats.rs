@@ -0,0 +1,19 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub len: usize,
+    pub order: usize,
+    pub cached_nodes: usize,
+
+    // The total size of the nodes written or read since the tree was created
+    // or loaded, before and after compression.
+    pub encoded_bytes: u64,
+    pub stored_bytes: u64,
+}
+
+impl Stats {
+    // How many times smaller nodes are once compressed, or `None` if no nodes
+    // have been written or read yet.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        (self.stored_bytes > 0).then(|| self.encoded_bytes as f64 / self.stored_bytes as f64)
+    }
+}
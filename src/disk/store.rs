@@ -0,0 +1,97 @@
+use super::error::Error;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, ErrorKind},
+    rc::Rc,
+};
+use uuid::Uuid;
+
+// Where a `BPTree` keeps its serialized nodes and metadata. Nodes are keyed
+// by id, and metadata (the root, order and length of the tree) by name.
+//
+// Writes, deletes and metadata updates only need to take effect once `sync`
+// returns, which the tree calls at the end of every persist. They must take
+// effect atomically, though: after a crash, the store should hold either
+// everything from before the last `sync` or everything from after it.
+pub trait NodeStore {
+    fn read(&self, id: Uuid) -> Result<Vec<u8>, Error>;
+
+    fn write(&mut self, id: Uuid, data: &[u8]) -> Result<(), Error>;
+
+    // Deleting a node that isn't in the store isn't an error.
+    fn delete(&mut self, id: Uuid) -> Result<(), Error>;
+
+    // Deletes a node nothing committed refers to, without waiting for the
+    // next `sync`. Stores that can't do that leave it to the next `sync`, as
+    // `delete` does.
+    fn discard(&mut self, id: Uuid) -> Result<(), Error> {
+        self.delete(id)
+    }
+
+    fn get_metadata(&self, name: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    fn put_metadata(&mut self, name: &str, data: &[u8]) -> Result<(), Error>;
+
+    fn sync(&mut self) -> Result<(), Error>;
+}
+
+// Keeps everything in memory. Clones share the same contents, so a tree can
+// be persisted to one clone and loaded back from another.
+#[derive(Clone, Default)]
+pub struct MemStore {
+    inner: Rc<RefCell<MemStoreInner>>,
+}
+
+#[derive(Default)]
+struct MemStoreInner {
+    nodes: HashMap<Uuid, Vec<u8>>,
+    metadata: HashMap<String, Vec<u8>>,
+}
+
+impl MemStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.inner.borrow().nodes.len()
+    }
+}
+
+impl NodeStore for MemStore {
+    fn read(&self, id: Uuid) -> Result<Vec<u8>, Error> {
+        self.inner
+            .borrow()
+            .nodes
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| io::Error::from(ErrorKind::NotFound).into())
+    }
+
+    fn write(&mut self, id: Uuid, data: &[u8]) -> Result<(), Error> {
+        self.inner.borrow_mut().nodes.insert(id, data.into());
+        Ok(())
+    }
+
+    fn delete(&mut self, id: Uuid) -> Result<(), Error> {
+        self.inner.borrow_mut().nodes.remove(&id);
+        Ok(())
+    }
+
+    fn get_metadata(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.inner.borrow().metadata.get(name).cloned())
+    }
+
+    fn put_metadata(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        self.inner
+            .borrow_mut()
+            .metadata
+            .insert(name.into(), data.into());
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+use super::{
+    codec::{Bincode, Codec},
+    directory::DirStore,
+    entry::Entry,
+    error::Error,
+    guard::ValueMutationGuard,
+    iter::{Iter, IterMut, ValuesMut},
+    node::Link,
+    pager::Changes,
+    range::{Range, RangeMut},
+    store::NodeStore,
+    wal::Wal,
+    BPTree,
+};
+use serde::{Deserialize, Serialize};
+use std::{borrow::Borrow, ops::RangeBounds};
+use uuid::Uuid;
+
+// A set of changes to a `BPTree` that either all land or none do.
+//
+// Nothing reaches the store until the transaction is committed, which persists
+// the tree, along with whatever was changed before the transaction started.
+// Rolling back, or dropping the transaction without committing it, throws away
+// the changes made through it and leaves the tree as it was when it started,
+// persisted or not. Changes aren't logged while the transaction is open, so a
+// crash loses all of them rather than replaying some.
+pub struct Transaction<'a, K, V, S = DirStore, C = Bincode>
+where
+    S: NodeStore,
+    C: Codec,
+    for<'de> K: Deserialize<'de>,
+    for<'de> V: Deserialize<'de>,
+{
+    tree: &'a mut BPTree<K, V, S, C>,
+    wal: Option<Wal<K, V>>,
+    checkpoint: Option<Checkpoint>,
+}
+
+// The tree as it was when a transaction started.
+struct Checkpoint {
+    changes: Changes,
+    root: Option<Uuid>,
+    root_is_dirty: bool,
+    len: usize,
+    len_is_dirty: bool,
+}
+
+impl<K, V, S: NodeStore, C: Codec> BPTree<K, V, S, C> {
+    pub fn transaction(&mut self) -> Result<Transaction<'_, K, V, S, C>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Serialize,
+        for<'de> V: Deserialize<'de> + Serialize,
+    {
+        let checkpoint = Checkpoint {
+            changes: self.pager.save_changes()?,
+            root: self.root.map(|root| unsafe { (*root.as_ptr()).uuid() }),
+            root_is_dirty: self.root_is_dirty,
+            len: self.len,
+            len_is_dirty: self.len_is_dirty,
+        };
+
+        Ok(Transaction {
+            wal: self.pager.wal.get_mut().take(),
+            tree: self,
+            checkpoint: Some(checkpoint),
+        })
+    }
+
+    // Throws away everything changed since the checkpoint was taken. Nodes the
+    // checkpoint didn't change are read back from the store, which nothing has
+    // been persisted to since.
+    fn restore(&mut self, checkpoint: Checkpoint) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.pager.restore_changes(checkpoint.changes)?;
+        self.root = checkpoint
+            .root
+            .map(|root| self.pager.intern(Link::unloaded(root)));
+        self.root_is_dirty = checkpoint.root_is_dirty;
+        self.len = checkpoint.len;
+        self.len_is_dirty = checkpoint.len_is_dirty;
+
+        Ok(())
+    }
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> Transaction<'a, K, V, S, C>
+where
+    for<'de> K: Deserialize<'de>,
+    for<'de> V: Deserialize<'de>,
+{
+    // Persists the changes. If that fails, they stay in the tree, as they
+    // would after any failed persist.
+    pub fn commit(mut self) -> Result<(), Error>
+    where
+        K: Serialize,
+        V: Serialize,
+    {
+        self.checkpoint = None;
+        *self.tree.pager.wal.get_mut() = self.wal.take();
+        self.tree.persist()
+    }
+
+    pub fn rollback(mut self) -> Result<(), Error> {
+        let checkpoint = self.checkpoint.take().unwrap();
+        *self.tree.pager.wal.get_mut() = self.wal.take();
+        self.tree.restore(checkpoint)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> Result<bool, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+    {
+        self.tree.contains_key(key)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<&V>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+    {
+        self.tree.get(key)
+    }
+
+    pub fn get_mut<Q>(
+        &mut self,
+        key: &Q,
+    ) -> Result<Option<ValueMutationGuard<'_, K, V, S, C>>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+    {
+        self.tree.get_mut(key)
+    }
+
+    pub fn first_key_value(&self) -> Result<Option<(&K, &V)>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.tree.first_key_value()
+    }
+
+    pub fn last_key_value(&self) -> Result<Option<(&K, &V)>, Error>
+    where
+        for<'de> K: Deserialize<'de>,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.tree.last_key_value()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V, S, C> {
+        self.tree.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V, S, C> {
+        self.tree.iter_mut()
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V, S, C> {
+        self.tree.values_mut()
+    }
+
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, V, S, C>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+        R: RangeBounds<Q>,
+    {
+        self.tree.range(range)
+    }
+
+    pub fn range_mut<Q, R>(&mut self, range: R) -> RangeMut<'_, K, V, S, C>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q>,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+        R: RangeBounds<Q>,
+    {
+        self.tree.range_mut(range)
+    }
+
+    pub fn entry(&mut self, key: K) -> Result<Entry<'_, K, V, S, C>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.tree.entry(key)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.tree.insert(key, value)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Result<Option<V>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q> + Clone,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+    {
+        self.tree.remove(key)
+    }
+
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Result<Option<(K, V)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q> + Clone,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+    {
+        self.tree.remove_entry(key)
+    }
+
+    pub fn remove_range<Q, R>(&mut self, range: R) -> Result<usize, Error>
+    where
+        for<'de> K: Deserialize<'de> + Borrow<Q> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+        Q: Ord,
+        R: RangeBounds<Q>,
+    {
+        self.tree.remove_range(range)
+    }
+
+    pub fn pop_first(&mut self) -> Result<Option<(K, V)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.tree.pop_first()
+    }
+
+    pub fn pop_last(&mut self) -> Result<Option<(K, V)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Ord + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.tree.pop_last()
+    }
+
+    pub fn retain<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Clone,
+        for<'de> V: Deserialize<'de>,
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.tree.retain(f)
+    }
+
+    pub fn drain(&mut self) -> Result<Vec<(K, V)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Clone,
+        for<'de> V: Deserialize<'de>,
+    {
+        self.tree.drain()
+    }
+
+    pub fn extract_if<F>(&mut self, pred: F) -> Result<Vec<(K, V)>, Error>
+    where
+        for<'de> K: Deserialize<'de> + Clone,
+        for<'de> V: Deserialize<'de>,
+        F: FnMut(&K, &V) -> bool,
+    {
+        self.tree.extract_if(pred)
+    }
+}
+
+impl<'a, K, V, S: NodeStore, C: Codec> Drop for Transaction<'a, K, V, S, C>
+where
+    for<'de> K: Deserialize<'de>,
+    for<'de> V: Deserialize<'de>,
+{
+    // There's nowhere to report a failed rollback from here, so a transaction
+    // that has to be rolled back reliably should be rolled back explicitly.
+    fn drop(&mut self) {
+        if let Some(checkpoint) = self.checkpoint.take() {
+            *self.tree.pager.wal.get_mut() = self.wal.take();
+            let _ = self.tree.restore(checkpoint);
+        }
+    }
+}
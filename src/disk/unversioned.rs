@@ -0,0 +1,106 @@
+use super::{
+    directory::DirStore,
+    error::Error,
+    format::FORMAT_VERSION,
+    persist::{ORDER_METADATA, ROOT_METADATA},
+    store::NodeStore,
+    BPTree,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Nodes as trees wrote them before the format was recorded: plain bincode,
+// without a checksum, and with leaves only linked forwards.
+#[derive(Deserialize)]
+enum UnversionedNode<K, V> {
+    Internal(UnversionedInternal<K>),
+    Leaf(UnversionedLeaf<K, V>),
+}
+
+#[derive(Deserialize)]
+struct UnversionedInternal<K> {
+    _uuid: Uuid,
+    _keys: Vec<K>,
+    children: Vec<Uuid>,
+    _parent: Option<Uuid>,
+}
+
+#[derive(Deserialize)]
+struct UnversionedLeaf<K, V> {
+    _uuid: Uuid,
+    keys: Vec<K>,
+    values: Vec<V>,
+    _parent: Option<Uuid>,
+    _next_leaf: Option<Uuid>,
+}
+
+// Rewrites a directory left by a build from before the format was recorded,
+// which is what format version 0 stands for, into the current format.
+// `BPTree::load` and `BPTree::load_with` run this unless given a migration of
+// their own.
+//
+// The old nodes are deleted in the same commit that writes the new tree, so a
+// crash part way through leaves the old tree in place to be migrated again.
+pub fn migrate_unversioned<K, V>(store: &mut DirStore, version: u32) -> Result<(), Error>
+where
+    for<'de> K: Deserialize<'de> + Serialize + Ord + Clone,
+    for<'de> V: Deserialize<'de> + Serialize,
+{
+    if version != 0 {
+        return Err(Error::UnsupportedVersion {
+            found: version,
+            supported: FORMAT_VERSION,
+        });
+    }
+
+    let root: Option<Uuid> = read_metadata(store, ROOT_METADATA)?;
+    let order: usize = read_metadata(store, ORDER_METADATA)?;
+
+    let mut nodes = Vec::new();
+    let mut entries = Vec::new();
+    if let Some(root) = root {
+        read_unversioned::<K, V>(store, root, &mut nodes, &mut entries)?;
+    }
+
+    let mut tree: BPTree<K, V> = BPTree::with_order(store.path(), order);
+    for (key, value) in entries {
+        tree.insert(key, value)?;
+    }
+    for uuid in nodes {
+        tree.pager.store.borrow_mut().delete(uuid)?;
+    }
+    tree.persist()
+}
+
+fn read_metadata<T>(store: &DirStore, name: &str) -> Result<T, Error>
+where
+    for<'de> T: Deserialize<'de>,
+{
+    let data = store.get_metadata(name)?.ok_or(Error::BadBPTree)?;
+    Ok(bincode::deserialize(&data)?)
+}
+
+// Collects the nodes under `uuid` and, in order, the entries in them.
+fn read_unversioned<K, V>(
+    store: &DirStore,
+    uuid: Uuid,
+    nodes: &mut Vec<Uuid>,
+    entries: &mut Vec<(K, V)>,
+) -> Result<(), Error>
+where
+    for<'de> K: Deserialize<'de>,
+    for<'de> V: Deserialize<'de>,
+{
+    nodes.push(uuid);
+
+    match bincode::deserialize(&store.read(uuid)?)? {
+        UnversionedNode::<K, V>::Internal(node) => {
+            for child in node.children {
+                read_unversioned(store, child, nodes, entries)?;
+            }
+        }
+        UnversionedNode::Leaf(node) => entries.extend(node.keys.into_iter().zip(node.values)),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,239 @@
+use super::{
+    codec::Codec, directory::DirStore, encryption::Cipher, error::Error, store::NodeStore, BPTree,
+};
+use path_macro::path;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+const WAL: &str = "wal";
+
+impl<K, V, C: Codec> BPTree<K, V, DirStore, C> {
+    // Logs mutations to a `wal` file in the tree's directory. `load` replays
+    // the log if it finds one.
+    pub fn enable_wal(&mut self) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Serialize + Ord + Clone,
+        for<'de> V: Deserialize<'de> + Serialize,
+    {
+        let path = path![self.pager.store.borrow().path() / WAL];
+        self.enable_wal_at(path)
+    }
+
+    pub(crate) fn has_wal(path: &Path) -> bool {
+        path![path / WAL].exists()
+    }
+}
+
+impl<K, V, S: NodeStore, C: Codec> BPTree<K, V, S, C> {
+    // Logs every insert, removal and value mutation to the log at `path`, so
+    // that syncing the log is enough to make them durable. Whatever is already
+    // in the log is replayed first. Persisting the tree checkpoints it, which
+    // empties the log.
+    pub fn enable_wal_at(&mut self, path: impl AsRef<Path>) -> Result<(), Error>
+    where
+        for<'de> K: Deserialize<'de> + Serialize + Ord + Clone,
+        for<'de> V: Deserialize<'de> + Serialize,
+    {
+        let (wal, records) = Wal::open(path, self.pager.cipher.clone())?;
+
+        // Records are replayed before the log is attached so they aren't
+        // logged a second time.
+        for record in records {
+            match record {
+                Record::Insert(key, value) => {
+                    self.insert_unlogged(key, value)?;
+                }
+                Record::Remove(key) => {
+                    self.remove_entry_unlogged(&key)?;
+                }
+            }
+        }
+
+        *self.pager.wal.get_mut() = Some(wal);
+
+        // A tree that was never persisted has nothing for the log to be
+        // replayed on top of, so checkpoint it now.
+        if self.order_is_dirty {
+            self.persist()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn sync_wal(&mut self) -> Result<(), Error> {
+        match self.pager.wal.get_mut() {
+            Some(wal) => wal.sync(),
+            None => Ok(()),
+        }
+    }
+}
+
+// Each record is framed by its length and checksum, so a record torn by a
+// crash can be told apart from the ones before it.
+const FRAME_HEADER: usize = 4 + 4;
+
+// Encrypted records are bound to where they are in the log, so they can't be
+// reordered, repeated or left out without it showing.
+fn aad(record: u64) -> Vec<u8> {
+    let mut aad = WAL.as_bytes().to_vec();
+    aad.extend_from_slice(&record.to_le_bytes());
+    aad
+}
+
+#[derive(Deserialize, Serialize)]
+pub(crate) enum Record<K, V> {
+    Insert(K, V),
+    Remove(K),
+}
+
+// An append-only log of the mutations made since the tree was last persisted.
+//
+// Appending only needs the records to be serializable, which the rest of the
+// tree's mutating methods don't require, so the log carries encoders that are
+// instantiated when it's opened.
+pub(crate) struct Wal<K, V> {
+    file: File,
+    encode_insert: fn(&K, &V) -> Result<Vec<u8>, Error>,
+    encode_remove: fn(&K) -> Result<Vec<u8>, Error>,
+
+    // Encrypts records when the tree is encrypted.
+    pub(crate) cipher: Option<Cipher>,
+
+    // How many records are in the log.
+    len: u64,
+
+    // An append that failed somewhere it couldn't be reported, such as when a
+    // value guard was dropped. It's returned by the next sync instead.
+    error: Option<Error>,
+}
+
+fn encode_insert<K: Serialize, V: Serialize>(key: &K, value: &V) -> Result<Vec<u8>, Error> {
+    Ok(bincode::serialize(&Record::<&K, &V>::Insert(key, value))?)
+}
+
+fn encode_remove<K: Serialize, V: Serialize>(key: &K) -> Result<Vec<u8>, Error> {
+    Ok(bincode::serialize(&Record::<&K, &V>::Remove(key))?)
+}
+
+impl<K, V> Wal<K, V> {
+    // Opens the log at `path`, creating it if needed, and returns the records
+    // already in it. A torn record at the end of the log is cut off.
+    pub fn open(
+        path: impl AsRef<Path>,
+        cipher: Option<Cipher>,
+    ) -> Result<(Self, Vec<Record<K, V>>), Error>
+    where
+        for<'de> K: Deserialize<'de> + Serialize,
+        for<'de> V: Deserialize<'de> + Serialize,
+    {
+        if let Some(parent) = path.as_ref().parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let data = match fs::read(path.as_ref()) {
+            Ok(data) => data,
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut records = Vec::new();
+        let mut offset = 0;
+
+        while let Some(header) = data.get(offset..offset + FRAME_HEADER) {
+            let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+            let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+            let payload = match data.get(offset + FRAME_HEADER..offset + FRAME_HEADER + len) {
+                Some(payload) if crc32fast::hash(payload) == checksum => payload,
+                _ => break,
+            };
+
+            let index = records.len() as u64;
+            let record = match &cipher {
+                Some(cipher) => cipher
+                    .decrypt(&aad(index), payload)
+                    .ok_or(Error::LogAuthentication { record: index })?,
+                None => payload.to_vec(),
+            };
+
+            records.push(bincode::deserialize(&record)?);
+            offset += FRAME_HEADER + len;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path.as_ref())?;
+        file.set_len(offset as u64)?;
+        file.seek(SeekFrom::End(0))?;
+
+        Ok((
+            Self {
+                file,
+                encode_insert: encode_insert::<K, V>,
+                encode_remove: encode_remove::<K, V>,
+                cipher,
+                len: records.len() as u64,
+                error: None,
+            },
+            records,
+        ))
+    }
+
+    pub fn encode_insert(&self, key: &K, value: &V) -> Result<Vec<u8>, Error> {
+        (self.encode_insert)(key, value)
+    }
+
+    pub fn encode_remove(&self, key: &K) -> Result<Vec<u8>, Error> {
+        (self.encode_remove)(key)
+    }
+
+    pub fn append(&mut self, record: &[u8]) -> Result<(), Error> {
+        let encrypted;
+        let record = match &self.cipher {
+            Some(cipher) => {
+                encrypted = cipher.encrypt(&aad(self.len), record);
+                &encrypted[..]
+            }
+            None => record,
+        };
+
+        let mut frame = Vec::with_capacity(FRAME_HEADER + record.len());
+        frame.extend_from_slice(&(record.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc32fast::hash(record).to_le_bytes());
+        frame.extend_from_slice(record);
+        self.file.write_all(&frame)?;
+
+        self.len += 1;
+        Ok(())
+    }
+
+    // Appends a record where a failure can't be returned.
+    pub fn append_or_defer(&mut self, record: Result<Vec<u8>, Error>) {
+        if let Err(err) = record.and_then(|record| self.append(&record)) {
+            self.error.get_or_insert(err);
+        }
+    }
+
+    pub fn sync(&mut self) -> Result<(), Error> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        Ok(self.file.sync_data()?)
+    }
+
+    // Empties the log once everything in it has been persisted.
+    pub fn truncate(&mut self) -> Result<(), Error> {
+        // A deferred failure only lost a record of something now persisted.
+        self.error = None;
+        self.len = 0;
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        Ok(self.file.sync_data()?)
+    }
+}
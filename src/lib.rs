@@ -1,4 +1,21 @@
 mod disk;
 mod mem;
 
-pub use {disk::BPTree, mem::BPTreeMap};
+pub use {
+    disk::{
+        error::Error, migrate_unversioned, type_fingerprint, BPTree, BPTreeDiskSet, Bincode, Codec,
+        Compression, DirStore, Key, LoadOptions, MemStore, Migration, NodeStore, PageFileStore,
+        Ron, Stats,
+    },
+    mem::{BPTreeMap, BPTreeSet},
+};
+
+// The types that come with `BPTree`, kept apart from their `BPTreeMap`
+// counterparts like `std::collections::btree_map` does.
+pub mod bptree {
+    pub use crate::disk::{Entry, OccupiedEntry, Snapshot, Transaction, VacantEntry};
+}
+
+pub mod bptree_map {
+    pub use crate::mem::{Entry, OccupiedEntry, VacantEntry};
+}
@@ -0,0 +1,142 @@
+use super::{
+    node::{Internal, Leaf, Link, Node},
+    BPTreeMap,
+};
+use crate::Error;
+use std::ptr::NonNull;
+
+impl<K, V> BPTreeMap<K, V> {
+    // Builds a map out of entries sorted by strictly increasing key, packing
+    // the nodes as full as they go.
+    pub fn from_sorted_iter(
+        order: usize,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self, Error>
+    where
+        K: Ord + Clone,
+    {
+        Self::from_sorted_iter_with_fill(order, 1.0, entries)
+    }
+
+    // Like `from_sorted_iter`, but fills the nodes to about `fill` of their
+    // capacity, leaving room for later inserts to land without splitting.
+    pub fn from_sorted_iter_with_fill(
+        order: usize,
+        fill: f64,
+        entries: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<Self, Error>
+    where
+        K: Ord + Clone,
+    {
+        assert!(
+            fill > 0.0 && fill <= 1.0,
+            "fill factor must be in (0, 1] in BPTreeMap"
+        );
+
+        let mut keys = Vec::new();
+        let mut values = Vec::new();
+        for (index, (key, value)) in entries.into_iter().enumerate() {
+            if keys.last().is_some_and(|last| *last >= key) {
+                return Err(Error::Unsorted { index });
+            }
+            keys.push(key);
+            values.push(value);
+        }
+
+        let mut tree = Self::with_order(order);
+        if keys.is_empty() {
+            return Ok(tree);
+        }
+        tree.len = keys.len();
+
+        // Each node of a level, along with the smallest key under it.
+        let mut level = Vec::new();
+
+        let mut keys = keys.into_iter();
+        let mut values = values.into_iter();
+        let mut prev_leaf: Option<Link<K, V>> = None;
+
+        let target = fill_target(order, fill, order.div_ceil(2));
+        for size in node_sizes(tree.len, target, order.div_ceil(2)) {
+            let leaf_keys = keys.by_ref().take(size).collect::<Vec<_>>();
+            let min_key = leaf_keys[0].clone();
+
+            unsafe {
+                let leaf = NonNull::new_unchecked(Box::into_raw(Box::new(Node::Leaf(Leaf {
+                    keys: leaf_keys,
+                    values: values.by_ref().take(size).collect(),
+                    parent: None,
+                    next_leaf: None,
+                    prev_leaf,
+                }))));
+
+                if let Some(prev_leaf) = prev_leaf {
+                    if let Node::Leaf(prev_leaf) = &mut (*prev_leaf.as_ptr()) {
+                        prev_leaf.next_leaf = Some(leaf);
+                    }
+                }
+
+                level.push((min_key, leaf));
+                prev_leaf = Some(leaf);
+            }
+        }
+
+        // Internal nodes hold between `order / 2` and `order` keys, and one
+        // more child than keys.
+        let target = fill_target(order + 1, fill, order / 2 + 1);
+        while level.len() > 1 {
+            let mut children = level.into_iter();
+            let mut parents = Vec::new();
+
+            for size in node_sizes(children.len(), target, order / 2 + 1) {
+                let (min_key, first_child) = children.next().unwrap();
+                let (keys, rest): (Vec<_>, Vec<_>) = children.by_ref().take(size - 1).unzip();
+
+                let mut node_children = vec![first_child];
+                node_children.extend(rest);
+
+                unsafe {
+                    let parent =
+                        NonNull::new_unchecked(Box::into_raw(Box::new(Node::Internal(Internal {
+                            keys,
+                            children: node_children.clone(),
+                            parent: None,
+                        }))));
+
+                    for child in node_children {
+                        match &mut (*child.as_ptr()) {
+                            Node::Internal(child) => child.parent = Some(parent),
+                            Node::Leaf(child) => child.parent = Some(parent),
+                        }
+                    }
+
+                    parents.push((min_key, parent));
+                }
+            }
+
+            level = parents;
+        }
+
+        tree.root = level.pop().map(|(_, root)| root);
+
+        Ok(tree)
+    }
+}
+
+// The number of entries or children to put in each node for a fill factor,
+// given the most and fewest a node can hold.
+fn fill_target(max: usize, fill: f64, min: usize) -> usize {
+    ((max as f64 * fill).round() as usize).clamp(min.max(1), max)
+}
+
+// Splits `len` entries or children into nodes of about `target` each, as
+// evenly as possible. The nodes must hold at least `min` each unless there's
+// just one, which becomes the root, so fewer nodes are used if need be.
+fn node_sizes(len: usize, target: usize, min: usize) -> impl Iterator<Item = usize> {
+    let mut nodes = len.div_ceil(target);
+    while nodes > 1 && len / nodes < min {
+        nodes -= 1;
+    }
+
+    (0..nodes).map(move |index| len / nodes + usize::from(index < len % nodes))
+}
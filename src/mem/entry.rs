@@ -0,0 +1,196 @@
+use super::{
+    node::{Leaf, Link, Node},
+    BPTreeMap,
+};
+use std::{
+    fmt::{self, Debug},
+    mem,
+};
+
+// A view into a single entry of a map, found with one descent so that the
+// entry can be read, changed, filled or removed without another.
+pub enum Entry<'a, K, V> {
+    Vacant(VacantEntry<'a, K, V>),
+    Occupied(OccupiedEntry<'a, K, V>),
+}
+
+pub struct VacantEntry<'a, K, V> {
+    key: K,
+    // The leaf the key belongs in and where, unless the map is empty.
+    cursor: Option<(Link<K, V>, usize)>,
+    map: &'a mut BPTreeMap<K, V>,
+}
+
+pub struct OccupiedEntry<'a, K, V> {
+    cursor: Link<K, V>,
+    cursor_index: usize,
+    index: usize,
+    map: &'a mut BPTreeMap<K, V>,
+}
+
+impl<K, V> BPTreeMap<K, V> {
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V>
+    where
+        K: Ord,
+    {
+        match self.search_leaf(&key) {
+            Some((cursor, cursor_index, Ok(index))) => Entry::Occupied(OccupiedEntry {
+                cursor,
+                cursor_index,
+                index,
+                map: self,
+            }),
+            Some((cursor, _, Err(index))) => Entry::Vacant(VacantEntry {
+                key,
+                cursor: Some((cursor, index)),
+                map: self,
+            }),
+            None => Entry::Vacant(VacantEntry {
+                key,
+                cursor: None,
+                map: self,
+            }),
+        }
+    }
+}
+
+impl<'a, K, V> Entry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Vacant(entry) => entry.key(),
+            Entry::Occupied(entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V
+    where
+        K: Ord + Clone,
+    {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V
+    where
+        K: Ord + Clone,
+    {
+        match self {
+            Entry::Vacant(entry) => entry.insert(default()),
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V
+    where
+        K: Ord + Clone,
+    {
+        match self {
+            Entry::Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value)
+            }
+            Entry::Occupied(entry) => entry.into_mut(),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        K: Ord + Clone,
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+        }
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    pub fn insert(self, value: V) -> &'a mut V
+    where
+        K: Ord + Clone,
+    {
+        let (cursor, index) = match self.cursor {
+            Some((cursor, index)) => unsafe { self.map.insert_at(cursor, index, self.key, value) },
+            None => (self.map.insert_root(self.key, value), 0),
+        };
+
+        unsafe { &mut leaf(cursor).values[index] }
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        unsafe { &leaf(self.cursor).keys[self.index] }
+    }
+
+    pub fn get(&self) -> &V {
+        unsafe { &leaf(self.cursor).values[self.index] }
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        unsafe { &mut leaf(self.cursor).values[self.index] }
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        unsafe { &mut leaf(self.cursor).values[self.index] }
+    }
+
+    // Swaps in a new value, returning the old one.
+    pub fn insert(&mut self, mut value: V) -> V {
+        mem::swap(self.get_mut(), &mut value);
+        value
+    }
+
+    pub fn remove_entry(self) -> (K, V)
+    where
+        K: Ord + Clone,
+    {
+        unsafe {
+            self.map
+                .remove_at::<K>(self.cursor, self.cursor_index, self.index)
+        }
+    }
+
+    pub fn remove(self) -> V
+    where
+        K: Ord + Clone,
+    {
+        self.remove_entry().1
+    }
+}
+
+// The leaf an entry was found in.
+unsafe fn leaf<'a, K, V>(cursor: Link<K, V>) -> &'a mut Leaf<K, V> {
+    match &mut *cursor.as_ptr() {
+        Node::Leaf(node) => node,
+        Node::Internal(_) => unreachable!("entry cursor isn't a leaf"),
+    }
+}
+
+impl<'a, K, V> Debug for Entry<'a, K, V>
+where
+    K: Debug,
+    V: Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Vacant(entry) => write!(f, "Entry({:?})", entry.key()),
+            Entry::Occupied(entry) => write!(f, "Entry({:?}: {:?})", entry.key(), entry.get()),
+        }
+    }
+}
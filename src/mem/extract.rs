@@ -0,0 +1,221 @@
+use super::{
+    iter::first_leaf,
+    node::{Link, Node},
+    split::set_parent,
+    BPTreeMap,
+};
+use std::vec;
+
+impl<K, V> BPTreeMap<K, V> {
+    // Keeps only the entries `f` returns true for.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        K: Clone,
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.extract_if(|key, value| !f(key, value)).for_each(drop);
+    }
+
+    // Removes every entry, returning them in order.
+    pub fn drain(&mut self) -> vec::IntoIter<(K, V)> {
+        self.take_entries().into_iter()
+    }
+
+    // Removes and yields the entries `pred` returns true for, in order.
+    //
+    // Entries are taken out of the leaves as the iterator walks the leaf
+    // chain, and the tree is only rebalanced once it's dropped. Entries it
+    // never got to are kept.
+    pub fn extract_if<F>(&mut self, pred: F) -> ExtractIf<'_, K, V, F>
+    where
+        K: Clone,
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let leaf = self.root.map(|root| unsafe { first_leaf(root) });
+        ExtractIf {
+            tree: self,
+            leaf,
+            index: 0,
+            pred,
+            extracted: false,
+        }
+    }
+
+    // Restores the occupancy invariants after entries were taken out of
+    // leaves anywhere in the tree.
+    unsafe fn rebalance_all(&mut self)
+    where
+        K: Clone,
+    {
+        if let Some(root) = self.root {
+            self.rebalance_subtree(root);
+        }
+
+        // That may leave the root with a single child, or with nothing.
+        while let Some(root) = self.root {
+            match &(*root.as_ptr()) {
+                Node::Internal(node) if node.children.len() < 2 => {
+                    self.root = node.children.first().copied();
+                }
+                Node::Leaf(node) if node.keys.is_empty() => self.root = None,
+                _ => break,
+            }
+
+            let _ = Box::from_raw(root.as_ptr());
+            if let Some(root) = self.root {
+                set_parent(root, None);
+            }
+        }
+    }
+
+    // Rebalances everything under `cursor` from the bottom up.
+    unsafe fn rebalance_subtree(&mut self, cursor: Link<K, V>)
+    where
+        K: Clone,
+    {
+        if let Node::Internal(node) = &(*cursor.as_ptr()) {
+            for child in node.children.clone() {
+                self.rebalance_subtree(child);
+            }
+            self.rebalance_children(cursor);
+        }
+    }
+
+    // Drops the empty children of an internal node, then merges or evens out
+    // each underfull child with a neighbour. Only the node itself can be left
+    // underfull, when it ends up with a single child.
+    unsafe fn rebalance_children(&mut self, cursor: Link<K, V>)
+    where
+        K: Clone,
+    {
+        let node = match &mut (*cursor.as_ptr()) {
+            Node::Internal(node) => node,
+            Node::Leaf(_) => return,
+        };
+
+        let mut index = 0;
+        while index < node.children.len() {
+            let child = node.children[index];
+            let is_empty = match &(*child.as_ptr()) {
+                Node::Internal(child) => child.children.is_empty(),
+                Node::Leaf(child) => child.keys.is_empty(),
+            };
+            if !is_empty {
+                index += 1;
+                continue;
+            }
+
+            if !node.keys.is_empty() {
+                node.keys.remove(index.saturating_sub(1));
+            }
+            node.children.remove(index);
+
+            if let Node::Leaf(child) = &(*child.as_ptr()) {
+                unlink_leaf(child.prev_leaf, child.next_leaf);
+            }
+            let _ = Box::from_raw(child.as_ptr());
+        }
+
+        while node.children.len() > 1 {
+            let index = match node
+                .children
+                .iter()
+                .position(|child| is_underfull(*child, self.order))
+            {
+                Some(index) => index,
+                None => break,
+            };
+
+            // Pair it with its left neighbour if it has one.
+            let index = index.saturating_sub(1);
+            let (left, right) = (node.children[index], node.children[index + 1]);
+            match self.rebalance(left, right, node.keys[index].clone()) {
+                Some(split_key) => {
+                    node.keys[index] = split_key;
+                    self.rebalance_children(left);
+                    self.rebalance_children(right);
+                }
+                None => {
+                    node.keys.remove(index);
+                    node.children.remove(index + 1);
+                    self.rebalance_children(left);
+                }
+            }
+        }
+    }
+}
+
+unsafe fn is_underfull<K, V>(cursor: Link<K, V>, order: usize) -> bool {
+    match &(*cursor.as_ptr()) {
+        Node::Internal(node) => node.is_underfull(order),
+        Node::Leaf(node) => node.is_underfull(order),
+    }
+}
+
+// Links the neighbours of a removed leaf to each other.
+unsafe fn unlink_leaf<K, V>(prev_leaf: Option<Link<K, V>>, next_leaf: Option<Link<K, V>>) {
+    if let Some(Node::Leaf(prev)) = prev_leaf.map(|leaf| &mut (*leaf.as_ptr())) {
+        prev.next_leaf = next_leaf;
+    }
+    if let Some(Node::Leaf(next)) = next_leaf.map(|leaf| &mut (*leaf.as_ptr())) {
+        next.prev_leaf = prev_leaf;
+    }
+}
+
+pub struct ExtractIf<'a, K, V, F>
+where
+    K: Clone,
+    F: FnMut(&K, &mut V) -> bool,
+{
+    tree: &'a mut BPTreeMap<K, V>,
+    leaf: Option<Link<K, V>>,
+    index: usize,
+    pred: F,
+    extracted: bool,
+}
+
+impl<'a, K, V, F> Iterator for ExtractIf<'a, K, V, F>
+where
+    K: Clone,
+    F: FnMut(&K, &mut V) -> bool,
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        unsafe {
+            while let Some(Node::Leaf(node)) = self.leaf.map(|leaf| &mut (*leaf.as_ptr())) {
+                while self.index < node.keys.len() {
+                    if (self.pred)(&node.keys[self.index], &mut node.values[self.index]) {
+                        self.tree.len -= 1;
+                        self.extracted = true;
+                        return Some((
+                            node.keys.remove(self.index),
+                            node.values.remove(self.index),
+                        ));
+                    }
+                    self.index += 1;
+                }
+
+                self.leaf = node.next_leaf;
+                self.index = 0;
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.tree.len))
+    }
+}
+
+impl<'a, K, V, F> Drop for ExtractIf<'a, K, V, F>
+where
+    K: Clone,
+    F: FnMut(&K, &mut V) -> bool,
+{
+    fn drop(&mut self) {
+        if self.extracted {
+            unsafe { self.tree.rebalance_all() }
+        }
+    }
+}
@@ -1,7 +1,85 @@
-use super::{node::Node, BPTreeMap};
+use super::{
+    node::{Link, Node},
+    BPTreeMap,
+};
 use std::borrow::Borrow;
 
 impl<K, V> BPTreeMap<K, V> {
+    // Finds the leaf a key is or belongs in, along with which child of its
+    // parent it is and where the key is or belongs in it.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn search_leaf<Q>(
+        &self,
+        key: &Q,
+    ) -> Option<(Link<K, V>, usize, Result<usize, usize>)>
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+    {
+        unsafe {
+            let mut cursor = self.root?;
+            let mut cursor_index = 0;
+
+            while let Node::Internal(node) = &(*cursor.as_ptr()) {
+                cursor_index = match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
+                    Ok(index) => index + 1,
+                    Err(index) => index,
+                };
+                cursor = node.children[cursor_index];
+            }
+
+            match &(*cursor.as_ptr()) {
+                Node::Leaf(node) => Some((
+                    cursor,
+                    cursor_index,
+                    node.keys.binary_search_by(|probe| probe.borrow().cmp(key)),
+                )),
+                Node::Internal(_) => None,
+            }
+        }
+    }
+
+    // Finds the leftmost or rightmost leaf, along with which child of its
+    // parent it is and the index of its first or last entry.
+    pub(crate) fn edge_leaf(&self, last: bool) -> Option<(Link<K, V>, usize, usize)> {
+        unsafe {
+            let mut cursor = self.root?;
+            let mut cursor_index = 0;
+
+            loop {
+                match &(*cursor.as_ptr()) {
+                    Node::Internal(node) => {
+                        cursor_index = if last { node.children.len() - 1 } else { 0 };
+                        cursor = node.children[cursor_index];
+                    }
+                    Node::Leaf(node) => {
+                        let index = if last { node.keys.len() - 1 } else { 0 };
+                        return Some((cursor, cursor_index, index));
+                    }
+                }
+            }
+        }
+    }
+
+    fn edge_key_value(&self, last: bool) -> Option<(&K, &V)> {
+        let (cursor, _, index) = self.edge_leaf(last)?;
+
+        unsafe {
+            match &(*cursor.as_ptr()) {
+                Node::Leaf(node) => Some((&node.keys[index], &node.values[index])),
+                Node::Internal(_) => None,
+            }
+        }
+    }
+
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        self.edge_key_value(false)
+    }
+
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        self.edge_key_value(true)
+    }
+
     pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
     where
         K: Borrow<Q>,
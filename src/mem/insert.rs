@@ -1,109 +1,120 @@
 use super::{
+    entry::Entry,
     node::{Internal, Leaf, Link, Node},
     BPTreeMap,
 };
-use std::{mem, ptr::NonNull};
+use std::ptr::NonNull;
 
 impl<K, V> BPTreeMap<K, V> {
-    pub fn insert(&mut self, key: K, mut value: V) -> Option<V>
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
     where
         K: Ord + Clone,
     {
+        match self.entry(key) {
+            Entry::Occupied(mut entry) => Some(entry.insert(value)),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn insert_root(&mut self, key: K, value: V) -> Link<K, V> {
         unsafe {
-            if self.root.is_none() {
-                let new_root = NonNull::new_unchecked(Box::into_raw(Box::new(Node::Leaf(Leaf {
-                    keys: vec![key],
-                    values: vec![value],
-                    parent: None,
-                    next_leaf: None,
-                }))));
+            let new_root = NonNull::new_unchecked(Box::into_raw(Box::new(Node::Leaf(Leaf {
+                keys: vec![key],
+                values: vec![value],
+                parent: None,
+                next_leaf: None,
+                prev_leaf: None,
+            }))));
+
+            self.root = Some(new_root);
+            self.len += 1;
+
+            new_root
+        }
+    }
 
-                self.root = Some(new_root);
-                self.len += 1;
-                return None;
+    // Inserts a key that isn't in the tree at `index` in the leaf it belongs
+    // in, splitting as needed. Returns the leaf the entry ends up in and where.
+    pub(crate) unsafe fn insert_at(
+        &mut self,
+        cursor: Link<K, V>,
+        index: usize,
+        key: K,
+        value: V,
+    ) -> (Link<K, V>, usize)
+    where
+        K: Ord + Clone,
+    {
+        if let Node::Leaf(node) = &mut (*cursor.as_ptr()) {
+            node.keys.insert(index, key);
+            node.values.insert(index, value);
+            self.len += 1;
+
+            // We're done if the node isn't overfull.
+            if !node.is_overfull(self.order) {
+                return (cursor, index);
             }
 
-            let mut cursor = self.root?;
-
-            // Descend the tree to the leaf node that the key should go in.
-            while let Node::Internal(node) = &(*cursor.as_ptr()) {
-                let index = match node.keys.binary_search(&key) {
-                    Ok(index) => index + 1,
-                    Err(index) => index,
-                };
-                cursor = node.children[index];
+            // The leaf node is overfull, so we split it in two.
+            let split_index = node.keys.len() / 2;
+            let sibling_keys = node.keys.drain(split_index..).collect::<Vec<_>>();
+            let sibling_values = node.values.drain(split_index..).collect::<Vec<_>>();
+            let split_key = sibling_keys[0].clone();
+
+            // Make the sibling now so we can link to it.
+            let sibling = NonNull::new_unchecked(Box::into_raw(Box::new(Node::Leaf(Leaf {
+                keys: sibling_keys,
+                values: sibling_values,
+                parent: node.parent,
+                next_leaf: node.next_leaf,
+                prev_leaf: Some(cursor),
+            }))));
+
+            // Connect the sibling and the leaf after it.
+            if let Some(next_leaf) = node.next_leaf {
+                if let Node::Leaf(next_leaf) = &mut (*next_leaf.as_ptr()) {
+                    next_leaf.prev_leaf = Some(sibling);
+                }
             }
 
-            if let Node::Leaf(node) = &mut (*cursor.as_ptr()) {
-                // Check if we already have a copy of this key and just need
-                // to swap in the updated value.
-                match node.keys.binary_search(&key) {
-                    Ok(index) => {
-                        // The key exists.
-                        mem::swap(&mut node.values[index], &mut value);
-                        return Some(value);
-                    }
-                    Err(index) => {
-                        // The key doesn't exist, so insert it.
-                        node.keys.insert(index, key);
-                        node.values.insert(index, value);
-                        self.len += 1;
-
-                        // We're done if the node isn't overfull.
-                        if !node.is_overfull(self.order) {
-                            return None;
-                        }
+            // Connect to the sibling.
+            node.next_leaf = Some(sibling);
 
-                        // The leaf node is overfull, so we split it in two.
-                        let split_index = node.keys.len() / 2;
-                        let sibling_keys = node.keys.drain(split_index..).collect::<Vec<_>>();
-                        let sibling_values = node.values.drain(split_index..).collect::<Vec<_>>();
-                        let split_key = sibling_keys[0].clone();
-
-                        // Make the sibling now so we can link to it.
-                        let sibling =
-                            NonNull::new_unchecked(Box::into_raw(Box::new(Node::Leaf(Leaf {
-                                keys: sibling_keys,
-                                values: sibling_values,
-                                parent: node.parent,
-                                next_leaf: node.next_leaf,
-                            }))));
-
-                        // Connect to the sibling.
-                        node.next_leaf = Some(sibling);
-
-                        if Some(cursor) == self.root {
-                            // We need a new root since we split it.
-                            let new_root = NonNull::new_unchecked(Box::into_raw(Box::new(
-                                Node::Internal(Internal {
-                                    keys: vec![split_key],
-                                    children: vec![cursor, sibling],
-                                    parent: None,
-                                }),
-                            )));
-
-                            // Connect the cursor to the new root.
-                            if let Node::Leaf(node) = &mut (*cursor.as_ptr()) {
-                                node.parent = Some(new_root);
-                            }
+            if Some(cursor) == self.root {
+                // We need a new root since we split it.
+                let new_root =
+                    NonNull::new_unchecked(Box::into_raw(Box::new(Node::Internal(Internal {
+                        keys: vec![split_key],
+                        children: vec![cursor, sibling],
+                        parent: None,
+                    }))));
 
-                            // Connect the sibling to the new root.
-                            if let Node::Leaf(sibling) = &mut (*sibling.as_ptr()) {
-                                sibling.parent = Some(new_root);
-                            }
+                // Connect the cursor to the new root.
+                if let Node::Leaf(node) = &mut (*cursor.as_ptr()) {
+                    node.parent = Some(new_root);
+                }
 
-                            // Use the new root.
-                            self.root = Some(new_root);
-                        } else {
-                            // Insert to the parent.
-                            self.insert_internal(split_key, node.parent.unwrap(), sibling)
-                        }
-                    }
+                // Connect the sibling to the new root.
+                if let Node::Leaf(sibling) = &mut (*sibling.as_ptr()) {
+                    sibling.parent = Some(new_root);
                 }
+
+                // Use the new root.
+                self.root = Some(new_root);
+            } else {
+                // Insert to the parent.
+                self.insert_internal(split_key, node.parent.unwrap(), sibling)
+            }
+
+            if index >= split_index {
+                return (sibling, index - split_index);
             }
         }
 
-        None
+        (cursor, index)
     }
 
     // This is called when `insert()` results in a split node, or if
@@ -123,7 +134,20 @@ impl<K, V> BPTreeMap<K, V> {
                 // Insert the key and child.
                 node.keys.insert(index, key);
                 node.children.insert(index + 1, child);
+            }
 
+            self.split_internal(cursor);
+        }
+    }
+
+    // Splits an internal node in two if it's overfull, inserting the split
+    // key into its parent.
+    pub(crate) fn split_internal(&mut self, cursor: Link<K, V>)
+    where
+        K: Ord + Clone,
+    {
+        unsafe {
+            if let Node::Internal(node) = &mut (*cursor.as_ptr()) {
                 // We're done if the node isn't overfull.
                 if !node.is_overfull(self.order) {
                     return;
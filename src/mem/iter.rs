@@ -6,44 +6,72 @@ use super::{
 };
 
 impl<K, V> BPTreeMap<K, V> {
-    pub fn iter(&self) -> Iter<K, V> {
+    pub fn iter(&self) -> Iter<'_, K, V> {
         Iter {
-            cursor: self.root,
-            index: 0,
+            front: self.root,
+            front_index: 0,
+            back: self.root,
+            back_index: 0,
             len: self.len,
-            at_leaves: false,
+            front_at_leaves: false,
+            back_at_leaves: false,
             _lifetime: PhantomData,
         }
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<K, V> {
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
         IterMut {
-            cursor: self.root,
-            index: 0,
+            front: self.root,
+            front_index: 0,
+            back: self.root,
+            back_index: 0,
             len: self.len,
-            at_leaves: false,
+            front_at_leaves: false,
+            back_at_leaves: false,
             _lifetime: PhantomData,
         }
     }
 
-    pub fn keys(&self) -> Keys<K, V> {
+    pub fn keys(&self) -> Keys<'_, K, V> {
         Keys(self.iter())
     }
 
-    pub fn values(&self) -> Values<K, V> {
+    pub fn values(&self) -> Values<'_, K, V> {
         Values(self.iter())
     }
 
-    pub fn values_mut(&mut self) -> ValuesMut<K, V> {
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
         ValuesMut(self.iter_mut())
     }
 }
 
+// Descends to the leftmost leaf under `cursor`.
+pub(crate) unsafe fn first_leaf<K, V>(mut cursor: Link<K, V>) -> Link<K, V> {
+    while let Node::Internal(node) = &(*cursor.as_ptr()) {
+        cursor = node.children[0];
+    }
+    cursor
+}
+
+// Descends to the rightmost leaf under `cursor`.
+pub(crate) unsafe fn last_leaf<K, V>(mut cursor: Link<K, V>) -> Link<K, V> {
+    while let Node::Internal(node) = &(*cursor.as_ptr()) {
+        cursor = node.children[node.children.len() - 1];
+    }
+    cursor
+}
+
+// The back of an iterator is tracked with an index that counts entries from
+// the end of its leaf, so stepping back to the previous leaf doesn't need to
+// know how many entries that leaf holds.
 pub struct Iter<'a, K, V> {
-    pub(crate) cursor: Option<Link<K, V>>,
-    pub(crate) index: usize,
+    pub(crate) front: Option<Link<K, V>>,
+    pub(crate) front_index: usize,
+    pub(crate) back: Option<Link<K, V>>,
+    pub(crate) back_index: usize,
     pub(crate) len: usize,
-    pub(crate) at_leaves: bool,
+    pub(crate) front_at_leaves: bool,
+    pub(crate) back_at_leaves: bool,
     pub(crate) _lifetime: PhantomData<(&'a K, &'a V)>,
 }
 
@@ -68,29 +96,27 @@ where
             return None;
         }
 
-        let mut cursor = self.cursor?;
+        let mut cursor = self.front?;
 
-        if !self.at_leaves {
+        if !self.front_at_leaves {
             unsafe {
-                while let Node::Internal(node) = &(*cursor.as_ptr()) {
-                    cursor = node.children[0];
-                }
-
-                self.cursor = Some(cursor);
-                self.at_leaves = true;
+                cursor = first_leaf(cursor);
             }
+
+            self.front = Some(cursor);
+            self.front_at_leaves = true;
         }
 
         unsafe {
             if let Node::Leaf(node) = &(*cursor.as_ptr()) {
-                let result = (&node.keys[self.index], &node.values[self.index]);
+                let result = (&node.keys[self.front_index], &node.values[self.front_index]);
 
                 self.len -= 1;
-                self.index += 1;
+                self.front_index += 1;
 
-                if self.index >= node.keys.len() {
-                    self.index = 0;
-                    self.cursor = node.next_leaf;
+                if self.front_index >= node.keys.len() {
+                    self.front_index = 0;
+                    self.front = node.next_leaf;
                 }
 
                 Some(result)
@@ -105,6 +131,48 @@ where
     }
 }
 
+impl<'a, K, V> DoubleEndedIterator for Iter<'a, K, V>
+where
+    K: 'a,
+    V: 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let mut cursor = self.back?;
+
+        if !self.back_at_leaves {
+            unsafe {
+                cursor = last_leaf(cursor);
+            }
+
+            self.back = Some(cursor);
+            self.back_at_leaves = true;
+        }
+
+        unsafe {
+            if let Node::Leaf(node) = &(*cursor.as_ptr()) {
+                let index = node.keys.len() - 1 - self.back_index;
+                let result = (&node.keys[index], &node.values[index]);
+
+                self.len -= 1;
+                self.back_index += 1;
+
+                if self.back_index >= node.keys.len() {
+                    self.back_index = 0;
+                    self.back = node.prev_leaf;
+                }
+
+                Some(result)
+            } else {
+                None
+            }
+        }
+    }
+}
+
 impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {
     fn len(&self) -> usize {
         self.len
@@ -112,10 +180,13 @@ impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {
 }
 
 pub struct IterMut<'a, K, V> {
-    pub(crate) cursor: Option<Link<K, V>>,
-    pub(crate) index: usize,
+    pub(crate) front: Option<Link<K, V>>,
+    pub(crate) front_index: usize,
+    pub(crate) back: Option<Link<K, V>>,
+    pub(crate) back_index: usize,
     pub(crate) len: usize,
-    pub(crate) at_leaves: bool,
+    pub(crate) front_at_leaves: bool,
+    pub(crate) back_at_leaves: bool,
     pub(crate) _lifetime: PhantomData<(&'a K, &'a mut V)>,
 }
 
@@ -136,29 +207,30 @@ impl<'a, K, V> Iterator for IterMut<'a, K, V> {
             return None;
         }
 
-        let mut cursor = self.cursor?;
+        let mut cursor = self.front?;
 
-        if !self.at_leaves {
+        if !self.front_at_leaves {
             unsafe {
-                while let Node::Internal(node) = &(*cursor.as_ptr()) {
-                    cursor = node.children[0];
-                }
-
-                self.cursor = Some(cursor);
-                self.at_leaves = true;
+                cursor = first_leaf(cursor);
             }
+
+            self.front = Some(cursor);
+            self.front_at_leaves = true;
         }
 
         unsafe {
             if let Node::Leaf(node) = &mut (*cursor.as_ptr()) {
-                let result = (&node.keys[self.index], &mut node.values[self.index]);
+                let result = (
+                    &node.keys[self.front_index],
+                    &mut node.values[self.front_index],
+                );
 
                 self.len -= 1;
-                self.index += 1;
+                self.front_index += 1;
 
-                if self.index >= node.keys.len() {
-                    self.index = 0;
-                    self.cursor = node.next_leaf;
+                if self.front_index >= node.keys.len() {
+                    self.front_index = 0;
+                    self.front = node.next_leaf;
                 }
 
                 Some(result)
@@ -173,6 +245,44 @@ impl<'a, K, V> Iterator for IterMut<'a, K, V> {
     }
 }
 
+impl<'a, K, V> DoubleEndedIterator for IterMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let mut cursor = self.back?;
+
+        if !self.back_at_leaves {
+            unsafe {
+                cursor = last_leaf(cursor);
+            }
+
+            self.back = Some(cursor);
+            self.back_at_leaves = true;
+        }
+
+        unsafe {
+            if let Node::Leaf(node) = &mut (*cursor.as_ptr()) {
+                let index = node.keys.len() - 1 - self.back_index;
+                let result = (&node.keys[index], &mut node.values[index]);
+
+                self.len -= 1;
+                self.back_index += 1;
+
+                if self.back_index >= node.keys.len() {
+                    self.back_index = 0;
+                    self.back = node.prev_leaf;
+                }
+
+                Some(result)
+            } else {
+                None
+            }
+        }
+    }
+}
+
 impl<'a, K, V> ExactSizeIterator for IterMut<'a, K, V> {
     fn len(&self) -> usize {
         self.len
@@ -189,6 +299,12 @@ impl<'a, K, V> Iterator for Keys<'a, K, V> {
     }
 }
 
+impl<'a, K, V> DoubleEndedIterator for Keys<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(key, _)| key)
+    }
+}
+
 pub struct Values<'a, K, V>(pub(crate) Iter<'a, K, V>);
 
 impl<'a, K, V> Iterator for Values<'a, K, V> {
@@ -199,6 +315,12 @@ impl<'a, K, V> Iterator for Values<'a, K, V> {
     }
 }
 
+impl<'a, K, V> DoubleEndedIterator for Values<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(_, value)| value)
+    }
+}
+
 pub struct ValuesMut<'a, K, V>(pub(crate) IterMut<'a, K, V>);
 
 impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
@@ -208,3 +330,9 @@ impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
         self.0.next().map(|(_, value)| value)
     }
 }
+
+impl<'a, K, V> DoubleEndedIterator for ValuesMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back().map(|(_, value)| value)
+    }
+}
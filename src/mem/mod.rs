@@ -1,8 +1,19 @@
+mod bulk;
+mod entry;
+mod extract;
 mod get;
 mod insert;
 mod iter;
 mod node;
+mod range;
 mod remove;
+mod set;
+mod split;
+
+pub use self::{
+    entry::{Entry, OccupiedEntry, VacantEntry},
+    set::BPTreeSet,
+};
 
 use self::node::{Link, Node};
 use std::{
@@ -135,6 +146,95 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Error;
+    use std::{
+        collections::{btree_map, BTreeMap, BTreeSet},
+        ops::Bound,
+    };
+
+    // Checks that every node other than the root holds as many keys as it
+    // should, that all leaves are at the same depth, and that the split keys,
+    // parent links and leaf links agree with where everything is. The root's
+    // parent link is left stale when the tree shrinks, so it isn't checked.
+    fn check<K: Ord, V>(tree: &BPTreeMap<K, V>) {
+        fn check_node<K: Ord, V>(
+            tree: &BPTreeMap<K, V>,
+            cursor: Link<K, V>,
+            parent: Option<Link<K, V>>,
+            bounds: (Option<&K>, Option<&K>),
+            depth: usize,
+            leaves: &mut Vec<(Link<K, V>, usize)>,
+        ) {
+            let in_bounds = |key: &K| {
+                bounds.0.is_none_or(|lower| lower <= key)
+                    && bounds.1.is_none_or(|upper| key < upper)
+            };
+            let is_root = Some(cursor) == tree.root;
+
+            match unsafe { &*cursor.as_ptr() } {
+                Node::Internal(node) => {
+                    assert!(is_root || node.parent == parent);
+                    assert_eq!(node.children.len(), node.keys.len() + 1);
+                    assert!(!node.keys.is_empty());
+                    assert!(!node.is_overfull(tree.order));
+                    assert!(is_root || !node.is_underfull(tree.order));
+                    assert!(node.keys.windows(2).all(|pair| pair[0] < pair[1]));
+                    assert!(node.keys.iter().all(in_bounds));
+
+                    for (index, child) in node.children.iter().enumerate() {
+                        let lower = index.checked_sub(1).map(|index| &node.keys[index]);
+                        let upper = node.keys.get(index);
+                        check_node(
+                            tree,
+                            *child,
+                            Some(cursor),
+                            (lower.or(bounds.0), upper.or(bounds.1)),
+                            depth + 1,
+                            leaves,
+                        );
+                    }
+                }
+                Node::Leaf(node) => {
+                    assert!(is_root || node.parent == parent);
+                    assert_eq!(node.keys.len(), node.values.len());
+                    assert!(!node.keys.is_empty());
+                    assert!(!node.is_overfull(tree.order));
+                    assert!(is_root || !node.is_underfull(tree.order));
+                    assert!(node.keys.windows(2).all(|pair| pair[0] < pair[1]));
+                    assert!(node.keys.iter().all(in_bounds));
+                    leaves.push((cursor, depth));
+                }
+            }
+        }
+
+        let mut leaves = Vec::new();
+        if let Some(root) = tree.root {
+            check_node(tree, root, None, (None, None), 0, &mut leaves);
+        }
+        assert!(leaves.iter().all(|(_, depth)| *depth == leaves[0].1));
+
+        let mut len = 0;
+        for (index, (leaf, _)) in leaves.iter().enumerate() {
+            if let Node::Leaf(node) = unsafe { &*leaf.as_ptr() } {
+                let prev = index.checked_sub(1).map(|index| leaves[index].0);
+                let next = leaves.get(index + 1).map(|(next, _)| *next);
+                assert_eq!(node.prev_leaf, prev);
+                assert_eq!(node.next_leaf, next);
+                len += node.keys.len();
+            }
+        }
+        assert_eq!(tree.len(), len);
+    }
+
+    // Checks that the root has no parent, which `check` leaves out.
+    fn check_root<K, V>(tree: &BPTreeMap<K, V>) {
+        if let Some(root) = tree.root {
+            match unsafe { &*root.as_ptr() } {
+                Node::Internal(node) => assert_eq!(node.parent, None),
+                Node::Leaf(node) => assert_eq!(node.parent, None),
+            }
+        }
+    }
 
     #[test]
     fn it_works() {
@@ -168,4 +268,436 @@ mod tests {
             tree.pretty_print();
         }
     }
+
+    #[test]
+    fn range() {
+        let mut tree = BPTreeMap::new();
+        let mut reference = BTreeMap::new();
+
+        for n in (0..100).step_by(2) {
+            tree.insert(n, n);
+            reference.insert(n, n);
+        }
+
+        // Leave some stale split keys behind in the internal nodes.
+        for n in (0..100).step_by(6) {
+            tree.remove(&n);
+            reference.remove(&n);
+        }
+
+        for start in [0, 1, 10, 11, 50, 97, 98, 99, 120] {
+            for end in [start, start + 1, start + 2, start + 7, 99, 100, 150] {
+                if end < start {
+                    continue;
+                }
+
+                assert!(tree.range(start..end).eq(reference.range(start..end)));
+                assert!(tree.range(start..=end).eq(reference.range(start..=end)));
+                assert!(tree.range(..end).eq(reference.range(..end)));
+                assert!(tree.range(start..).eq(reference.range(start..)));
+
+                let bounds = (Bound::Excluded(start), Bound::Included(end));
+                assert!(tree.range(bounds).eq(reference.range(bounds)));
+            }
+        }
+
+        for (_, value) in tree.range_mut(40..50) {
+            *value += 1;
+        }
+
+        for (_, value) in reference.range_mut(40..50) {
+            *value += 1;
+        }
+
+        assert!(tree.iter().eq(reference.iter()));
+        assert_eq!(BPTreeMap::<usize, ()>::new().range(..).next(), None);
+    }
+
+    #[test]
+    fn double_ended() {
+        let mut tree = BPTreeMap::new();
+        let mut reference = BTreeMap::new();
+
+        for n in 0..100 {
+            tree.insert(n, n);
+            reference.insert(n, n);
+        }
+
+        for n in (0..100).step_by(3) {
+            tree.remove(&n);
+            reference.remove(&n);
+        }
+
+        assert!(tree.iter().rev().eq(reference.iter().rev()));
+        assert!(tree.keys().rev().eq(reference.keys().rev()));
+        assert!(tree.values().rev().eq(reference.values().rev()));
+        assert!(tree.range(10..50).rev().eq(reference.range(10..50).rev()));
+
+        // Alternate ends until the two meet.
+        let mut iter = tree.iter();
+        let mut reference_iter = reference.iter();
+        loop {
+            let next = (iter.next(), reference_iter.next());
+            assert_eq!(next.0, next.1);
+            let next_back = (iter.next_back(), reference_iter.next_back());
+            assert_eq!(next_back.0, next_back.1);
+            if next.0.is_none() || next_back.0.is_none() {
+                break;
+            }
+        }
+
+        let mut range = tree.range(20..=40);
+        let mut reference_range = reference.range(20..=40);
+        loop {
+            let next = (range.next_back(), reference_range.next_back());
+            assert_eq!(next.0, next.1);
+            let next_back = (range.next(), reference_range.next());
+            assert_eq!(next_back.0, next_back.1);
+            if next.0.is_none() || next_back.0.is_none() {
+                break;
+            }
+        }
+
+        for value in tree.values_mut().rev().take(10) {
+            *value += 1;
+        }
+
+        for value in reference.values_mut().rev().take(10) {
+            *value += 1;
+        }
+
+        assert!(tree.iter().eq(reference.iter()));
+    }
+
+    #[test]
+    fn from_sorted_iter() {
+        for order in [3, 4, 5, 8] {
+            for fill in [0.1, 0.5, 0.75, 1.0] {
+                for len in [0, 1, 2, 3, 7, 10, 50, 333] {
+                    let mut tree = BPTreeMap::from_sorted_iter_with_fill(
+                        order,
+                        fill,
+                        (0..len).map(|n| (n * 2, n)),
+                    )
+                    .unwrap();
+                    let mut reference = (0..len).map(|n| (n * 2, n)).collect::<BTreeMap<_, _>>();
+
+                    assert_eq!(tree.len(), len);
+                    assert!(tree.iter().eq(reference.iter()));
+                    assert!(tree.iter().rev().eq(reference.iter().rev()));
+
+                    // The tree has to stay balanced as it changes.
+                    for n in 0..len {
+                        tree.insert(n * 2 + 1, n);
+                        reference.insert(n * 2 + 1, n);
+                    }
+                    for n in (0..len * 2).step_by(3) {
+                        tree.remove(&n);
+                        reference.remove(&n);
+                    }
+                    assert!(tree.iter().eq(reference.iter()));
+                    assert!(tree.range(10..40).rev().eq(reference.range(10..40).rev()));
+                }
+            }
+        }
+
+        assert!(matches!(
+            BPTreeMap::from_sorted_iter(4, [(1, ()), (3, ()), (2, ())]),
+            Err(Error::Unsorted { index: 2 })
+        ));
+        assert!(matches!(
+            BPTreeMap::from_sorted_iter(4, [(1, ()), (1, ())]),
+            Err(Error::Unsorted { index: 1 })
+        ));
+    }
+
+    #[test]
+    fn entry() {
+        let mut tree = BPTreeMap::with_order(4);
+        let mut reference = BTreeMap::new();
+
+        for n in 0..1000 {
+            let key = n * 7 % 150;
+            *tree.entry(key).or_insert(0) += 1;
+            *reference.entry(key).or_insert(0) += 1;
+
+            tree.entry(key + 1000)
+                .and_modify(|value| *value *= 2)
+                .or_insert_with_key(|key| *key);
+            reference
+                .entry(key + 1000)
+                .and_modify(|value| *value *= 2)
+                .or_insert_with_key(|key| *key);
+        }
+        assert!(tree.iter().eq(reference.iter()));
+        assert_eq!(tree.len(), reference.len());
+
+        for key in (0..1200).step_by(3) {
+            match (tree.entry(key), reference.entry(key)) {
+                (Entry::Occupied(entry), btree_map::Entry::Occupied(reference_entry)) => {
+                    assert_eq!(entry.key(), reference_entry.key());
+                    assert_eq!(entry.remove_entry(), reference_entry.remove_entry());
+                }
+                (Entry::Vacant(entry), btree_map::Entry::Vacant(reference_entry)) => {
+                    assert_eq!(entry.into_key(), reference_entry.into_key());
+                }
+                _ => panic!("entries differ for {key}"),
+            }
+        }
+        for key in (1..1200).step_by(3) {
+            if let Entry::Occupied(mut entry) = tree.entry(key) {
+                assert_eq!(entry.insert(0), reference.insert(key, 0).unwrap());
+            }
+            *tree.entry(key).or_default() += 1;
+            *reference.entry(key).or_default() += 1;
+        }
+        assert!(tree.iter().eq(reference.iter()));
+        assert!(tree.iter().rev().eq(reference.iter().rev()));
+        assert_eq!(tree.len(), reference.len());
+    }
+
+    #[test]
+    fn first_last_pop() {
+        let mut tree = BPTreeMap::with_order(4);
+        assert_eq!(tree.first_key_value(), None);
+        assert_eq!(tree.pop_first(), None);
+
+        let mut reference = BTreeMap::new();
+        for n in 0..1000 {
+            tree.insert(n * 7 % 1000, n);
+            reference.insert(n * 7 % 1000, n);
+        }
+        assert_eq!(tree.first_key_value(), reference.first_key_value());
+        assert_eq!(tree.last_key_value(), reference.last_key_value());
+
+        for n in 0..1000 {
+            if n % 3 == 0 {
+                assert_eq!(tree.pop_last(), reference.pop_last());
+            } else {
+                assert_eq!(tree.pop_first(), reference.pop_first());
+            }
+            assert_eq!(tree.first_key_value(), reference.first_key_value());
+            assert_eq!(tree.len(), reference.len());
+        }
+        assert!(tree.is_empty());
+        assert_eq!(tree.pop_last(), None);
+
+        let mut set = (0..50).collect::<BPTreeSet<_>>();
+        assert_eq!(set.pop_first(), Some(0));
+        assert_eq!(set.pop_last(), Some(49));
+        assert_eq!(set.first(), Some(&1));
+        assert_eq!(set.last(), Some(&48));
+    }
+
+    #[test]
+    fn split_off() {
+        for order in [2, 3, 4, 5, 8] {
+            for len in [0, 1, 2, 5, 40, 500] {
+                for at in [0, 1, len / 3, len, len * 2 + 1, len * 2 + 2] {
+                    let mut tree = BPTreeMap::with_order(order);
+                    let mut reference = BTreeMap::new();
+                    for n in 0..len {
+                        tree.insert(n * 2, n);
+                        reference.insert(n * 2, n);
+                    }
+
+                    let mut split = tree.split_off(&at);
+                    let mut reference_split = reference.split_off(&at);
+                    check(&tree);
+                    check(&split);
+                    check_root(&tree);
+                    check_root(&split);
+                    assert!(tree.iter().eq(reference.iter()));
+                    assert!(split.iter().eq(reference_split.iter()));
+                    assert!(split.iter().rev().eq(reference_split.iter().rev()));
+
+                    // Both halves have to stay balanced as they change.
+                    for n in (0..len * 2).step_by(3) {
+                        tree.remove(&n);
+                        reference.remove(&n);
+                        split.insert(n + 1, n);
+                        reference_split.insert(n + 1, n);
+                    }
+                    check(&tree);
+                    check(&split);
+                    assert!(tree.iter().eq(reference.iter()));
+                    assert!(split.iter().eq(reference_split.iter()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn append() {
+        for order in [2, 3, 4, 5, 8] {
+            for (len, other_len) in [(0, 10), (10, 0), (1, 300), (300, 1), (50, 60), (7, 500)] {
+                // Keys after the map's, keys before it, and keys in among it.
+                for offset in [len * 3, 0, len] {
+                    let mut tree = BPTreeMap::with_order(order);
+                    let mut other = BPTreeMap::with_order(order);
+                    let mut reference = BTreeMap::new();
+                    let mut reference_other = BTreeMap::new();
+                    for n in 0..len {
+                        tree.insert(n * 3 + other_len * 3 * usize::from(offset == 0), n);
+                        reference.insert(n * 3 + other_len * 3 * usize::from(offset == 0), n);
+                    }
+                    for n in 0..other_len {
+                        other.insert(n * 3 + offset, n + 1000);
+                        reference_other.insert(n * 3 + offset, n + 1000);
+                    }
+
+                    tree.append(&mut other);
+                    reference.append(&mut reference_other);
+                    check(&tree);
+                    check(&other);
+                    check_root(&tree);
+                    assert!(other.is_empty());
+                    assert!(tree.iter().eq(reference.iter()));
+                    assert!(tree.iter().rev().eq(reference.iter().rev()));
+
+                    for n in (0..(len + other_len) * 3).step_by(2) {
+                        tree.remove(&n);
+                        reference.remove(&n);
+                    }
+                    check(&tree);
+                    assert!(tree.iter().eq(reference.iter()));
+                }
+            }
+        }
+
+        // Maps of different orders can't share nodes.
+        let mut tree = (0..100).map(|n| (n, n)).collect::<BTreeMap<_, _>>();
+        let mut map = BPTreeMap::with_order(3);
+        let mut other = BPTreeMap::with_order(6);
+        for n in 0..100 {
+            map.insert(n, n);
+            other.insert(n + 100, n);
+            tree.insert(n + 100, n);
+        }
+        map.append(&mut other);
+        check(&map);
+        check_root(&map);
+        assert!(map.iter().eq(tree.iter()));
+
+        let mut set = (0..100).collect::<BPTreeSet<_>>();
+        let mut split = set.split_off(&40);
+        assert!(set.iter().copied().eq(0..40));
+        assert!(split.iter().copied().eq(40..100));
+        split.append(&mut set);
+        assert!(split.iter().copied().eq(0..100));
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn retain_drain_extract_if() {
+        for order in [2, 3, 4, 5, 8] {
+            for len in [0, 1, 7, 100, 600] {
+                let predicates: [fn(&usize) -> bool; 5] = [
+                    |n| n % 2 == 0,
+                    |n| n % 7 != 3,
+                    |n| *n < 10 || *n > 80,
+                    |n| (30..70).contains(n),
+                    |_| false,
+                ];
+                for keep in predicates {
+                    let mut tree = BPTreeMap::with_order(order);
+                    let mut reference = BTreeMap::new();
+                    for n in 0..len {
+                        tree.insert(n, n);
+                        reference.insert(n, n);
+                    }
+
+                    tree.retain(|key, value| {
+                        *value += 1;
+                        keep(key)
+                    });
+                    reference.retain(|key, value| {
+                        *value += 1;
+                        keep(key)
+                    });
+                    check(&tree);
+                    assert!(tree.iter().eq(reference.iter()));
+                    assert!(tree.iter().rev().eq(reference.iter().rev()));
+
+                    let extracted = tree.extract_if(|key, _| key % 3 == 0).collect::<Vec<_>>();
+                    let reference_extracted = reference
+                        .extract_if(.., |key, _| key % 3 == 0)
+                        .collect::<Vec<_>>();
+                    check(&tree);
+                    assert_eq!(extracted, reference_extracted);
+                    assert!(tree.iter().eq(reference.iter()));
+
+                    // Stopping early keeps whatever wasn't reached.
+                    let mut extract = tree.extract_if(|key, _| key % 5 == 1);
+                    let first = extract.next();
+                    drop(extract);
+                    let mut reference_extract = reference.extract_if(.., |key, _| key % 5 == 1);
+                    assert_eq!(first, reference_extract.next());
+                    drop(reference_extract);
+                    check(&tree);
+                    assert!(tree.iter().eq(reference.iter()));
+
+                    for n in (0..len).step_by(4) {
+                        tree.insert(n, n);
+                        reference.insert(n, n);
+                    }
+                    check(&tree);
+                    assert!(tree.iter().eq(reference.iter()));
+
+                    let drained = tree.drain().collect::<Vec<_>>();
+                    check(&tree);
+                    assert!(tree.is_empty());
+                    assert!(drained.into_iter().eq(reference.into_iter()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn set() {
+        let a = (0..200).step_by(2).collect::<BPTreeSet<_>>();
+        let b = (0..300).step_by(3).collect::<BPTreeSet<_>>();
+        let reference_a = (0..200).step_by(2).collect::<BTreeSet<_>>();
+        let reference_b = (0..300).step_by(3).collect::<BTreeSet<_>>();
+
+        assert!(a.iter().eq(reference_a.iter()));
+        assert!(a.iter().rev().eq(reference_a.iter().rev()));
+        assert!(a.range(10..=50).eq(reference_a.range(10..=50)));
+        assert_eq!(a.first(), reference_a.first());
+        assert_eq!(b.last(), reference_b.last());
+
+        assert!(a.union(&b).eq(reference_a.union(&reference_b)));
+        assert!(a
+            .intersection(&b)
+            .eq(reference_a.intersection(&reference_b)));
+        assert!(a.difference(&b).eq(reference_a.difference(&reference_b)));
+        assert!(b.difference(&a).eq(reference_b.difference(&reference_a)));
+        assert!(a
+            .symmetric_difference(&b)
+            .eq(reference_a.symmetric_difference(&reference_b)));
+
+        let mut set = BPTreeSet::with_order(4);
+        assert_eq!(set.first(), None);
+        assert!(set.insert(6));
+        assert!(!set.insert(6));
+        assert!(set.contains(&6));
+        assert!(set.is_subset(&a));
+        assert!(!set.is_disjoint(&b));
+        assert!(set.remove(&6));
+        assert!(!set.remove(&6));
+        assert!(set.is_empty());
+        assert!(set.is_disjoint(&a));
+        assert_eq!(
+            format!("{:?}", (0..3).collect::<BPTreeSet<_>>()),
+            "{0, 1, 2}"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "range start is greater than range end in BPTreeMap")]
+    fn range_inverted() {
+        let tree: BPTreeMap<usize, ()> = BPTreeMap::new();
+        #[allow(clippy::reversed_empty_ranges)]
+        tree.range(5..3);
+    }
 }
@@ -0,0 +1,348 @@
+use super::{
+    node::{Link, Node},
+    BPTreeMap,
+};
+use std::{
+    borrow::Borrow,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
+
+// A leaf and the index of an entry within it. The index of the back of a range
+// counts entries from the end of the leaf.
+type Position<K, V> = (Link<K, V>, usize);
+
+impl<K, V> BPTreeMap<K, V> {
+    pub fn range<Q, R>(&self, range: R) -> Range<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+        R: RangeBounds<Q>,
+    {
+        let (front, back) = self.seek_range(&range).unzip();
+
+        Range {
+            front: front.map(|(front, _)| front),
+            front_index: front.map_or(0, |(_, index)| index),
+            back: back.map(|(back, _)| back),
+            back_index: back.map_or(0, |(_, index)| index),
+            _lifetime: PhantomData,
+        }
+    }
+
+    pub fn range_mut<Q, R>(&mut self, range: R) -> RangeMut<'_, K, V>
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+        R: RangeBounds<Q>,
+    {
+        let (front, back) = self.seek_range(&range).unzip();
+
+        RangeMut {
+            front: front.map(|(front, _)| front),
+            front_index: front.map_or(0, |(_, index)| index),
+            back: back.map(|(back, _)| back),
+            back_index: back.map_or(0, |(_, index)| index),
+            _lifetime: PhantomData,
+        }
+    }
+
+    // Finds the leaf positions of the first and last entries in the range, or
+    // `None` if the range is empty.
+    fn seek_range<Q, R>(&self, range: &R) -> Option<(Position<K, V>, Position<K, V>)>
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+        R: RangeBounds<Q>,
+    {
+        match (range.start_bound(), range.end_bound()) {
+            (Bound::Excluded(start), Bound::Excluded(end)) if start == end => {
+                panic!("range start and end are equal and excluded in BPTreeMap")
+            }
+            (
+                Bound::Included(start) | Bound::Excluded(start),
+                Bound::Included(end) | Bound::Excluded(end),
+            ) if start > end => {
+                panic!("range start is greater than range end in BPTreeMap")
+            }
+            _ => {}
+        }
+
+        unsafe {
+            let (front, front_index) = self.seek_front(range.start_bound())?;
+
+            // The first entry past the start bound may already be past the end
+            // bound, in which case the range is empty.
+            if let Node::Leaf(node) = &(*front.as_ptr()) {
+                let key = node.keys[front_index].borrow();
+                let in_range = match range.end_bound() {
+                    Bound::Included(end) => key <= end,
+                    Bound::Excluded(end) => key < end,
+                    Bound::Unbounded => true,
+                };
+
+                if !in_range {
+                    return None;
+                }
+            }
+
+            Some(((front, front_index), self.seek_back(range.end_bound())?))
+        }
+    }
+
+    // Descends to the first entry that lies past the start bound.
+    unsafe fn seek_front<Q>(&self, bound: Bound<&Q>) -> Option<Position<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+    {
+        let mut cursor = self.root?;
+
+        while let Node::Internal(node) = &(*cursor.as_ptr()) {
+            let index = match bound {
+                Bound::Included(key) | Bound::Excluded(key) => {
+                    match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
+                        Ok(index) => index + 1,
+                        Err(index) => index,
+                    }
+                }
+                Bound::Unbounded => 0,
+            };
+            cursor = node.children[index];
+        }
+
+        if let Node::Leaf(node) = &(*cursor.as_ptr()) {
+            let index = match bound {
+                Bound::Included(key) => node.keys.partition_point(|probe| probe.borrow() < key),
+                Bound::Excluded(key) => node.keys.partition_point(|probe| probe.borrow() <= key),
+                Bound::Unbounded => 0,
+            };
+
+            if index < node.keys.len() {
+                return Some((cursor, index));
+            }
+
+            // Every key in this leaf is before the bound, so the first entry
+            // is the head of the next leaf.
+            return node.next_leaf.map(|next_leaf| (next_leaf, 0));
+        }
+
+        None
+    }
+
+    // Descends to the last entry that lies before the end bound.
+    unsafe fn seek_back<Q>(&self, bound: Bound<&Q>) -> Option<Position<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Ord,
+    {
+        let mut cursor = self.root?;
+
+        // The closest subtree to the left of the descent. Its rightmost leaf
+        // precedes the leaf we end up in.
+        let mut left_subtree = None;
+
+        while let Node::Internal(node) = &(*cursor.as_ptr()) {
+            let index = match bound {
+                Bound::Included(key) => {
+                    match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
+                        Ok(index) => index + 1,
+                        Err(index) => index,
+                    }
+                }
+                Bound::Excluded(key) => {
+                    match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
+                        Ok(index) => index,
+                        Err(index) => index,
+                    }
+                }
+                Bound::Unbounded => node.children.len() - 1,
+            };
+
+            if index > 0 {
+                left_subtree = Some(node.children[index - 1]);
+            }
+
+            cursor = node.children[index];
+        }
+
+        if let Node::Leaf(node) = &(*cursor.as_ptr()) {
+            let index = match bound {
+                Bound::Included(key) => node.keys.partition_point(|probe| probe.borrow() <= key),
+                Bound::Excluded(key) => node.keys.partition_point(|probe| probe.borrow() < key),
+                Bound::Unbounded => node.keys.len(),
+            };
+
+            if index > 0 {
+                return Some((cursor, node.keys.len() - index));
+            }
+        }
+
+        // Every key in the leaf is past the bound, so the last entry is the
+        // tail of the previous leaf.
+        let mut cursor = left_subtree?;
+
+        while let Node::Internal(node) = &(*cursor.as_ptr()) {
+            cursor = node.children[node.children.len() - 1];
+        }
+
+        Some((cursor, 0))
+    }
+}
+
+pub struct Range<'a, K, V> {
+    pub(crate) front: Option<Link<K, V>>,
+    pub(crate) front_index: usize,
+    pub(crate) back: Option<Link<K, V>>,
+    pub(crate) back_index: usize,
+    pub(crate) _lifetime: PhantomData<(&'a K, &'a V)>,
+}
+
+impl<'a, K, V> Iterator for Range<'a, K, V>
+where
+    K: 'a,
+    V: 'a,
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cursor = self.front?;
+
+        unsafe {
+            if let Node::Leaf(node) = &(*cursor.as_ptr()) {
+                let result = (&node.keys[self.front_index], &node.values[self.front_index]);
+
+                if self.front == self.back
+                    && self.front_index == node.keys.len() - 1 - self.back_index
+                {
+                    self.front = None;
+                    self.back = None;
+                } else {
+                    self.front_index += 1;
+
+                    if self.front_index >= node.keys.len() {
+                        self.front_index = 0;
+                        self.front = node.next_leaf;
+                    }
+                }
+
+                Some(result)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Range<'a, K, V>
+where
+    K: 'a,
+    V: 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let cursor = self.back?;
+
+        unsafe {
+            if let Node::Leaf(node) = &(*cursor.as_ptr()) {
+                let index = node.keys.len() - 1 - self.back_index;
+                let result = (&node.keys[index], &node.values[index]);
+
+                if self.front == self.back && self.front_index == index {
+                    self.front = None;
+                    self.back = None;
+                } else {
+                    self.back_index += 1;
+
+                    if self.back_index >= node.keys.len() {
+                        self.back_index = 0;
+                        self.back = node.prev_leaf;
+                    }
+                }
+
+                Some(result)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+pub struct RangeMut<'a, K, V> {
+    pub(crate) front: Option<Link<K, V>>,
+    pub(crate) front_index: usize,
+    pub(crate) back: Option<Link<K, V>>,
+    pub(crate) back_index: usize,
+    pub(crate) _lifetime: PhantomData<(&'a K, &'a mut V)>,
+}
+
+impl<'a, K, V> Iterator for RangeMut<'a, K, V>
+where
+    K: 'a,
+    V: 'a,
+{
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cursor = self.front?;
+
+        unsafe {
+            if let Node::Leaf(node) = &mut (*cursor.as_ptr()) {
+                let result = (
+                    &node.keys[self.front_index],
+                    &mut node.values[self.front_index],
+                );
+
+                if self.front == self.back
+                    && self.front_index == node.keys.len() - 1 - self.back_index
+                {
+                    self.front = None;
+                    self.back = None;
+                } else {
+                    self.front_index += 1;
+
+                    if self.front_index >= node.keys.len() {
+                        self.front_index = 0;
+                        self.front = node.next_leaf;
+                    }
+                }
+
+                Some(result)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for RangeMut<'a, K, V>
+where
+    K: 'a,
+    V: 'a,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let cursor = self.back?;
+
+        unsafe {
+            if let Node::Leaf(node) = &mut (*cursor.as_ptr()) {
+                let index = node.keys.len() - 1 - self.back_index;
+                let result = (&node.keys[index], &mut node.values[index]);
+
+                if self.front == self.back && self.front_index == index {
+                    self.front = None;
+                    self.back = None;
+                } else {
+                    self.back_index += 1;
+
+                    if self.back_index >= node.keys.len() {
+                        self.back_index = 0;
+                        self.back = node.prev_leaf;
+                    }
+                }
+
+                Some(result)
+            } else {
+                None
+            }
+        }
+    }
+}
@@ -10,142 +10,181 @@ impl<K, V> BPTreeMap<K, V> {
         K: Borrow<Q> + Clone,
         Q: Ord,
     {
-        unsafe {
-            let mut cursor = self.root?;
-            let mut cursor_index = 0;
-
-            while let Node::Internal(node) = &(*cursor.as_ptr()) {
-                cursor_index = match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
-                    Ok(index) => index + 1,
-                    Err(index) => index,
-                };
-                cursor = node.children[cursor_index];
-            }
-
-            if let Node::Leaf(node) = &mut (*cursor.as_ptr()) {
-                let index = node
-                    .keys
-                    .binary_search_by(|probe| probe.borrow().cmp(key))
-                    .ok()?;
+        match self.search_leaf(key)? {
+            (cursor, cursor_index, Ok(index)) => unsafe {
+                Some(self.remove_at(cursor, cursor_index, index))
+            },
+            _ => None,
+        }
+    }
 
-                let key = node.keys.remove(index);
-                let value = node.values.remove(index);
-                self.len -= 1;
+    fn pop(&mut self, last: bool) -> Option<(K, V)>
+    where
+        K: Ord + Clone,
+    {
+        let (cursor, cursor_index, index) = self.edge_leaf(last)?;
+        unsafe { Some(self.remove_at::<K>(cursor, cursor_index, index)) }
+    }
 
-                // Check if the node is now underfull or if its the root. The
-                // root is exceptional in that it is allowed to be underfull.
-                if !node.is_underfull(self.order) || Some(cursor) == self.root {
-                    // Clean out the root if we've emptied it.
-                    if Some(cursor) == self.root && node.keys.is_empty() {
-                        let _ = Box::from_raw(cursor.as_ptr());
-                        self.root = None;
-                    }
-                    return Some((key, value));
-                }
+    // Removes the entry with the smallest key, so the map can serve as a
+    // priority queue.
+    pub fn pop_first(&mut self) -> Option<(K, V)>
+    where
+        K: Ord + Clone,
+    {
+        self.pop(false)
+    }
 
-                // We have an underfull non-root leaf node.
-                if let Node::Internal(parent) = &mut (*node.parent.unwrap().as_ptr()) {
-                    // Check if the left sibling has any extra keys.
-                    if cursor_index > 0 {
-                        if let Node::Leaf(left_sibling) =
-                            &mut (*parent.children[cursor_index - 1].as_ptr())
-                        {
-                            if left_sibling.has_extra_keys(self.order) {
-                                // We want the max key/value pair from the left
-                                // sibling.
-                                let max_key = left_sibling.keys.pop().unwrap();
-                                let max_value = left_sibling.values.pop().unwrap();
-
-                                // The max key/value pair from the left sibling
-                                // is smaller than any key/value in the cursor
-                                // node.
-                                node.keys.insert(0, max_key);
-                                node.values.insert(0, max_value);
+    pub fn pop_last(&mut self) -> Option<(K, V)>
+    where
+        K: Ord + Clone,
+    {
+        self.pop(true)
+    }
 
-                                // Update parent key.
-                                parent.keys[cursor_index - 1] = node.keys[0].clone();
+    // Removes the entry at `index` in a leaf, the child at `cursor_index` of
+    // its parent, merging or borrowing as needed.
+    pub(crate) unsafe fn remove_at<Q>(
+        &mut self,
+        cursor: Link<K, V>,
+        cursor_index: usize,
+        index: usize,
+    ) -> (K, V)
+    where
+        K: Borrow<Q> + Clone,
+        Q: Ord,
+    {
+        if let Node::Leaf(node) = &mut (*cursor.as_ptr()) {
+            let key = node.keys.remove(index);
+            let value = node.values.remove(index);
+            self.len -= 1;
+
+            // Check if the node is now underfull or if its the root. The
+            // root is exceptional in that it is allowed to be underfull.
+            if !node.is_underfull(self.order) || Some(cursor) == self.root {
+                // Clean out the root if we've emptied it.
+                if Some(cursor) == self.root && node.keys.is_empty() {
+                    let _ = Box::from_raw(cursor.as_ptr());
+                    self.root = None;
+                }
+                return (key, value);
+            }
 
-                                return Some((key, value));
-                            }
+            // We have an underfull non-root leaf node.
+            if let Node::Internal(parent) = &mut (*node.parent.unwrap().as_ptr()) {
+                // Check if the left sibling has any extra keys.
+                if cursor_index > 0 {
+                    if let Node::Leaf(left_sibling) =
+                        &mut (*parent.children[cursor_index - 1].as_ptr())
+                    {
+                        if left_sibling.has_extra_keys(self.order) {
+                            // We want the max key/value pair from the left
+                            // sibling.
+                            let max_key = left_sibling.keys.pop().unwrap();
+                            let max_value = left_sibling.values.pop().unwrap();
+
+                            // The max key/value pair from the left sibling
+                            // is smaller than any key/value in the cursor
+                            // node.
+                            node.keys.insert(0, max_key);
+                            node.values.insert(0, max_value);
+
+                            // Update parent key.
+                            parent.keys[cursor_index - 1] = node.keys[0].clone();
+
+                            return (key, value);
                         }
                     }
+                }
 
-                    // Check if the right sibling has any extra keys.
-                    if cursor_index + 1 < parent.children.len() {
-                        if let Node::Leaf(right_sibling) =
-                            &mut (*parent.children[cursor_index + 1].as_ptr())
-                        {
-                            if right_sibling.has_extra_keys(self.order) {
-                                // We want the min key/value pair from the right
-                                // sibling.
-                                let min_key = right_sibling.keys.remove(0);
-                                let min_value = right_sibling.values.remove(0);
-
-                                // The min key/value pair from the right sibling
-                                // is larger than any key/value in the cursor
-                                // node.
-                                node.keys.push(min_key);
-                                node.values.push(min_value);
-
-                                // Update parent key.
-                                parent.keys[cursor_index] = right_sibling.keys[0].clone();
-
-                                return Some((key, value));
-                            }
+                // Check if the right sibling has any extra keys.
+                if cursor_index + 1 < parent.children.len() {
+                    if let Node::Leaf(right_sibling) =
+                        &mut (*parent.children[cursor_index + 1].as_ptr())
+                    {
+                        if right_sibling.has_extra_keys(self.order) {
+                            // We want the min key/value pair from the right
+                            // sibling.
+                            let min_key = right_sibling.keys.remove(0);
+                            let min_value = right_sibling.values.remove(0);
+
+                            // The min key/value pair from the right sibling
+                            // is larger than any key/value in the cursor
+                            // node.
+                            node.keys.push(min_key);
+                            node.values.push(min_value);
+
+                            // Update parent key.
+                            parent.keys[cursor_index] = right_sibling.keys[0].clone();
+
+                            return (key, value);
                         }
                     }
+                }
 
-                    // Check if we can merge into the left sibling.
-                    if cursor_index > 0 {
-                        if let Node::Leaf(left_sibling) =
-                            &mut (*parent.children[cursor_index - 1].as_ptr())
-                        {
-                            // Take/merge in the keys and values.
-                            left_sibling.keys.append(&mut node.keys);
-                            left_sibling.values.append(&mut node.values);
-
-                            // Relink the left sibling.
-                            left_sibling.next_leaf = node.next_leaf;
+                // Check if we can merge into the left sibling.
+                if cursor_index > 0 {
+                    if let Node::Leaf(left_sibling) =
+                        &mut (*parent.children[cursor_index - 1].as_ptr())
+                    {
+                        // Take/merge in the keys and values.
+                        left_sibling.keys.append(&mut node.keys);
+                        left_sibling.values.append(&mut node.values);
+
+                        // Relink the left sibling.
+                        left_sibling.next_leaf = node.next_leaf;
+
+                        if let Some(next_leaf) = node.next_leaf {
+                            if let Node::Leaf(next_leaf) = &mut (*next_leaf.as_ptr()) {
+                                next_leaf.prev_leaf = Some(parent.children[cursor_index - 1]);
+                            }
+                        }
 
-                            // Remove the split key.
-                            self.remove_entry_internal(
-                                parent.keys[cursor_index - 1].clone().borrow(),
-                                node.parent.unwrap(),
-                                cursor,
-                            );
+                        // Remove the split key.
+                        self.remove_entry_internal(
+                            parent.keys[cursor_index - 1].clone().borrow(),
+                            node.parent.unwrap(),
+                            cursor,
+                        );
 
-                            return Some((key, value));
-                        }
+                        return (key, value);
                     }
+                }
 
-                    // Check if we can merge the right sibling.
-                    if cursor_index + 1 < parent.children.len() {
-                        if let Node::Leaf(right_sibling) =
-                            &mut (*parent.children[cursor_index + 1].as_ptr())
-                        {
-                            // Take/merge in the keys and values.
-                            node.keys.append(&mut right_sibling.keys);
-                            node.values.append(&mut right_sibling.values);
-
-                            // Relink the right sibling.
-                            node.next_leaf = right_sibling.next_leaf;
+                // Check if we can merge the right sibling.
+                if cursor_index + 1 < parent.children.len() {
+                    if let Node::Leaf(right_sibling) =
+                        &mut (*parent.children[cursor_index + 1].as_ptr())
+                    {
+                        // Take/merge in the keys and values.
+                        node.keys.append(&mut right_sibling.keys);
+                        node.values.append(&mut right_sibling.values);
+
+                        // Relink the right sibling.
+                        node.next_leaf = right_sibling.next_leaf;
+
+                        if let Some(next_leaf) = right_sibling.next_leaf {
+                            if let Node::Leaf(next_leaf) = &mut (*next_leaf.as_ptr()) {
+                                next_leaf.prev_leaf = Some(cursor);
+                            }
+                        }
 
-                            // Remove the split key from the parent.
-                            // The clone is to satisfy miri's stacked borrow check.
-                            self.remove_entry_internal(
-                                parent.keys[cursor_index].clone().borrow(),
-                                node.parent.unwrap(),
-                                parent.children[cursor_index + 1],
-                            );
+                        // Remove the split key from the parent.
+                        // The clone is to satisfy miri's stacked borrow check.
+                        self.remove_entry_internal(
+                            parent.keys[cursor_index].clone().borrow(),
+                            node.parent.unwrap(),
+                            parent.children[cursor_index + 1],
+                        );
 
-                            return Some((key, value));
-                        }
+                        return (key, value);
                     }
                 }
             }
-
-            None
         }
+
+        // Leaves other than the root always have a parent.
+        unreachable!("leaf without a parent")
     }
 
     fn remove_entry_internal<Q>(&mut self, key: &Q, cursor: Link<K, V>, child: Link<K, V>)
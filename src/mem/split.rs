@@ -0,0 +1,421 @@
+use super::{
+    iter::{first_leaf, last_leaf},
+    node::{Internal, Leaf, Link, Node},
+    BPTreeMap,
+};
+use std::{borrow::Borrow, cmp::Ordering, iter, mem, ptr::NonNull};
+
+impl<K, V> BPTreeMap<K, V> {
+    // Splits the map in two at `key`, returning everything from `key` on.
+    //
+    // The tree is cut along the path down to the leaf `key` belongs in. Every
+    // node on the path is split into the children left of the path and those
+    // right of it, and each side is then joined back together from the bottom
+    // up, which only has to fix up nodes along the cut.
+    pub fn split_off<Q>(&mut self, key: &Q) -> Self
+    where
+        K: Borrow<Q> + Ord + Clone,
+        Q: Ord,
+    {
+        let mut right = Self::with_order(self.order);
+        let mut cursor = match self.root.take() {
+            Some(root) => root,
+            None => return right,
+        };
+
+        unsafe {
+            // The pieces left and right of the path at each level.
+            let mut pieces = Vec::new();
+
+            while let Node::Internal(node) = &mut (*cursor.as_ptr()) {
+                let index = match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
+                    Ok(index) => index + 1,
+                    Err(index) => index,
+                };
+                let child = node.children[index];
+
+                // Drop the child on the path along with the split keys either
+                // side of it.
+                let right_children = node.children.split_off(index + 1);
+                let mut right_keys = node.keys.split_off(index);
+                node.children.pop();
+                node.keys.pop();
+                if !right_keys.is_empty() {
+                    right_keys.remove(0);
+                }
+
+                let right_piece = if right_children.is_empty() {
+                    None
+                } else {
+                    let piece =
+                        NonNull::new_unchecked(Box::into_raw(Box::new(Node::Internal(Internal {
+                            keys: right_keys,
+                            children: right_children.clone(),
+                            parent: None,
+                        }))));
+                    for child in right_children {
+                        set_parent(child, Some(piece));
+                    }
+                    Some(piece)
+                };
+
+                pieces.push((trim(cursor), right_piece.and_then(|piece| trim(piece))));
+                cursor = child;
+            }
+
+            if let Node::Leaf(node) = &mut (*cursor.as_ptr()) {
+                let index = match node.keys.binary_search_by(|probe| probe.borrow().cmp(key)) {
+                    Ok(index) | Err(index) => index,
+                };
+
+                let right_keys = node.keys.split_off(index);
+                let right_values = node.values.split_off(index);
+
+                if !right_keys.is_empty() {
+                    right.root = Some(NonNull::new_unchecked(Box::into_raw(Box::new(Node::Leaf(
+                        Leaf {
+                            keys: right_keys,
+                            values: right_values,
+                            parent: None,
+                            next_leaf: node.next_leaf,
+                            prev_leaf: None,
+                        },
+                    )))));
+                }
+
+                node.next_leaf = None;
+                if node.keys.is_empty() {
+                    let _ = Box::from_raw(cursor.as_ptr());
+                } else {
+                    self.root = Some(cursor);
+                }
+            }
+
+            for (left_piece, right_piece) in pieces.into_iter().rev() {
+                let left_root = self.root.take();
+                self.join(left_piece, left_root);
+
+                // The last leaf on the left may still point across the cut,
+                // and merging it into another leaf in a later join would
+                // follow that link.
+                if let Some(root) = self.root {
+                    if let Node::Leaf(node) = &mut (*last_leaf(root).as_ptr()) {
+                        node.next_leaf = None;
+                    }
+                }
+
+                let right_root = right.root.take();
+                right.join(right_root, right_piece);
+            }
+
+            // The first leaf on the right may still point back across the cut.
+            if let Some(root) = right.root {
+                let mut leaf = Some(first_leaf(root));
+                if let Some(Node::Leaf(node)) = leaf.map(|leaf| &mut *leaf.as_ptr()) {
+                    node.prev_leaf = None;
+                }
+
+                // Count what ended up on the right, a leaf at a time.
+                while let Some(Node::Leaf(node)) = leaf.map(|leaf| &*leaf.as_ptr()) {
+                    right.len += node.keys.len();
+                    leaf = node.next_leaf;
+                }
+            }
+        }
+
+        self.len -= right.len;
+        right
+    }
+
+    // Moves every entry of `other` into the map, leaving `other` empty. Entries
+    // in `other` replace those in the map with the same key.
+    //
+    // If one map's keys all come before the other's, the shorter tree is
+    // grafted onto the edge of the taller one. Otherwise the entries are merged
+    // in order and the tree is rebuilt from them.
+    pub fn append(&mut self, other: &mut Self)
+    where
+        K: Ord + Clone,
+    {
+        if other.is_empty() {
+            return;
+        }
+
+        if self.order == other.order {
+            if self.is_empty() {
+                mem::swap(self, other);
+                return;
+            }
+
+            let first = self.first_key_value().map(|(key, _)| key);
+            let last = self.last_key_value().map(|(key, _)| key);
+            let other_first = other.first_key_value().map(|(key, _)| key);
+            let other_last = other.last_key_value().map(|(key, _)| key);
+
+            let roots = if last < other_first {
+                Some((self.root, other.root))
+            } else if other_last < first {
+                Some((other.root, self.root))
+            } else {
+                None
+            };
+
+            if let Some((left, right)) = roots {
+                self.len += mem::take(&mut other.len);
+                other.root = None;
+                unsafe { self.join(left, right) };
+                return;
+            }
+        }
+
+        let mut entries = self.take_entries().into_iter().peekable();
+        let mut other_entries = other.take_entries().into_iter().peekable();
+
+        let merged = iter::from_fn(|| match (entries.peek(), other_entries.peek()) {
+            (Some((key, _)), Some((other_key, _))) => match key.cmp(other_key) {
+                Ordering::Less => entries.next(),
+                Ordering::Greater => other_entries.next(),
+                Ordering::Equal => {
+                    entries.next();
+                    other_entries.next()
+                }
+            },
+            (Some(_), None) => entries.next(),
+            (None, _) => other_entries.next(),
+        });
+
+        *self = Self::from_sorted_iter(self.order, merged).expect("merged entries are sorted");
+    }
+
+    // Empties the map, returning its entries in order.
+    pub(crate) fn take_entries(&mut self) -> Vec<(K, V)> {
+        let mut entries = Vec::with_capacity(self.len);
+
+        unsafe {
+            let mut leaf = self.root.map(|root| first_leaf(root));
+            while let Some(Node::Leaf(node)) = leaf.map(|leaf| &mut *leaf.as_ptr()) {
+                entries.extend(node.keys.drain(..).zip(node.values.drain(..)));
+                leaf = node.next_leaf;
+            }
+        }
+
+        *self = Self::with_order(self.order);
+        entries
+    }
+
+    // Joins two trees into one at the root, where every key under `left` is
+    // less than every key under `right`. Either root may be underfull, as
+    // roots are allowed to be, but nothing below them can be.
+    unsafe fn join(&mut self, left: Option<Link<K, V>>, right: Option<Link<K, V>>)
+    where
+        K: Ord + Clone,
+    {
+        let (left, right) = match (left, right) {
+            (Some(left), Some(right)) => (left, right),
+            (root, None) | (None, root) => {
+                if let Some(root) = root {
+                    set_parent(root, None);
+                }
+                self.root = root;
+                return;
+            }
+        };
+
+        set_parent(left, None);
+        set_parent(right, None);
+
+        // Link up the leaves either side of the seam.
+        let (last, first) = (last_leaf(left), first_leaf(right));
+        if let Node::Leaf(node) = &mut (*last.as_ptr()) {
+            node.next_leaf = Some(first);
+        }
+        if let Node::Leaf(node) = &mut (*first.as_ptr()) {
+            node.prev_leaf = Some(last);
+        }
+
+        let (left_height, right_height) = (height(left), height(right));
+        match left_height.cmp(&right_height) {
+            Ordering::Equal => {
+                self.root = Some(left);
+
+                // The two roots either merge into one or become the children
+                // of a new root.
+                let split_key = first_key(right).clone();
+                if let Some(split_key) = self.rebalance(left, right, split_key) {
+                    let new_root =
+                        NonNull::new_unchecked(Box::into_raw(Box::new(Node::Internal(Internal {
+                            keys: vec![split_key],
+                            children: vec![left, right],
+                            parent: None,
+                        }))));
+
+                    set_parent(left, Some(new_root));
+                    set_parent(right, Some(new_root));
+                    self.root = Some(new_root);
+                }
+            }
+            Ordering::Greater => {
+                self.root = Some(left);
+
+                // Graft the right tree onto the right edge of the left one,
+                // next to the last node at the same height.
+                let parent = descend(left, left_height - right_height - 1, true);
+                let sibling = match &(*parent.as_ptr()) {
+                    Node::Internal(parent) => parent.children[parent.children.len() - 1],
+                    Node::Leaf(_) => unreachable!("leaf above the grafted tree"),
+                };
+
+                let split_key = first_key(right).clone();
+                if let Some(split_key) = self.rebalance(sibling, right, split_key) {
+                    if let Node::Internal(parent) = &mut (*parent.as_ptr()) {
+                        parent.keys.push(split_key);
+                        parent.children.push(right);
+                    }
+                    set_parent(right, Some(parent));
+                    self.split_internal(parent);
+                }
+            }
+            Ordering::Less => {
+                self.root = Some(right);
+
+                // Graft the left tree onto the left edge of the right one,
+                // next to the first node at the same height.
+                let parent = descend(right, right_height - left_height - 1, false);
+                let sibling = match &(*parent.as_ptr()) {
+                    Node::Internal(parent) => parent.children[0],
+                    Node::Leaf(_) => unreachable!("leaf above the grafted tree"),
+                };
+
+                let split_key = first_key(sibling).clone();
+                let split_key = self.rebalance(left, sibling, split_key);
+                if let Node::Internal(parent) = &mut (*parent.as_ptr()) {
+                    match split_key {
+                        // The sibling was merged into the left tree's root.
+                        None => parent.children[0] = left,
+                        Some(split_key) => {
+                            parent.keys.insert(0, split_key);
+                            parent.children.insert(0, left);
+                        }
+                    }
+                }
+                set_parent(left, Some(parent));
+                self.split_internal(parent);
+            }
+        }
+    }
+
+    // Merges `right` into `left`, two neighbouring nodes of the same height
+    // split by `split_key`, if everything fits in one node. Otherwise evens
+    // them out and returns the new split key. Either way neither is left
+    // underfull unless both were.
+    pub(crate) unsafe fn rebalance(
+        &mut self,
+        left: Link<K, V>,
+        right: Link<K, V>,
+        split_key: K,
+    ) -> Option<K>
+    where
+        K: Clone,
+    {
+        match (&mut (*left.as_ptr()), &mut (*right.as_ptr())) {
+            (Node::Leaf(left_node), Node::Leaf(right_node)) => {
+                left_node.keys.append(&mut right_node.keys);
+                left_node.values.append(&mut right_node.values);
+
+                if !left_node.is_overfull(self.order) {
+                    // Relink around the merged leaf.
+                    left_node.next_leaf = right_node.next_leaf;
+                    if let Some(next_leaf) = right_node.next_leaf {
+                        if let Node::Leaf(next_leaf) = &mut (*next_leaf.as_ptr()) {
+                            next_leaf.prev_leaf = Some(left);
+                        }
+                    }
+
+                    let _ = Box::from_raw(right.as_ptr());
+                    return None;
+                }
+
+                let split_index = left_node.keys.len() / 2;
+                right_node.keys = left_node.keys.split_off(split_index);
+                right_node.values = left_node.values.split_off(split_index);
+
+                Some(right_node.keys[0].clone())
+            }
+            (Node::Internal(left_node), Node::Internal(right_node)) => {
+                // Left keys, split key, then right keys.
+                left_node.keys.push(split_key);
+                left_node.keys.append(&mut right_node.keys);
+                for child in &right_node.children {
+                    set_parent(*child, Some(left));
+                }
+                left_node.children.append(&mut right_node.children);
+
+                if !left_node.is_overfull(self.order) {
+                    let _ = Box::from_raw(right.as_ptr());
+                    return None;
+                }
+
+                // One key moves up as the new split key.
+                let split_index = (left_node.keys.len() - 1) / 2;
+                right_node.keys = left_node.keys.split_off(split_index + 1);
+                right_node.children = left_node.children.split_off(split_index + 1);
+                for child in &right_node.children {
+                    set_parent(*child, Some(right));
+                }
+
+                left_node.keys.pop()
+            }
+            _ => unreachable!("rebalancing nodes of different heights"),
+        }
+    }
+}
+
+// Frees a piece of a split internal node that's left with no children, and
+// replaces one that's left with a single child by that child.
+unsafe fn trim<K, V>(cursor: Link<K, V>) -> Option<Link<K, V>> {
+    let child = match &(*cursor.as_ptr()) {
+        Node::Internal(node) if node.children.len() < 2 => node.children.first().copied(),
+        _ => return Some(cursor),
+    };
+
+    let _ = Box::from_raw(cursor.as_ptr());
+    child
+}
+
+pub(crate) unsafe fn set_parent<K, V>(cursor: Link<K, V>, parent: Option<Link<K, V>>) {
+    match &mut (*cursor.as_ptr()) {
+        Node::Internal(node) => node.parent = parent,
+        Node::Leaf(node) => node.parent = parent,
+    }
+}
+
+// The number of internal nodes between `cursor` and its leaves.
+unsafe fn height<K, V>(mut cursor: Link<K, V>) -> usize {
+    let mut height = 0;
+    while let Node::Internal(node) = &(*cursor.as_ptr()) {
+        cursor = node.children[0];
+        height += 1;
+    }
+    height
+}
+
+// Descends `depth` levels down the left or right edge under `cursor`.
+unsafe fn descend<K, V>(mut cursor: Link<K, V>, depth: usize, last: bool) -> Link<K, V> {
+    for _ in 0..depth {
+        if let Node::Internal(node) = &(*cursor.as_ptr()) {
+            cursor = if last {
+                node.children[node.children.len() - 1]
+            } else {
+                node.children[0]
+            };
+        }
+    }
+    cursor
+}
+
+unsafe fn first_key<'a, K: 'a, V: 'a>(cursor: Link<K, V>) -> &'a K {
+    match &(*first_leaf(cursor).as_ptr()) {
+        Node::Leaf(node) => &node.keys[0],
+        Node::Internal(_) => unreachable!("internal node at the bottom of the tree"),
+    }
+}